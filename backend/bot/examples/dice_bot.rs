@@ -0,0 +1,61 @@
+// Answers `/echo <text>` and `/roll [NdM]` in one room.
+//
+//   WS_CHAT_URL=wss://127.0.0.1:8000 WS_CHAT_TOKEN=wsc_... \
+//   cargo run -p ws_chat_bot --example dice_bot -- <room_id>
+
+use rand::Rng;
+use ws_chat_bot::{Bot, Command};
+
+fn echo(command: &Command) -> Option<String> {
+    if command.raw_args.is_empty() {
+        return None;
+    }
+
+    Some(command.raw_args.clone())
+}
+
+fn roll(command: &Command) -> Option<String> {
+    let dice = command.args.first().map(String::as_str).unwrap_or("1d6");
+    let (count, sides) = dice.split_once('d')?;
+    let count: u32 = if count.is_empty() {
+        1
+    } else {
+        count.parse().ok()?
+    };
+    let sides: u32 = sides.parse().ok()?;
+
+    if !(1..=20).contains(&count) || !(2..=1000).contains(&sides) {
+        return Some("Roll between 1 and 20 dice with 2 to 1000 sides".into());
+    }
+
+    let mut rng = rand::rng();
+    let rolls: Vec<u32> = (0..count).map(|_| rng.random_range(1..=sides)).collect();
+    let total: u32 = rolls.iter().sum();
+    let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+
+    Some(format!(
+        "{} rolled {}: {} = {}",
+        command.sender,
+        dice,
+        rolls.join(" + "),
+        total
+    ))
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let url = std::env::var("WS_CHAT_URL").unwrap_or_else(|_| "wss://127.0.0.1:8000".into());
+    let token = std::env::var("WS_CHAT_TOKEN").expect("WS_CHAT_TOKEN is not set");
+    let room_id = std::env::args().nth(1).expect("usage: dice_bot <room_id>");
+
+    let bot = Bot::new(url, token)
+        .accept_invalid_certs(true)
+        .command("echo", echo)
+        .command("roll", roll);
+
+    if let Err(err) = bot.run(&room_id).await {
+        eprintln!("Bot stopped: {}", err);
+    }
+}
@@ -0,0 +1,62 @@
+// a slash-command posted to the room, e.g. `/roll 2d6`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<String>,
+    // everything after the command name, untouched
+    pub raw_args: String,
+    pub sender: String,
+}
+
+impl Command {
+    pub fn parse(message: &str, sender: &str) -> Option<Command> {
+        let body = message.trim().strip_prefix('/')?;
+        let (name, raw_args) = match body.split_once(char::is_whitespace) {
+            Some((name, raw_args)) => (name, raw_args.trim()),
+            None => (body, ""),
+        };
+
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Command {
+            name: name.to_lowercase(),
+            args: raw_args.split_whitespace().map(String::from).collect(),
+            raw_args: raw_args.to_string(),
+            sender: sender.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_and_args() {
+        let command = Command::parse("  /Roll 2d6   +3 ", "alice").unwrap();
+
+        assert_eq!(command.name, "roll");
+        assert_eq!(command.args, vec!["2d6", "+3"]);
+        assert_eq!(command.raw_args, "2d6   +3");
+        assert_eq!(command.sender, "alice");
+    }
+
+    #[test]
+    fn command_without_args() {
+        let command = Command::parse("/help", "alice").unwrap();
+
+        assert_eq!(command.name, "help");
+        assert!(command.args.is_empty());
+        assert_eq!(command.raw_args, "");
+    }
+
+    #[test]
+    fn plain_messages_are_not_commands() {
+        assert_eq!(Command::parse("hello /roll", "alice"), None);
+        assert_eq!(Command::parse("/", "alice"), None);
+        assert_eq!(Command::parse("/ roll", "alice"), None);
+        assert_eq!(Command::parse("", "alice"), None);
+    }
+}
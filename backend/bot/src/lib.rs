@@ -0,0 +1,232 @@
+//! A small client for writing WS Chat Room bots.
+//!
+//! A [`Bot`] joins a room over `/api/join_room` with its API token, reads the
+//! `StreamCommand` stream and answers slash-commands with registered handlers.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{
+    Connector, connect_async_tls_with_config,
+    tungstenite::{self, Message, client::IntoClientRequest, http::HeaderValue},
+};
+
+mod command;
+pub use command::Command;
+pub mod stream;
+use stream::{StreamCommand, StreamMethod};
+
+type Handler = Arc<dyn Fn(&Command) -> Option<String> + Send + Sync>;
+
+pub struct Bot {
+    url: String,
+    token: String,
+    accept_invalid_certs: bool,
+    handlers: HashMap<String, Handler>,
+}
+
+impl Bot {
+    // `url` is the server origin, e.g. `wss://127.0.0.1:8000`
+    pub fn new(url: impl Into<String>, token: impl Into<String>) -> Self {
+        Bot {
+            url: url.into(),
+            token: token.into(),
+            accept_invalid_certs: false,
+            handlers: HashMap::new(),
+        }
+    }
+
+    // for local servers running on the self-signed certificate
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+
+        self
+    }
+
+    // answer `/name ...`, the returned text is posted back to the room
+    pub fn command<F>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(&Command) -> Option<String> + Send + Sync + 'static,
+    {
+        self.handlers.insert(name.to_lowercase(), Arc::new(handler));
+
+        self
+    }
+
+    // the reply for a message, if it is a command this bot knows
+    pub fn dispatch(&self, stream_command: &StreamCommand) -> Option<String> {
+        if stream_command.method != StreamMethod::Send || stream_command.is_self {
+            return None;
+        }
+
+        let command = Command::parse(&stream_command.message, &stream_command.sender)?;
+        let handler = self.handlers.get(&command.name)?;
+
+        handler(&command)
+    }
+
+    // follow a room until the server closes the connection
+    pub async fn run(&self, room_id: &str) -> Result<(), Error> {
+        let url = format!(
+            "{}/api/join_room?room_id={}",
+            self.url.trim_end_matches('/'),
+            room_id
+        );
+        let mut request = url.into_client_request()?;
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", self.token))
+            .map_err(|_err| Error::InvalidToken)?;
+        request.headers_mut().insert("Authorization", authorization);
+
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .build()?;
+
+        let (stream, _response) = connect_async_tls_with_config(
+            request,
+            None,
+            false,
+            Some(Connector::NativeTls(connector)),
+        )
+        .await?;
+        let (mut sender, mut receiver) = stream.split();
+
+        tracing::info!("Joined room {}", room_id);
+
+        while let Some(message) = receiver.next().await {
+            let stream_command = match message? {
+                Message::Text(text) => match serde_json::from_str::<StreamCommand>(&text) {
+                    Ok(stream_command) => stream_command,
+                    Err(err) => {
+                        tracing::warn!("Skipping unknown frame: {}", err);
+
+                        continue;
+                    }
+                },
+                Message::Close(frame) => {
+                    tracing::info!("Room {} closed: {:?}", room_id, frame);
+
+                    break;
+                }
+                _ => continue,
+            };
+
+            if stream_command.method == StreamMethod::Error {
+                tracing::warn!("Server error: {}", stream_command.message);
+            }
+
+            if let Some(reply) = self.dispatch(&stream_command) {
+                let frame = serde_json::to_string(&StreamCommand::send(reply))?;
+
+                sender.send(Message::text(frame)).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    WebSocket(tungstenite::Error),
+    Tls(native_tls::Error),
+    Json(serde_json::Error),
+    InvalidToken,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WebSocket(err) => write!(f, "websocket error: {}", err),
+            Error::Tls(err) => write!(f, "tls error: {}", err),
+            Error::Json(err) => write!(f, "invalid frame: {}", err),
+            Error::InvalidToken => write!(f, "token is not a valid header value"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        Error::WebSocket(err)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Self {
+        Error::Tls(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_bot() -> Bot {
+        Bot::new("wss://127.0.0.1:8000", "wsc_token")
+            .command("echo", |command| Some(command.raw_args.clone()))
+            .command("quiet", |_command| None)
+    }
+
+    fn message(method: StreamMethod, message: &str, is_self: bool) -> StreamCommand {
+        let mut stream_command = StreamCommand::send(message.to_string());
+        stream_command.method = method;
+        stream_command.sender = "alice".to_string();
+        stream_command.is_self = is_self;
+
+        stream_command
+    }
+
+    #[test]
+    fn dispatches_to_the_registered_handler() {
+        let bot = echo_bot();
+
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Send, "/ECHO hi there", false)),
+            Some("hi there".to_string())
+        );
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Send, "/quiet", false)),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_commands_and_plain_text() {
+        let bot = echo_bot();
+
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Send, "/roll 2d6", false)),
+            None
+        );
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Send, "echo hi", false)),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_own_messages_and_other_frames() {
+        let bot = echo_bot();
+
+        // answering itself would loop forever
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Send, "/echo hi", true)),
+            None
+        );
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Join, "/echo hi", false)),
+            None
+        );
+        assert_eq!(
+            bot.dispatch(&message(StreamMethod::Error, "/echo hi", false)),
+            None
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+// the server's StreamCommand frame, spoken when no subprotocol is negotiated
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamCommand {
+    pub method: StreamMethod,
+    pub message: String,
+    #[serde(default)]
+    pub sender: String,
+    #[serde(default)]
+    pub is_self: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<String>,
+}
+
+impl StreamCommand {
+    pub fn send(message: String) -> Self {
+        StreamCommand {
+            method: StreamMethod::Send,
+            message,
+            sender: String::new(),
+            is_self: false,
+            request_id: None,
+            message_id: None,
+            sent_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StreamMethod {
+    Send,
+    Join,
+    Leave,
+    Ack,
+    Error,
+}
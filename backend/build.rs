@@ -0,0 +1,4 @@
+// rebuild when a migration is added or changed, they are embedded with sqlx::migrate!
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
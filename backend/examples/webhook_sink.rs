@@ -0,0 +1,87 @@
+// local stand-in for a webhook receiver, verifies signatures and prints deliveries
+//
+//   WEBHOOK_SECRET=whsec_... cargo run --example webhook_sink -- 9000 2
+//
+// the optional second argument fails that many requests with 500 to exercise retries
+use axum::{Router, body::Bytes, extract::State, http::HeaderMap, http::StatusCode, routing::post};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+struct Sink {
+    secret: String,
+    fail_first: AtomicU32,
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let port: u16 = args
+        .next()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(9000);
+    let fail_first: u32 = args.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    let secret = std::env::var("WEBHOOK_SECRET").expect("WEBHOOK_SECRET is not set");
+
+    let sink = Arc::new(Sink {
+        secret,
+        fail_first: AtomicU32::new(fail_first),
+    });
+    let app = Router::new().route("/", post(receive)).with_state(sink);
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+    println!("Listening on http://{}/", addr);
+
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn receive(State(sink): State<Arc<Sink>>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    // compare in constant time
+    let mut mac = Hmac::<Sha256>::new_from_slice(sink.secret.as_bytes()).unwrap();
+    mac.update(&body);
+    let verified = header("X-WsChat-Signature")
+        .strip_prefix("sha256=")
+        .and_then(|signature| hex::decode(signature).ok())
+        .is_some_and(|signature| mac.verify_slice(&signature).is_ok());
+
+    if !verified {
+        println!("rejected {}: bad signature", header("X-WsChat-Delivery"));
+
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if sink
+        .fail_first
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok()
+    {
+        println!("failing {} on purpose", header("X-WsChat-Delivery"));
+
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    println!(
+        "{} {}: {}",
+        header("X-WsChat-Event"),
+        header("X-WsChat-Delivery"),
+        String::from_utf8_lossy(&body)
+    );
+
+    StatusCode::NO_CONTENT
+}
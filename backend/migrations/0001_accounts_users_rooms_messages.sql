@@ -0,0 +1,34 @@
+-- core chat schema, "if not exists" lets an existing hand-made schema be adopted
+create table if not exists accounts (
+    id serial primary key,
+    account text not null unique,
+    password text not null,
+    created_at timestamptz not null default now()
+);
+
+create table if not exists users (
+    id serial primary key,
+    account_id integer not null unique references accounts(id) on delete cascade,
+    username text not null
+);
+
+create table if not exists rooms (
+    id uuid primary key,
+    room_name text not null,
+    created_at timestamptz not null default now(),
+    closed_at timestamptz
+);
+
+-- GET /api/rooms lists the open ones
+create index if not exists rooms_open_idx on rooms(created_at) where closed_at is null;
+
+create table if not exists messages (
+    id bigserial primary key,
+    room_id uuid not null references rooms(id) on delete cascade,
+    user_id integer references users(id) on delete set null,
+    content text not null,
+    sent_at timestamptz not null default now()
+);
+
+create index if not exists messages_room_id_idx on messages(room_id, id);
+create index if not exists messages_user_id_idx on messages(user_id);
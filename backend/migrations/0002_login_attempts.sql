@@ -0,0 +1,10 @@
+create table if not exists login_attempts (
+    id bigserial primary key,
+    account text not null,
+    ip inet not null,
+    outcome text not null,
+    attempted_at timestamptz not null default now()
+);
+
+create index if not exists login_attempts_account_idx on login_attempts(account, attempted_at);
+create index if not exists login_attempts_ip_idx on login_attempts(ip, attempted_at);
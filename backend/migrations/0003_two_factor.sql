@@ -0,0 +1,16 @@
+-- pending until the first code is verified
+create table if not exists account_totp (
+    account_id integer primary key references accounts(id) on delete cascade,
+    secret text not null,
+    enabled boolean not null default false,
+    created_at timestamptz not null default now()
+);
+
+create table if not exists totp_recovery_codes (
+    id bigserial primary key,
+    account_id integer not null references accounts(id) on delete cascade,
+    code_hash text not null,
+    used_at timestamptz
+);
+
+create index if not exists totp_recovery_codes_account_id_idx on totp_recovery_codes(account_id, code_hash);
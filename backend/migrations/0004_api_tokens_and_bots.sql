@@ -0,0 +1,17 @@
+alter table users add column if not exists is_bot boolean not null default false;
+alter table users add column if not exists owner_id integer references users(id) on delete cascade;
+
+create index if not exists users_owner_id_idx on users(owner_id) where is_bot;
+
+create table if not exists api_tokens (
+    id bigserial primary key,
+    user_id integer not null references users(id) on delete cascade,
+    name text not null,
+    token_hash text not null unique,
+    scope text not null check (scope in ('read', 'send', 'admin')),
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
+
+create index if not exists api_tokens_user_id_idx on api_tokens(user_id);
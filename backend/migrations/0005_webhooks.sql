@@ -0,0 +1,39 @@
+create table if not exists room_webhooks (
+    id bigserial primary key,
+    room_id uuid not null references rooms(id) on delete cascade,
+    url text not null,
+    secret text not null,
+    events text[] not null,
+    created_at timestamptz not null default now(),
+    deleted_at timestamptz
+);
+
+create index if not exists room_webhooks_room_id_idx on room_webhooks(room_id) where deleted_at is null;
+
+-- one row per attempt, retries share the delivery id
+create table if not exists webhook_deliveries (
+    id bigserial primary key,
+    webhook_id bigint not null references room_webhooks(id) on delete cascade,
+    delivery_id uuid not null,
+    event text not null,
+    attempt integer not null,
+    status_code integer,
+    error text,
+    succeeded boolean not null,
+    attempted_at timestamptz not null default now()
+);
+
+create index if not exists webhook_deliveries_webhook_id_idx on webhook_deliveries(webhook_id, id);
+
+create table if not exists incoming_webhooks (
+    id bigserial primary key,
+    room_id uuid not null references rooms(id) on delete cascade,
+    user_id integer not null references users(id) on delete cascade,
+    name text not null,
+    token_hash text not null unique,
+    created_at timestamptz not null default now(),
+    last_used_at timestamptz,
+    deleted_at timestamptz
+);
+
+create index if not exists incoming_webhooks_room_id_idx on incoming_webhooks(room_id) where deleted_at is null;
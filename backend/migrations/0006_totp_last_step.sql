@@ -0,0 +1,2 @@
+-- the newest time step a code was accepted for, older and equal steps are replays
+alter table account_totp add column if not exists last_step bigint;
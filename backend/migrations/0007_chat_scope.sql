@@ -0,0 +1,8 @@
+-- chat tokens read and post but can't manage anything, bot tokens get this scope
+alter table api_tokens drop constraint if exists api_tokens_scope_check;
+alter table api_tokens add constraint api_tokens_scope_check
+    check (scope in ('read', 'send', 'chat', 'admin'));
+
+-- bots were issued admin tokens before
+update api_tokens set scope = 'chat'
+where scope = 'admin' and user_id in (select id from users where is_bot);
@@ -0,0 +1,205 @@
+-- 0001 adopts tables that already existed, which may lack the keys and indexes it would have
+-- created. Add whatever is missing, looked up by column not by name, and stop with a message
+-- naming the table when the existing data or column types can't take them. The server also
+-- runs this before the other migrations when adopting, so they can reference these tables;
+-- tables that don't exist yet are left to 0001.
+
+create function pg_temp.require_column(tbl text, col text, udt text) returns void as $$
+declare
+    found text;
+begin
+    select udt_name into found from information_schema.columns
+    where table_schema = current_schema() and table_name = tbl and column_name = col;
+
+    if to_regclass(tbl) is null then
+        return;
+    elsif found is null then
+        raise exception 'Existing table % has no % column, add it before migrating', tbl, col;
+    elsif found <> udt then
+        raise exception 'Existing column %.% is %, expected %', tbl, col, found, udt;
+    end if;
+end
+$$ language plpgsql;
+
+-- a primary key or plain unique index on exactly these columns
+create function pg_temp.has_unique(tbl regclass, cols text[], is_primary boolean) returns boolean as $$
+    select exists (
+        select 1 from pg_index i
+        where i.indrelid = tbl
+          and i.indisunique
+          and i.indpred is null
+          and (i.indisprimary or not is_primary)
+          and (
+              select array_agg(a.attname::text order by a.attname)
+              from unnest(i.indkey) k join pg_attribute a on a.attrelid = tbl and a.attnum = k
+          ) = (select array_agg(c order by c) from unnest(cols) c)
+    )
+$$ language sql;
+
+create function pg_temp.has_foreign_key(tbl regclass, col text, target regclass) returns boolean as $$
+    select exists (
+        select 1 from pg_constraint c
+        join pg_attribute a on a.attrelid = tbl and a.attnum = c.conkey[1]
+        where c.conrelid = tbl
+          and c.contype = 'f'
+          and c.confrelid = target
+          and cardinality(c.conkey) = 1
+          and a.attname = col
+    )
+$$ language sql;
+
+-- an index whose leading columns are these
+create function pg_temp.has_index(tbl regclass, cols text[]) returns boolean as $$
+    select exists (
+        select 1 from pg_index i
+        where i.indrelid = tbl
+          and (
+              select array_agg(a.attname::text order by k.n)
+              from unnest(i.indkey) with ordinality k(attnum, n)
+              join pg_attribute a on a.attrelid = tbl and a.attnum = k.attnum
+              where k.n <= cardinality(cols)
+          ) = cols
+    )
+$$ language sql;
+
+create function pg_temp.has_table(tbl text) returns boolean as $$
+    select to_regclass(tbl) is not null
+$$ language sql;
+
+-- 0001's index name, unless an index that doesn't fit already took it
+create function pg_temp.add_index(what text, name text, definition text) returns void as $$
+begin
+    if to_regclass(name) is not null then
+        name := name || '1';
+    end if;
+
+    perform pg_temp.apply(what, format('create index %I on %s', name, definition));
+end
+$$ language plpgsql;
+
+-- runs one statement, a failure names what was being added
+create function pg_temp.apply(what text, statement text) returns void as $$
+begin
+    execute statement;
+exception when others then
+    raise exception 'Could not add % to the existing schema: %', what, sqlerrm;
+end
+$$ language plpgsql;
+
+do $$
+begin
+    perform pg_temp.require_column('accounts', 'id', 'int4');
+    perform pg_temp.require_column('accounts', 'account', 'text');
+    perform pg_temp.require_column('accounts', 'password', 'text');
+    perform pg_temp.require_column('accounts', 'created_at', 'timestamptz');
+    perform pg_temp.require_column('users', 'id', 'int4');
+    perform pg_temp.require_column('users', 'account_id', 'int4');
+    perform pg_temp.require_column('users', 'username', 'text');
+    perform pg_temp.require_column('rooms', 'id', 'uuid');
+    perform pg_temp.require_column('rooms', 'room_name', 'text');
+    perform pg_temp.require_column('rooms', 'created_at', 'timestamptz');
+    perform pg_temp.require_column('rooms', 'closed_at', 'timestamptz');
+    perform pg_temp.require_column('messages', 'id', 'int8');
+    perform pg_temp.require_column('messages', 'room_id', 'uuid');
+    perform pg_temp.require_column('messages', 'user_id', 'int4');
+    perform pg_temp.require_column('messages', 'content', 'text');
+    perform pg_temp.require_column('messages', 'sent_at', 'timestamptz');
+
+    -- ids are generated by the database
+    if exists (
+        select 1 from information_schema.columns
+        where table_schema = current_schema()
+          and table_name in ('accounts', 'users', 'messages')
+          and column_name = 'id'
+          and column_default is null
+          and is_identity = 'NO'
+    ) then
+        raise exception 'Existing accounts, users and messages tables need generated ids (serial or identity)';
+    end if;
+
+    if pg_temp.has_table('accounts') then
+        if not pg_temp.has_unique('accounts', '{id}', true) then
+            perform pg_temp.apply('the accounts primary key',
+                'alter table accounts add primary key (id)');
+        end if;
+        if not pg_temp.has_unique('accounts', '{account}', false) then
+            perform pg_temp.apply('unique accounts',
+                'alter table accounts add constraint accounts_account_key unique (account)');
+        end if;
+        perform pg_temp.apply('not null accounts columns',
+            'alter table accounts alter column account set not null, alter column password set not null');
+    end if;
+
+    if pg_temp.has_table('users') then
+        if not pg_temp.has_unique('users', '{id}', true) then
+            perform pg_temp.apply('the users primary key', 'alter table users add primary key (id)');
+        end if;
+        if not pg_temp.has_unique('users', '{account_id}', false) then
+            perform pg_temp.apply('one user per account',
+                'alter table users add constraint users_account_id_key unique (account_id)');
+        end if;
+        perform pg_temp.apply('not null users columns',
+            'alter table users alter column account_id set not null, alter column username set not null');
+    end if;
+
+    if pg_temp.has_table('rooms') then
+        if not pg_temp.has_unique('rooms', '{id}', true) then
+            perform pg_temp.apply('the rooms primary key', 'alter table rooms add primary key (id)');
+        end if;
+        perform pg_temp.apply('not null rooms columns',
+            'alter table rooms alter column room_name set not null');
+        -- 0001 skipped its indexes when one of the same name already existed
+        if not pg_temp.has_index('rooms', '{created_at}') then
+            perform pg_temp.add_index('the open rooms index', 'rooms_open_idx',
+                'rooms(created_at) where closed_at is null');
+        end if;
+    end if;
+
+    if pg_temp.has_table('messages') then
+        if not pg_temp.has_unique('messages', '{id}', true) then
+            perform pg_temp.apply('the messages primary key',
+                'alter table messages add primary key (id)');
+        end if;
+        perform pg_temp.apply('not null messages columns',
+            'alter table messages alter column room_id set not null, alter column content set not null');
+        if not pg_temp.has_index('messages', '{room_id,id}') then
+            perform pg_temp.add_index('the messages room index', 'messages_room_id_idx',
+                'messages(room_id, id)');
+        end if;
+        if not pg_temp.has_index('messages', '{user_id}') then
+            perform pg_temp.add_index('the messages user index', 'messages_user_id_idx',
+                'messages(user_id)');
+        end if;
+    end if;
+
+    if pg_temp.has_table('users') and pg_temp.has_table('accounts') then
+        if not pg_temp.has_foreign_key('users', 'account_id', 'accounts') then
+            perform pg_temp.apply('the users account reference',
+                'alter table users add constraint users_account_id_fkey
+                    foreign key (account_id) references accounts(id) on delete cascade');
+        end if;
+    end if;
+    if pg_temp.has_table('messages') and pg_temp.has_table('rooms') then
+        if not pg_temp.has_foreign_key('messages', 'room_id', 'rooms') then
+            perform pg_temp.apply('the messages room reference',
+                'alter table messages add constraint messages_room_id_fkey
+                    foreign key (room_id) references rooms(id) on delete cascade');
+        end if;
+    end if;
+    if pg_temp.has_table('messages') and pg_temp.has_table('users') then
+        if not pg_temp.has_foreign_key('messages', 'user_id', 'users') then
+            perform pg_temp.apply('the messages user reference',
+                'alter table messages add constraint messages_user_id_fkey
+                    foreign key (user_id) references users(id) on delete set null');
+        end if;
+    end if;
+end
+$$;
+
+drop function pg_temp.require_column(text, text, text);
+drop function pg_temp.has_unique(regclass, text[], boolean);
+drop function pg_temp.has_foreign_key(regclass, text, regclass);
+drop function pg_temp.has_index(regclass, text[]);
+drop function pg_temp.has_table(text);
+drop function pg_temp.add_index(text, text, text);
+drop function pg_temp.apply(text, text);
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const TOKEN_PREFIX: &str = "wsc_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // list rooms, read limits and follow rooms
+    Read,
+    // post messages
+    Send,
+    // read and send, what bots are issued
+    Chat,
+    // everything a logged in user can do
+    Admin,
+}
+
+impl Scope {
+    pub fn allows(&self, needed: Scope) -> bool {
+        match self {
+            Scope::Admin => true,
+            Scope::Chat => matches!(needed, Scope::Read | Scope::Send | Scope::Chat),
+            _ => *self == needed,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Send => "send",
+            Scope::Chat => "chat",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "read" => Some(Scope::Read),
+            "send" => Some(Scope::Send),
+            "chat" => Some(Scope::Chat),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scope: Scope,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// the token holder, resolved from an Authorization header
+pub struct TokenOwner {
+    pub token_id: i64,
+    pub user_id: i32,
+    pub username: String,
+    pub is_bot: bool,
+    pub scope: Scope,
+}
+
+// tokens are shown once and only their hashes are stored
+pub fn generate_token() -> String {
+    format!(
+        "{}{}{}",
+        TOKEN_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+pub fn hash_token(token: &str) -> String {
+    let digest = Sha256::digest(token.trim().as_bytes());
+
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn has_prefix(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
@@ -0,0 +1,7 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Bot {
+    pub id: i32,
+    pub username: String,
+}
@@ -0,0 +1,745 @@
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::SocketAddr, path::PathBuf, time::Duration};
+use toml::{Table, Value};
+
+use crate::rate_limit::RoomLimits;
+
+const DEFAULT_CONFIG_FILE: &str = "ws_chat.toml";
+// WS_CHAT_DATABASE__MAX_CONNECTIONS sets database.max_connections
+const ENV_PREFIX: &str = "WS_CHAT_";
+const ENV_SEPARATOR: &str = "__";
+// the variables db.rs used to read, kept so existing .env files still work; bare HOST
+// and USER are left out, shells set them to the machine and login name
+const LEGACY_ENV: [(&str, &str); 2] = [("PASSWORD", "password"), ("DBNAME", "name")];
+
+#[derive(Debug, Parser)]
+#[command(version, about = "WS Chat Room server")]
+pub struct Cli {
+    /// TOML config file, ws_chat.toml is read when present
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// address to listen on, e.g. 0.0.0.0:8443
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+    /// PEM certificate chain
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+    /// override any setting, e.g. --set room.history_size=256
+    #[arg(short = 's', long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// apply pending database migrations and exit
+    Migrate,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub session: SessionConfig,
+    pub room: RoomConfig,
+    pub messages: MessagesConfig,
+    pub login: LoginConfig,
+    pub webhook: WebhookConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    // plain HTTP listener for /metrics, its labels name rooms so keep it off the public network
+    pub metrics_bind: SocketAddr,
+    // how long HTTP senders wait for their message to be stored
+    pub ack_timeout_secs: u64,
+    // how long to wait for rooms and the message writer on SIGINT/SIGTERM
+    pub shutdown_timeout_secs: u64,
+    // how long /readyz fails before the listener closes, so load balancers stop routing first
+    pub drain_delay_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // memory keeps everything in the process, for tests and trying the server out
+    pub backend: StorageBackend,
+    pub host: String,
+    pub user: String,
+    pub password: String,
+    pub name: String,
+    // the one pool shared by handlers, rooms, webhooks and the message writer
+    pub max_connections: u32,
+    pub acquire_timeout_secs: u64,
+    // apply pending migrations at startup instead of refusing to start
+    pub auto_migrate: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Postgres,
+    Memory,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub idle_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomConfig {
+    pub idle_timeout_secs: u64,
+    pub channel_capacity: usize,
+    pub broadcast_capacity: usize,
+    pub history_size: usize,
+    // limits new rooms start with
+    pub limits: RoomLimits,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessagesConfig {
+    // messages waiting for the database writer
+    pub queue_capacity: usize,
+    // most messages stored by one insert
+    pub batch_size: usize,
+    // attempts for a batch that hit a transient database error
+    pub max_attempts: u32,
+    // delay before the first retry, doubled on every further one
+    pub retry_delay_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoginConfig {
+    pub account_free_attempts: u32,
+    pub ip_free_attempts: u32,
+    // accounts and ips with failed attempts kept in memory, each
+    pub max_tracked: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub queue_capacity: usize,
+    pub max_attempts: u32,
+    pub base_delay_secs: u64,
+    pub timeout_secs: u64,
+    // deliver to loopback, private and link-local addresses, for hooks on the same network
+    pub allow_private_targets: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: SocketAddr::from(([127, 0, 0, 1], 8000)),
+            metrics_bind: SocketAddr::from(([127, 0, 0, 1], 9090)),
+            ack_timeout_secs: 10,
+            shutdown_timeout_secs: 30,
+            drain_delay_secs: 0,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        let cert_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("self_signed_cert");
+
+        TlsConfig {
+            cert: cert_dir.join("cert.pem"),
+            key: cert_dir.join("key.pem"),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            backend: StorageBackend::Postgres,
+            host: String::new(),
+            user: String::new(),
+            password: String::new(),
+            name: String::new(),
+            max_connections: 10,
+            acquire_timeout_secs: 3,
+            auto_migrate: false,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            idle_timeout_secs: 30 * 60,
+        }
+    }
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        RoomConfig {
+            idle_timeout_secs: 30 * 60,
+            channel_capacity: 128,
+            broadcast_capacity: 128,
+            history_size: 128,
+            limits: RoomLimits::default(),
+        }
+    }
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        MessagesConfig {
+            queue_capacity: 128,
+            batch_size: 64,
+            max_attempts: 5,
+            retry_delay_ms: 100,
+        }
+    }
+}
+
+impl MessagesConfig {
+    pub fn retry_delay(&self) -> Duration {
+        Duration::from_millis(self.retry_delay_ms)
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            account_free_attempts: 3,
+            ip_free_attempts: 20,
+            max_tracked: 100_000,
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            queue_capacity: 1024,
+            max_attempts: 5,
+            base_delay_secs: 2,
+            timeout_secs: 10,
+            allow_private_targets: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn ack_timeout(&self) -> Duration {
+        Duration::from_secs(self.ack_timeout_secs)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+
+    pub fn drain_delay(&self) -> Duration {
+        Duration::from_secs(self.drain_delay_secs)
+    }
+}
+
+impl DatabaseConfig {
+    pub fn acquire_timeout(&self) -> Duration {
+        Duration::from_secs(self.acquire_timeout_secs)
+    }
+}
+
+impl SessionConfig {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+impl RoomConfig {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+impl WebhookConfig {
+    // doubled after every failed attempt
+    pub fn base_delay(&self) -> Duration {
+        Duration::from_secs(self.base_delay_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(String),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            ConfigError::Parse(message) => write!(f, "{}", message),
+            ConfigError::Invalid(problems) => {
+                writeln!(f, "invalid configuration:")?;
+
+                for problem in problems {
+                    writeln!(f, "  - {}", problem)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // defaults < legacy PASSWORD/DBNAME < TOML file < WS_CHAT_* env < CLI flags
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let legacy = LEGACY_ENV
+            .iter()
+            .filter_map(|(name, key)| Some((key.to_string(), dotenv::var(name).ok()?)));
+
+        Config::resolve(cli, legacy, std::env::vars())
+    }
+
+    // `load` over the given variables instead of the process environment
+    fn resolve(
+        cli: &Cli,
+        legacy: impl IntoIterator<Item = (String, String)>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, ConfigError> {
+        // the defaults tell which settings are strings, so PASSWORD=1234 stays one
+        let template = Value::try_from(Config::default()).unwrap();
+        let mut table = Table::new();
+
+        for (key, raw) in legacy {
+            set(&mut table, &template, &["database".into(), key], &raw);
+        }
+
+        let (path, required) = match &cli.config {
+            Some(path) => (path.clone(), true),
+            None => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+
+        if required || path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|err| ConfigError::Read(path.clone(), err))?;
+            let file: Table = content.parse().map_err(|err| {
+                ConfigError::Parse(format!("cannot parse {}: {}", path.display(), err))
+            })?;
+
+            merge(&mut table, file);
+        }
+
+        for (name, raw) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+
+            // single segment names like WS_CHAT_TOKEN belong to clients
+            if !key.contains(ENV_SEPARATOR) {
+                continue;
+            }
+
+            let path: Vec<String> = key
+                .split(ENV_SEPARATOR)
+                .map(|segment| segment.to_lowercase())
+                .collect();
+            set(&mut table, &template, &path, &raw);
+        }
+
+        let mut overrides = cli.overrides.clone();
+
+        if let Some(bind) = cli.bind {
+            overrides.push(format!("server.bind={}", bind));
+        }
+        if let Some(cert) = &cli.tls_cert {
+            overrides.push(format!("tls.cert={}", cert.display()));
+        }
+        if let Some(key) = &cli.tls_key {
+            overrides.push(format!("tls.key={}", key.display()));
+        }
+
+        for entry in overrides {
+            let Some((key, raw)) = entry.split_once('=') else {
+                return Err(ConfigError::Parse(format!(
+                    "--set {} is not KEY=VALUE",
+                    entry
+                )));
+            };
+
+            let path: Vec<String> = key.trim().split('.').map(str::to_string).collect();
+            set(&mut table, &template, &path, raw.trim());
+        }
+
+        // going through text makes errors point at the offending key
+        let merged = toml::to_string(&table).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let config: Config = toml::from_str(&merged)
+            .map_err(|err| ConfigError::Parse(format!("invalid configuration: {}", err)))?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let mut require = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        if self.database.backend == StorageBackend::Postgres {
+            require(!self.database.host.is_empty(), "database.host is not set");
+            require(!self.database.user.is_empty(), "database.user is not set");
+            require(!self.database.name.is_empty(), "database.name is not set");
+        }
+        require(
+            self.database.max_connections > 0,
+            "database.max_connections must be at least 1",
+        );
+        require(
+            self.database.acquire_timeout_secs > 0,
+            "database.acquire_timeout_secs must be at least 1",
+        );
+        require(
+            self.tls.cert.is_file(),
+            &format!("tls.cert {} does not exist", self.tls.cert.display()),
+        );
+        require(
+            self.tls.key.is_file(),
+            &format!("tls.key {} does not exist", self.tls.key.display()),
+        );
+        require(
+            self.server.metrics_bind != self.server.bind,
+            "server.metrics_bind must differ from server.bind",
+        );
+        require(
+            self.server.ack_timeout_secs > 0,
+            "server.ack_timeout_secs must be at least 1",
+        );
+        require(
+            self.server.shutdown_timeout_secs > 0,
+            "server.shutdown_timeout_secs must be at least 1",
+        );
+        require(
+            self.session.idle_timeout_secs > 0,
+            "session.idle_timeout_secs must be at least 1",
+        );
+        require(
+            self.room.idle_timeout_secs > 0,
+            "room.idle_timeout_secs must be at least 1",
+        );
+        require(
+            self.room.channel_capacity > 0,
+            "room.channel_capacity must be at least 1",
+        );
+        require(
+            self.room.broadcast_capacity > 0,
+            "room.broadcast_capacity must be at least 1",
+        );
+        require(
+            self.room.history_size > 0,
+            "room.history_size must be at least 1",
+        );
+        require(
+            self.room.limits.connection_burst > 0,
+            "room.limits.connection_burst must be at least 1",
+        );
+        require(
+            self.room.limits.connection_per_minute > 0,
+            "room.limits.connection_per_minute must be at least 1",
+        );
+        require(
+            self.room.limits.user_burst > 0,
+            "room.limits.user_burst must be at least 1",
+        );
+        require(
+            self.room.limits.user_per_minute > 0,
+            "room.limits.user_per_minute must be at least 1",
+        );
+        require(
+            self.room.limits.room_burst > 0,
+            "room.limits.room_burst must be at least 1",
+        );
+        require(
+            self.room.limits.room_per_minute > 0,
+            "room.limits.room_per_minute must be at least 1",
+        );
+        require(
+            self.room.limits.mute_after > 0,
+            "room.limits.mute_after must be at least 1",
+        );
+        require(
+            self.messages.queue_capacity > 0,
+            "messages.queue_capacity must be at least 1",
+        );
+        require(
+            self.messages.batch_size > 0,
+            "messages.batch_size must be at least 1",
+        );
+        require(
+            self.messages.max_attempts > 0,
+            "messages.max_attempts must be at least 1",
+        );
+        require(
+            self.login.max_tracked > 0,
+            "login.max_tracked must be at least 1",
+        );
+        require(
+            self.webhook.queue_capacity > 0,
+            "webhook.queue_capacity must be at least 1",
+        );
+        require(
+            self.webhook.max_attempts > 0,
+            "webhook.max_attempts must be at least 1",
+        );
+        require(
+            self.webhook.timeout_secs > 0,
+            "webhook.timeout_secs must be at least 1",
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn set(table: &mut Table, template: &Value, path: &[String], raw: &str) {
+    let Some((last, parents)) = path.split_last() else {
+        return;
+    };
+
+    let expected = path
+        .iter()
+        .try_fold(template, |value, key| value.get(key.as_str()));
+    let value = match expected {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        _ => parse_value(raw),
+    };
+
+    let mut table = table;
+
+    for key in parents {
+        let entry = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+
+        table = entry.as_table_mut().unwrap();
+    }
+
+    table.insert(last.clone(), value);
+}
+
+// numbers and booleans keep their type, anything else is a string
+fn parse_value(raw: &str) -> Value {
+    format!("value = {}", raw)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a config file of its own per test, tests run in parallel
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ws_chat_{}_{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+
+        path
+    }
+
+    // an empty config file unless the test brings one, a local ws_chat.toml is not read
+    fn cli(args: &[&str]) -> Cli {
+        let empty = config_file("empty", "");
+        let mut cli = Cli::parse_from(["ws_chat_room"].iter().chain(args));
+        cli.config.get_or_insert(empty);
+
+        cli
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    const DATABASE: &str = r#"
+        [database]
+        host = "file-host"
+        user = "file-user"
+        name = "file-db"
+        password = "file-secret"
+    "#;
+
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let path = config_file(
+            "precedence",
+            &format!(
+                "{}\n[room]\nhistory_size = 10\nidle_timeout_secs = 20",
+                DATABASE
+            ),
+        );
+        let path = path.to_str().unwrap();
+        let legacy = vars(&[("name", "legacy-db"), ("password", "legacy-secret")]);
+        let env = vars(&[
+            ("WS_CHAT_ROOM__HISTORY_SIZE", "30"),
+            ("WS_CHAT_ROOM__IDLE_TIMEOUT_SECS", "40"),
+        ]);
+
+        let config = Config::resolve(
+            &cli(&["--config", path, "--set", "room.idle_timeout_secs=50"]),
+            legacy,
+            env,
+        )
+        .unwrap();
+
+        // the file beats the legacy variables
+        assert_eq!(config.database.name, "file-db");
+        assert_eq!(config.database.password, "file-secret");
+        // the environment beats the file
+        assert_eq!(config.room.history_size, 30);
+        // flags beat the environment
+        assert_eq!(config.room.idle_timeout_secs, 50);
+        // untouched settings keep their defaults
+        assert_eq!(
+            config.room.broadcast_capacity,
+            RoomConfig::default().broadcast_capacity
+        );
+    }
+
+    #[test]
+    fn legacy_variables_fill_in_the_database() {
+        let path = config_file("legacy", "[database]\nhost = \"db\"\nuser = \"chat\"");
+        let legacy = vars(&[("name", "chat"), ("password", "1234")]);
+
+        let config =
+            Config::resolve(&cli(&["--config", path.to_str().unwrap()]), legacy, vec![]).unwrap();
+
+        assert_eq!(config.database.name, "chat");
+        // the template keeps numeric looking secrets strings
+        assert_eq!(config.database.password, "1234");
+    }
+
+    #[test]
+    fn shell_host_and_user_are_not_database_settings() {
+        let env = vars(&[("HOST", "laptop"), ("USER", "alice")]);
+
+        assert!(
+            !LEGACY_ENV
+                .iter()
+                .any(|(name, _key)| matches!(*name, "HOST" | "USER"))
+        );
+        assert!(matches!(
+            Config::resolve(&cli(&[]), vec![], env),
+            Err(ConfigError::Invalid(problems)) if problems.contains(&"database.host is not set".to_string())
+        ));
+    }
+
+    #[test]
+    fn file_tables_merge_into_the_defaults() {
+        let mut base: Table =
+            "[room]\nhistory_size = 1\nidle_timeout_secs = 2\n[login]\nmax_tracked = 3"
+                .parse()
+                .unwrap();
+        let overlay: Table = "[room]\nhistory_size = 10\n[webhook]\nmax_attempts = 4"
+            .parse()
+            .unwrap();
+
+        merge(&mut base, overlay);
+
+        assert_eq!(base["room"]["history_size"].as_integer(), Some(10));
+        assert_eq!(base["room"]["idle_timeout_secs"].as_integer(), Some(2));
+        assert_eq!(base["login"]["max_tracked"].as_integer(), Some(3));
+        assert_eq!(base["webhook"]["max_attempts"].as_integer(), Some(4));
+    }
+
+    #[test]
+    fn env_values_keep_their_types() {
+        let env = vars(&[
+            ("WS_CHAT_DATABASE__HOST", "db"),
+            ("WS_CHAT_DATABASE__USER", "chat"),
+            ("WS_CHAT_DATABASE__NAME", "chat"),
+            ("WS_CHAT_DATABASE__PASSWORD", "42"),
+            ("WS_CHAT_WEBHOOK__ALLOW_PRIVATE_TARGETS", "true"),
+            ("WS_CHAT_DATABASE__MAX_CONNECTIONS", "7"),
+            // single segment names belong to clients like the bot
+            ("WS_CHAT_TOKEN", "wsc_token"),
+        ]);
+
+        let config = Config::resolve(&cli(&[]), vec![], env).unwrap();
+
+        assert_eq!(config.database.password, "42");
+        assert!(config.webhook.allow_private_targets);
+        assert_eq!(config.database.max_connections, 7);
+    }
+
+    #[test]
+    fn invalid_overrides_are_reported() {
+        let env = vars(&[("WS_CHAT_DATABASE__BACKEND", "memory")]);
+
+        assert!(matches!(
+            Config::resolve(&cli(&["--set", "room.history_size"]), vec![], env.clone()),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            Config::resolve(&cli(&["--set", "room.history_size=0"]), vec![], env.clone()),
+            Err(ConfigError::Invalid(problems)) if problems == ["room.history_size must be at least 1"]
+        ));
+        assert!(matches!(
+            Config::resolve(&cli(&["--set", "room.limits.user_per_minute=0"]), vec![], env.clone()),
+            Err(ConfigError::Invalid(problems))
+                if problems == ["room.limits.user_per_minute must be at least 1"]
+        ));
+        assert!(matches!(
+            Config::resolve(&cli(&["--set", "room.unknown=1"]), vec![], env),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,804 @@
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    future,
+    net::IpAddr,
+    sync::{Mutex, MutexGuard},
+};
+use uuid::Uuid;
+
+use super::{
+    AccountStore, BotStore, Credentials, IncomingWebhookStore, MessageStore, NewMessage, RoomStore,
+    SchemaError, Storage, StorageError, StorageFuture, TokenStore, TotpSecret, TotpStore,
+    WebhookStore,
+};
+use crate::{
+    api_token::{self, ApiToken, Scope, TokenOwner},
+    bot::Bot,
+    webhook::{
+        self, EventKind, Target, Webhook, WebhookDelivery,
+        incoming::{self, IncomingWebhook, Integration},
+    },
+};
+
+struct Account {
+    id: i32,
+    account: String,
+    password: String,
+}
+
+struct User {
+    id: i32,
+    account_id: i32,
+    username: String,
+    is_bot: bool,
+    owner_id: Option<i32>,
+}
+
+struct Totp {
+    secret: String,
+    enabled: bool,
+    last_step: Option<u64>,
+}
+
+struct RecoveryCode {
+    account_id: i32,
+    code_hash: String,
+    used: bool,
+}
+
+struct Room {
+    name: String,
+    closed: bool,
+}
+
+struct Token {
+    user_id: i32,
+    token_hash: String,
+    revoked: bool,
+    api_token: ApiToken,
+}
+
+struct Hook {
+    room_id: Uuid,
+    secret: String,
+    deleted: bool,
+    webhook: Webhook,
+}
+
+struct Delivery {
+    webhook_id: i64,
+    delivery: WebhookDelivery,
+}
+
+struct IncomingHook {
+    room_id: Uuid,
+    token_hash: String,
+    deleted: bool,
+    incoming_webhook: IncomingWebhook,
+}
+
+// the tables the postgres schema has, with the constraints handlers rely on
+#[derive(Default)]
+struct State {
+    next_id: i64,
+    accounts: Vec<Account>,
+    users: Vec<User>,
+    // by account id
+    totp: HashMap<i32, Totp>,
+    recovery_codes: Vec<RecoveryCode>,
+    rooms: HashMap<Uuid, Room>,
+    tokens: Vec<Token>,
+    webhooks: Vec<Hook>,
+    deliveries: Vec<Delivery>,
+    incoming_webhooks: Vec<IncomingHook>,
+}
+
+impl State {
+    // one sequence for every table, ids stay unique across them like serials do within one
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn insert_user(
+        &mut self,
+        account: &str,
+        password: &str,
+        username: &str,
+        owner_id: Option<i32>,
+    ) -> Result<(i32, String), StorageError> {
+        if self.accounts.iter().any(|row| row.account == account) {
+            return Err(StorageError::Conflict);
+        }
+
+        let account_id = self.next_id() as i32;
+        let user_id = self.next_id() as i32;
+
+        self.accounts.push(Account {
+            id: account_id,
+            account: account.to_string(),
+            password: password.to_string(),
+        });
+        self.users.push(User {
+            id: user_id,
+            account_id,
+            username: username.to_string(),
+            is_bot: owner_id.is_some(),
+            owner_id,
+        });
+
+        Ok((user_id, username.to_string()))
+    }
+
+    fn user(&self, user_id: i32) -> Option<&User> {
+        self.users.iter().find(|user| user.id == user_id)
+    }
+}
+
+// keeps everything in the process, for tests and running without a database
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+// every call finishes before it returns, the future only hands the result over
+fn ready<'a, T: Send + 'a>(result: Result<T, StorageError>) -> StorageFuture<'a, T> {
+    Box::pin(future::ready(result))
+}
+
+impl Storage for MemoryStorage {
+    fn accounts(&self) -> &dyn AccountStore {
+        self
+    }
+
+    fn bots(&self) -> &dyn BotStore {
+        self
+    }
+
+    fn incoming_webhooks(&self) -> &dyn IncomingWebhookStore {
+        self
+    }
+
+    fn messages(&self) -> &dyn MessageStore {
+        self
+    }
+
+    fn rooms(&self) -> &dyn RoomStore {
+        self
+    }
+
+    fn tokens(&self) -> &dyn TokenStore {
+        self
+    }
+
+    fn totp(&self) -> &dyn TotpStore {
+        self
+    }
+
+    fn webhooks(&self) -> &dyn WebhookStore {
+        self
+    }
+
+    // there is no schema to keep up to date
+    fn migrate(&self) -> BoxFuture<'_, Result<(), SchemaError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn check_schema(&self) -> BoxFuture<'_, Result<(), SchemaError>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn ping(&self) -> StorageFuture<'_, ()> {
+        ready(Ok(()))
+    }
+}
+
+impl AccountStore for MemoryStorage {
+    fn create<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StorageFuture<'a, (i32, String)> {
+        ready(self.state().insert_user(account, password, account, None))
+    }
+
+    fn find_by_credentials<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StorageFuture<'a, Credentials> {
+        let state = self.state();
+
+        let credentials = state
+            .accounts
+            .iter()
+            .find(|row| row.account == account && row.password == password)
+            .and_then(|row| state.users.iter().find(|user| user.account_id == row.id))
+            .filter(|user| !user.is_bot)
+            .map(|user| Credentials {
+                user_id: user.id,
+                username: user.username.clone(),
+                account_id: user.account_id,
+                totp_enabled: state
+                    .totp
+                    .get(&user.account_id)
+                    .is_some_and(|totp| totp.enabled),
+            })
+            .ok_or(StorageError::NotFound);
+
+        ready(credentials)
+    }
+
+    // the login guard already counts failures, the audit trail is only kept by postgres
+    fn record_login_attempt<'a>(
+        &'a self,
+        _account: &'a str,
+        _ip: IpAddr,
+        _outcome: &'a str,
+    ) -> StorageFuture<'a, ()> {
+        ready(Ok(()))
+    }
+
+    fn find_username<'a>(&'a self, username: &'a str) -> StorageFuture<'a, Option<i32>> {
+        let username = username.to_lowercase();
+        let user_id = self
+            .state()
+            .users
+            .iter()
+            .find(|user| user.username.to_lowercase() == username)
+            .map(|user| user.id);
+
+        ready(Ok(user_id))
+    }
+}
+
+impl BotStore for MemoryStorage {
+    fn create<'a>(&'a self, owner_id: i32, name: &'a str) -> StorageFuture<'a, (Bot, String)> {
+        let mut state = self.state();
+
+        let result = state
+            .insert_user(
+                &format!("bot:{}", name),
+                &Uuid::new_v4().to_string(),
+                name,
+                Some(owner_id),
+            )
+            .map(|(id, username)| {
+                let token = api_token::generate_token();
+                let token_id = state.next_id();
+
+                state.tokens.push(Token {
+                    user_id: id,
+                    token_hash: api_token::hash_token(&token),
+                    revoked: false,
+                    api_token: ApiToken {
+                        id: token_id,
+                        name: "bot".to_string(),
+                        scope: Scope::Chat,
+                        created_at: Utc::now(),
+                        last_used_at: None,
+                    },
+                });
+
+                (Bot { id, username }, token)
+            });
+
+        ready(result)
+    }
+
+    fn list(&self, owner_id: i32) -> StorageFuture<'_, Vec<Bot>> {
+        let bots = self
+            .state()
+            .users
+            .iter()
+            .filter(|user| user.is_bot && user.owner_id == Some(owner_id))
+            .map(|user| Bot {
+                id: user.id,
+                username: user.username.clone(),
+            })
+            .collect();
+
+        ready(Ok(bots))
+    }
+
+    fn find(&self, owner_id: i32, bot_id: i32) -> StorageFuture<'_, Option<Bot>> {
+        let bot = self
+            .state()
+            .user(bot_id)
+            .filter(|user| user.is_bot && user.owner_id == Some(owner_id))
+            .map(|user| Bot {
+                id: user.id,
+                username: user.username.clone(),
+            });
+
+        ready(Ok(bot))
+    }
+}
+
+impl IncomingWebhookStore for MemoryStorage {
+    fn create<'a>(
+        &'a self,
+        room_id: Uuid,
+        owner_id: i32,
+        name: &'a str,
+    ) -> StorageFuture<'a, (IncomingWebhook, String)> {
+        let mut state = self.state();
+
+        let result = state
+            .insert_user(
+                &format!("hook:{}", Uuid::new_v4().simple()),
+                &Uuid::new_v4().to_string(),
+                name,
+                Some(owner_id),
+            )
+            .map(|(user_id, _)| {
+                let token = incoming::generate_token();
+                let incoming_webhook = IncomingWebhook {
+                    id: state.next_id(),
+                    name: name.to_string(),
+                    user_id,
+                    created_at: Utc::now(),
+                    last_used_at: None,
+                };
+
+                state.incoming_webhooks.push(IncomingHook {
+                    room_id,
+                    token_hash: api_token::hash_token(&token),
+                    deleted: false,
+                    incoming_webhook: incoming_webhook.clone(),
+                });
+
+                (incoming_webhook, token)
+            });
+
+        ready(result)
+    }
+
+    fn list(&self, room_id: Uuid) -> StorageFuture<'_, Vec<IncomingWebhook>> {
+        let incoming_webhooks = self
+            .state()
+            .incoming_webhooks
+            .iter()
+            .filter(|hook| hook.room_id == room_id && !hook.deleted)
+            .map(|hook| hook.incoming_webhook.clone())
+            .collect();
+
+        ready(Ok(incoming_webhooks))
+    }
+
+    fn delete(&self, room_id: Uuid, id: i64) -> StorageFuture<'_, bool> {
+        let mut state = self.state();
+
+        let deleted = state
+            .incoming_webhooks
+            .iter_mut()
+            .find(|hook| hook.incoming_webhook.id == id && hook.room_id == room_id && !hook.deleted)
+            .map(|hook| hook.deleted = true)
+            .is_some();
+
+        ready(Ok(deleted))
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> StorageFuture<'a, Option<Integration>> {
+        if !incoming::has_prefix(token) {
+            return ready(Ok(None));
+        }
+
+        let token_hash = api_token::hash_token(token);
+        let mut state = self.state();
+
+        let integration = state
+            .incoming_webhooks
+            .iter_mut()
+            .find(|hook| hook.token_hash == token_hash && !hook.deleted)
+            .map(|hook| {
+                hook.incoming_webhook.last_used_at = Some(Utc::now());
+
+                Integration {
+                    room_id: hook.room_id,
+                    user_id: hook.incoming_webhook.user_id,
+                    name: hook.incoming_webhook.name.clone(),
+                }
+            });
+
+        ready(Ok(integration))
+    }
+}
+
+impl MessageStore for MemoryStorage {
+    // rejects the whole batch like the insert does, so the writer's fallback still applies
+    fn insert_batch<'a>(
+        &'a self,
+        messages: &'a [NewMessage],
+    ) -> StorageFuture<'a, Vec<(i64, DateTime<Utc>)>> {
+        let mut state = self.state();
+
+        if let Some(message) = messages.iter().find(|message| {
+            message.content.is_none() || !state.rooms.contains_key(&message.room_id)
+        }) {
+            let problem = match message.content {
+                None => "Message has no content",
+                Some(_) => "Room does not exist",
+            };
+
+            return ready(Err(StorageError::backend(problem)));
+        }
+
+        let sent_at = Utc::now();
+        let rows = messages
+            .iter()
+            .map(|_| (state.next_id(), sent_at))
+            .collect();
+
+        ready(Ok(rows))
+    }
+}
+
+impl RoomStore for MemoryStorage {
+    fn create<'a>(&'a self, room_id: Uuid, room_name: &'a str) -> StorageFuture<'a, ()> {
+        let mut state = self.state();
+
+        if state.rooms.contains_key(&room_id) {
+            return ready(Err(StorageError::Conflict));
+        }
+
+        state.rooms.insert(
+            room_id,
+            Room {
+                name: room_name.to_string(),
+                closed: false,
+            },
+        );
+
+        ready(Ok(()))
+    }
+
+    fn close(&self, room_id: Uuid) -> StorageFuture<'_, ()> {
+        if let Some(room) = self.state().rooms.get_mut(&room_id) {
+            room.closed = true;
+        }
+
+        ready(Ok(()))
+    }
+
+    fn list_open(&self) -> StorageFuture<'_, Vec<(Uuid, String)>> {
+        let rooms = self
+            .state()
+            .rooms
+            .iter()
+            .filter(|(_, room)| !room.closed)
+            .map(|(room_id, room)| (*room_id, room.name.clone()))
+            .collect();
+
+        ready(Ok(rooms))
+    }
+}
+
+impl TokenStore for MemoryStorage {
+    fn create<'a>(
+        &'a self,
+        user_id: i32,
+        name: &'a str,
+        scope: Scope,
+    ) -> StorageFuture<'a, (String, ApiToken)> {
+        let mut state = self.state();
+        let token = api_token::generate_token();
+        let api_token = ApiToken {
+            id: state.next_id(),
+            name: name.to_string(),
+            scope,
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+
+        state.tokens.push(Token {
+            user_id,
+            token_hash: api_token::hash_token(&token),
+            revoked: false,
+            api_token: api_token.clone(),
+        });
+
+        ready(Ok((token, api_token)))
+    }
+
+    fn list(&self, user_id: i32) -> StorageFuture<'_, Vec<ApiToken>> {
+        let api_tokens = self
+            .state()
+            .tokens
+            .iter()
+            .filter(|token| token.user_id == user_id && !token.revoked)
+            .map(|token| token.api_token.clone())
+            .collect();
+
+        ready(Ok(api_tokens))
+    }
+
+    fn revoke(&self, user_id: i32, id: i64) -> StorageFuture<'_, bool> {
+        let revoked = self
+            .state()
+            .tokens
+            .iter_mut()
+            .find(|token| token.api_token.id == id && token.user_id == user_id && !token.revoked)
+            .map(|token| token.revoked = true)
+            .is_some();
+
+        ready(Ok(revoked))
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> StorageFuture<'a, Option<TokenOwner>> {
+        if !api_token::has_prefix(token) {
+            return ready(Ok(None));
+        }
+
+        let token_hash = api_token::hash_token(token);
+        let mut state = self.state();
+
+        let Some(token) = state
+            .tokens
+            .iter_mut()
+            .find(|token| token.token_hash == token_hash && !token.revoked)
+        else {
+            return ready(Ok(None));
+        };
+
+        token.api_token.last_used_at = Some(Utc::now());
+
+        let (token_id, user_id, scope) = (token.api_token.id, token.user_id, token.api_token.scope);
+        let token_owner = state.user(user_id).map(|user| TokenOwner {
+            token_id,
+            user_id,
+            username: user.username.clone(),
+            is_bot: user.is_bot,
+            scope,
+        });
+
+        ready(Ok(token_owner))
+    }
+}
+
+impl TotpStore for MemoryStorage {
+    fn enroll<'a>(&'a self, user_id: i32, secret: &'a str) -> StorageFuture<'a, bool> {
+        let mut state = self.state();
+
+        let Some(account_id) = state.user(user_id).map(|user| user.account_id) else {
+            return ready(Ok(false));
+        };
+
+        if state.totp.get(&account_id).is_some_and(|totp| totp.enabled) {
+            return ready(Ok(false));
+        }
+
+        state.totp.insert(
+            account_id,
+            Totp {
+                secret: secret.to_string(),
+                enabled: false,
+                last_step: None,
+            },
+        );
+
+        ready(Ok(true))
+    }
+
+    fn find(&self, user_id: i32, enabled: bool) -> StorageFuture<'_, Option<TotpSecret>> {
+        let state = self.state();
+
+        let totp_secret = state.user(user_id).and_then(|user| {
+            state
+                .totp
+                .get(&user.account_id)
+                .filter(|totp| totp.enabled == enabled)
+                .map(|totp| TotpSecret {
+                    account_id: user.account_id,
+                    secret: totp.secret.clone(),
+                })
+        });
+
+        ready(Ok(totp_secret))
+    }
+
+    fn enabled_secret(&self, account_id: i32) -> StorageFuture<'_, String> {
+        let secret = self
+            .state()
+            .totp
+            .get(&account_id)
+            .filter(|totp| totp.enabled)
+            .map(|totp| totp.secret.clone())
+            .ok_or(StorageError::NotFound);
+
+        ready(secret)
+    }
+
+    fn enable<'a>(&'a self, account_id: i32, code_hashes: &'a [String]) -> StorageFuture<'a, ()> {
+        let mut state = self.state();
+
+        if let Some(totp) = state.totp.get_mut(&account_id) {
+            totp.enabled = true;
+        }
+
+        state
+            .recovery_codes
+            .retain(|code| code.account_id != account_id);
+        state
+            .recovery_codes
+            .extend(code_hashes.iter().map(|code_hash| RecoveryCode {
+                account_id,
+                code_hash: code_hash.clone(),
+                used: false,
+            }));
+
+        ready(Ok(()))
+    }
+
+    fn disable(&self, account_id: i32) -> StorageFuture<'_, ()> {
+        let mut state = self.state();
+
+        state
+            .recovery_codes
+            .retain(|code| code.account_id != account_id);
+        state.totp.remove(&account_id);
+
+        ready(Ok(()))
+    }
+
+    fn accept_step(&self, account_id: i32, step: u64) -> StorageFuture<'_, bool> {
+        let accepted = self
+            .state()
+            .totp
+            .get_mut(&account_id)
+            .filter(|totp| totp.last_step.is_none_or(|last_step| last_step < step))
+            .map(|totp| totp.last_step = Some(step))
+            .is_some();
+
+        ready(Ok(accepted))
+    }
+
+    fn use_recovery_code<'a>(
+        &'a self,
+        account_id: i32,
+        code_hash: &'a str,
+    ) -> StorageFuture<'a, bool> {
+        let used = self
+            .state()
+            .recovery_codes
+            .iter_mut()
+            .find(|code| code.account_id == account_id && code.code_hash == code_hash && !code.used)
+            .map(|code| code.used = true)
+            .is_some();
+
+        ready(Ok(used))
+    }
+}
+
+impl WebhookStore for MemoryStorage {
+    fn create<'a>(
+        &'a self,
+        room_id: Uuid,
+        url: &'a str,
+        events: Vec<EventKind>,
+    ) -> StorageFuture<'a, (Webhook, String)> {
+        let mut state = self.state();
+        let secret = webhook::generate_secret();
+        let webhook = Webhook {
+            id: state.next_id(),
+            url: url.to_string(),
+            events,
+            created_at: Utc::now(),
+        };
+
+        state.webhooks.push(Hook {
+            room_id,
+            secret: secret.clone(),
+            deleted: false,
+            webhook: webhook.clone(),
+        });
+
+        ready(Ok((webhook, secret)))
+    }
+
+    fn list(&self, room_id: Uuid) -> StorageFuture<'_, Vec<Webhook>> {
+        let webhooks = self
+            .state()
+            .webhooks
+            .iter()
+            .filter(|hook| hook.room_id == room_id && !hook.deleted)
+            .map(|hook| hook.webhook.clone())
+            .collect();
+
+        ready(Ok(webhooks))
+    }
+
+    fn delete(&self, room_id: Uuid, id: i64) -> StorageFuture<'_, bool> {
+        let deleted = self
+            .state()
+            .webhooks
+            .iter_mut()
+            .find(|hook| hook.webhook.id == id && hook.room_id == room_id && !hook.deleted)
+            .map(|hook| hook.deleted = true)
+            .is_some();
+
+        ready(Ok(deleted))
+    }
+
+    fn list_deliveries(
+        &self,
+        room_id: Uuid,
+        webhook_id: i64,
+    ) -> StorageFuture<'_, Vec<WebhookDelivery>> {
+        let state = self.state();
+
+        let in_room = state
+            .webhooks
+            .iter()
+            .any(|hook| hook.webhook.id == webhook_id && hook.room_id == room_id);
+        let deliveries = state
+            .deliveries
+            .iter()
+            .rev()
+            .filter(|delivery| in_room && delivery.webhook_id == webhook_id)
+            .take(100)
+            .map(|delivery| delivery.delivery.clone())
+            .collect();
+
+        ready(Ok(deliveries))
+    }
+
+    fn targets(&self, room_id: Uuid, kind: EventKind) -> StorageFuture<'_, Vec<Target>> {
+        let targets = self
+            .state()
+            .webhooks
+            .iter()
+            .filter(|hook| {
+                hook.room_id == room_id && hook.webhook.events.contains(&kind) && !hook.deleted
+            })
+            .map(|hook| Target {
+                id: hook.webhook.id,
+                url: hook.webhook.url.clone(),
+                secret: hook.secret.clone(),
+            })
+            .collect();
+
+        ready(Ok(targets))
+    }
+
+    fn record_attempt<'a>(
+        &'a self,
+        webhook_id: i64,
+        delivery_id: Uuid,
+        kind: EventKind,
+        attempt: u32,
+        status_code: Option<u16>,
+        error: Option<&'a str>,
+    ) -> StorageFuture<'a, ()> {
+        let mut state = self.state();
+        let delivery = WebhookDelivery {
+            id: state.next_id(),
+            delivery_id,
+            event: kind.as_str().to_string(),
+            attempt: attempt as i32,
+            status_code: status_code.map(i32::from),
+            error: error.map(str::to_string),
+            succeeded: error.is_none(),
+            attempted_at: Utc::now(),
+        };
+
+        state.deliveries.push(Delivery {
+            webhook_id,
+            delivery,
+        });
+
+        ready(Ok(()))
+    }
+}
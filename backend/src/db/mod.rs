@@ -1,74 +1,29 @@
-use sqlx::{
-    Pool, Postgres,
-    postgres::{PgConnectOptions, PgPoolOptions},
+use std::sync::Arc;
+
+use crate::config::{DatabaseConfig, StorageBackend};
+
+mod memory;
+pub use memory::MemoryStorage;
+mod postgres;
+pub use postgres::{PgStorage, migrate::SchemaError};
+mod storage;
+pub use storage::{
+    AccountStore, BotStore, Credentials, IncomingWebhookStore, MessageStore, NewMessage, RoomStore,
+    Storage, StorageError, StorageFuture, TokenStore, TotpSecret, TotpStore, WebhookStore,
 };
-use std::{str::FromStr, time::Duration};
-use tokio::sync::mpsc::{self, Sender};
-use uuid::Uuid;
+mod writer;
+pub use writer::{MessageWriter, WriterProbe, WriterStats};
 
-use crate::room_manager::RoomCommand;
+// the one storage handlers, rooms, webhooks and the message writer share
+pub type Db = Arc<dyn Storage>;
 
-pub async fn db_connection() -> Pool<Postgres> {
-    let connection_option = PgConnectOptions::new()
-        .host(&dotenv::var("HOST").unwrap())
-        .username(&dotenv::var("USER").unwrap())
-        .password(&dotenv::var("PASSWORD").unwrap())
-        .database(&dotenv::var("DBNAME").unwrap())
-        .ssl_mode(sqlx::postgres::PgSslMode::Require);
+pub async fn connect(config: &DatabaseConfig) -> Db {
+    match config.backend {
+        StorageBackend::Postgres => Arc::new(PgStorage::connect(config).await),
+        StorageBackend::Memory => {
+            tracing::warn!("Using in-memory storage, nothing is kept after the server stops");
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(3))
-        .connect_with(connection_option)
-        .await
-        .map_err(|err| panic!("Database connection error: {}", err))
-        .unwrap();
-
-    tracing::info!("Database connected...");
-
-    pool
-}
-
-pub async fn db_messages_connection() -> Sender<RoomCommand> {
-    let (sender, mut receiver) = mpsc::channel::<RoomCommand>(128);
-
-    let connection_option = PgConnectOptions::new()
-        .host(&dotenv::var("HOST").unwrap())
-        .username(&dotenv::var("USER").unwrap())
-        .password(&dotenv::var("PASSWORD").unwrap())
-        .database(&dotenv::var("DBNAME").unwrap())
-        .ssl_mode(sqlx::postgres::PgSslMode::Require);
-
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(3))
-        .connect_with(connection_option)
-        .await
-        .map_err(|err| panic!("Database connection error: {}", err))
-        .unwrap();
-
-    tokio::spawn(async move {
-        while let Some(command) = receiver.recv().await {
-            let query_str = r#"
-                insert into messages(room_id, user_id, content)
-                values($1, $2, $3)
-            "#;
-
-            if let Ok(room_id) = Uuid::from_str(&command.room_id.unwrap()) {
-                let _ = sqlx::query(query_str)
-                    .bind(room_id)
-                    .bind(&command.user_id.unwrap())
-                    .bind(&command.message.unwrap())
-                    .execute(&pool)
-                    .await
-                    .map_err(|err| {
-                        tracing::error!("Failed to insert message: {}", err.to_string())
-                    });
-            };
+            Arc::new(MemoryStorage::new())
         }
-    });
-
-    tracing::info!("Listening on message...");
-
-    sender
+    }
 }
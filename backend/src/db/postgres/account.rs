@@ -0,0 +1,140 @@
+use sqlx::{Pool, Postgres, Transaction};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::db::{AccountStore, Credentials, StorageFuture};
+
+pub struct AccountRepo {
+    pool: Pool<Postgres>,
+}
+
+impl AccountRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        AccountRepo { pool }
+    }
+}
+
+impl AccountStore for AccountRepo {
+    fn create<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StorageFuture<'a, (i32, String)> {
+        Box::pin(async move {
+            let row = sqlx::query!(
+                r#"
+                with new_account as (
+                    insert into accounts(account, password) values($1, $2)
+                    returning id, account
+                )
+                insert into users(account_id, username)
+                select id, account from new_account
+                returning id, username
+                "#,
+                account,
+                password,
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok((row.id, row.username))
+        })
+    }
+
+    fn find_by_credentials<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StorageFuture<'a, Credentials> {
+        Box::pin(async move {
+            let row = sqlx::query!(
+                r#"
+                select u.id as "user_id!", u.username as "username!", a.id as account_id,
+                    coalesce(t.enabled, false) as "totp_enabled!"
+                from accounts a
+                left join users u on u.account_id = a.id
+                left join account_totp t on t.account_id = a.id
+                where a.account = $1 and a.password = $2 and not coalesce(u.is_bot, false)
+                "#,
+                account,
+                password,
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok(Credentials {
+                user_id: row.user_id,
+                username: row.username,
+                account_id: row.account_id,
+                totp_enabled: row.totp_enabled,
+            })
+        })
+    }
+
+    fn record_login_attempt<'a>(
+        &'a self,
+        account: &'a str,
+        ip: IpAddr,
+        outcome: &'a str,
+    ) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            // bound as text, sqlx only maps inet to the ipnetwork types
+            sqlx::query!(
+                r#"
+                insert into login_attempts(account, ip, outcome)
+                values($1, $2::text::inet, $3)
+                "#,
+                account,
+                ip.to_string(),
+                outcome,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(())
+        })
+    }
+
+    fn find_username<'a>(&'a self, username: &'a str) -> StorageFuture<'a, Option<i32>> {
+        Box::pin(async move {
+            let id = sqlx::query_scalar!(
+                r#"
+                select id from users where lower(username) = lower($1) limit 1
+                "#,
+                username,
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(id)
+        })
+    }
+}
+
+// bots and incoming webhooks get an account without a usable password
+pub(super) async fn insert_bot_user(
+    tx: &mut Transaction<'_, Postgres>,
+    account: &str,
+    username: &str,
+    owner_id: i32,
+) -> Result<(i32, String), sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        with new_account as (
+            insert into accounts(account, password) values($1, $2)
+            returning id
+        )
+        insert into users(account_id, username, is_bot, owner_id)
+        select id, $3, true, $4 from new_account
+        returning id, username
+        "#,
+        account,
+        Uuid::new_v4().to_string(),
+        username,
+        owner_id,
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok((row.id, row.username))
+}
@@ -0,0 +1,83 @@
+use sqlx::{Pool, Postgres};
+
+use super::account::insert_bot_user;
+use crate::{
+    api_token::{self, Scope},
+    bot::Bot,
+    db::{BotStore, StorageFuture},
+};
+
+pub struct BotRepo {
+    pool: Pool<Postgres>,
+}
+
+impl BotRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        BotRepo { pool }
+    }
+}
+
+impl BotStore for BotRepo {
+    fn create<'a>(&'a self, owner_id: i32, name: &'a str) -> StorageFuture<'a, (Bot, String)> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            let (id, username) =
+                insert_bot_user(&mut tx, &format!("bot:{}", name), name, owner_id).await?;
+            let bot = Bot { id, username };
+
+            // the bot's own token, returned once to its owner
+            let token = api_token::generate_token();
+
+            sqlx::query!(
+                "insert into api_tokens(user_id, name, token_hash, scope) values($1, $2, $3, $4)",
+                bot.id,
+                "bot",
+                api_token::hash_token(&token),
+                Scope::Chat.as_str(),
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+
+            Ok((bot, token))
+        })
+    }
+
+    fn list(&self, owner_id: i32) -> StorageFuture<'_, Vec<Bot>> {
+        Box::pin(async move {
+            let bots = sqlx::query_as!(
+                Bot,
+                r#"
+                select id, username from users
+                where owner_id = $1 and is_bot
+                order by id
+                "#,
+                owner_id,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(bots)
+        })
+    }
+
+    fn find(&self, owner_id: i32, bot_id: i32) -> StorageFuture<'_, Option<Bot>> {
+        Box::pin(async move {
+            let bot = sqlx::query_as!(
+                Bot,
+                r#"
+                select id, username from users
+                where id = $1 and owner_id = $2 and is_bot
+                "#,
+                bot_id,
+                owner_id,
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(bot)
+        })
+    }
+}
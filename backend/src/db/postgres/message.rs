@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::db::{MessageStore, NewMessage, StorageFuture};
+
+pub struct MessageRepo {
+    pool: Pool<Postgres>,
+}
+
+impl MessageRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        MessageRepo { pool }
+    }
+}
+
+impl MessageStore for MessageRepo {
+    // one multi-row insert
+    fn insert_batch<'a>(
+        &'a self,
+        messages: &'a [NewMessage],
+    ) -> StorageFuture<'a, Vec<(i64, DateTime<Utc>)>> {
+        Box::pin(async move {
+            let room_ids: Vec<Uuid> = messages.iter().map(|message| message.room_id).collect();
+            let user_ids: Vec<Option<i32>> =
+                messages.iter().map(|message| message.user_id).collect();
+            let contents: Vec<Option<String>> = messages
+                .iter()
+                .map(|message| message.content.clone())
+                .collect();
+
+            let rows = sqlx::query!(
+                r#"
+                insert into messages(room_id, user_id, content)
+                select room_id, user_id, content
+                from unnest($1::uuid[], $2::int[], $3::text[]) with ordinality as m(room_id, user_id, content, position)
+                order by position
+                returning id::bigint as "id!", sent_at::timestamptz as "sent_at!"
+                "#,
+                &room_ids,
+                &user_ids as &[Option<i32>],
+                &contents as &[Option<String>],
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            let mut rows: Vec<(i64, DateTime<Utc>)> =
+                rows.into_iter().map(|row| (row.id, row.sent_at)).collect();
+            rows.sort_by_key(|(message_id, _)| *message_id);
+
+            Ok(rows)
+        })
+    }
+}
@@ -0,0 +1,265 @@
+// The repositories' queries are checked at compile time, these stay runtime `sqlx::query`s: they
+// read sqlx's own bookkeeping table, which doesn't exist until the first migration ran, and run
+// before the schema they would be checked against has been created.
+use sqlx::{
+    Pool, Postgres, Row,
+    migrate::{MigrateError, Migrator},
+};
+use std::fmt;
+
+// backend/migrations, embedded at build time
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Database(sqlx::Error),
+    Migrate(MigrateError),
+    // a hand-made schema the pre-flight could not complete
+    Adopt(sqlx::Error),
+    // migrations this build ships that the database has not run
+    Pending(Vec<i64>),
+    // migrations the database has run that this build does not know
+    Unknown(Vec<i64>),
+    // migrations whose SQL changed after they were applied
+    Modified(Vec<i64>),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let versions = |versions: &[i64]| {
+            versions
+                .iter()
+                .map(i64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            SchemaError::Database(err) => write!(f, "Failed to read the schema version: {}", err),
+            SchemaError::Migrate(err) => write!(f, "Migration failed: {}", err),
+            SchemaError::Adopt(err) => write!(f, "Could not adopt the existing schema: {}", err),
+            SchemaError::Pending(pending) => write!(
+                f,
+                "Database schema is out of date, pending migrations: {} (run `ws_chat_room migrate` or set database.auto_migrate)",
+                versions(pending)
+            ),
+            SchemaError::Unknown(unknown) => write!(
+                f,
+                "Database schema is newer than this build, unknown migrations: {}",
+                versions(unknown)
+            ),
+            SchemaError::Modified(modified) => write!(
+                f,
+                "Applied migrations were modified afterwards: {}",
+                versions(modified)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+// completes a schema 0001 adopts, the migrations after it reference its keys
+const ADOPT_SCHEMA: &str = include_str!("adopt_schema.sql");
+
+pub async fn run(pool: &Pool<Postgres>) -> Result<(), SchemaError> {
+    adopt(pool).await?;
+
+    MIGRATOR.run(pool).await.map_err(SchemaError::Migrate)
+}
+
+// a hand-made schema gets the pre-flight before the migrations run, they would otherwise
+// fail on a missing key with an error that doesn't say why
+async fn adopt(pool: &Pool<Postgres>) -> Result<(), SchemaError> {
+    let row = sqlx::query(
+        "select to_regclass('accounts') is not null, to_regclass('_sqlx_migrations') is not null",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(SchemaError::Database)?;
+    let (has_accounts, has_table): (bool, bool) = (row.get(0), row.get(1));
+
+    if !has_accounts {
+        return Ok(());
+    }
+
+    if has_table {
+        let adopted: bool =
+            sqlx::query("select exists (select 1 from _sqlx_migrations where version = 8)")
+                .fetch_one(pool)
+                .await
+                .map_err(SchemaError::Database)?
+                .get(0);
+
+        if adopted {
+            return Ok(());
+        }
+    }
+
+    // one simple query, postgres runs its statements in one implicit transaction
+    sqlx::raw_sql(ADOPT_SCHEMA)
+        .execute(pool)
+        .await
+        .map_err(SchemaError::Adopt)?;
+
+    Ok(())
+}
+
+// compares the applied migrations with the embedded ones without changing anything
+pub async fn check(pool: &Pool<Postgres>) -> Result<(), SchemaError> {
+    // a database that never ran a migration has no bookkeeping table yet
+    let has_table: bool = sqlx::query("select to_regclass('_sqlx_migrations') is not null")
+        .fetch_one(pool)
+        .await
+        .map_err(SchemaError::Database)?
+        .get(0);
+
+    let query_str = r#"
+        select version, checksum from _sqlx_migrations
+        where success
+        order by version
+    "#;
+
+    let applied: Vec<(i64, Vec<u8>)> = if has_table {
+        sqlx::query(query_str)
+            .fetch_all(pool)
+            .await
+            .map_err(SchemaError::Database)?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let unknown: Vec<i64> = applied
+        .iter()
+        .filter(|(version, _)| {
+            !MIGRATOR
+                .iter()
+                .any(|migration| migration.version == *version)
+        })
+        .map(|(version, _)| *version)
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(SchemaError::Unknown(unknown));
+    }
+
+    let modified: Vec<i64> = applied
+        .iter()
+        .filter(|(version, checksum)| {
+            MIGRATOR.iter().any(|migration| {
+                migration.version == *version && *migration.checksum != checksum[..]
+            })
+        })
+        .map(|(version, _)| *version)
+        .collect();
+
+    if !modified.is_empty() {
+        return Err(SchemaError::Modified(modified));
+    }
+
+    let pending: Vec<i64> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| {
+            !applied
+                .iter()
+                .any(|(version, _)| *version == migration.version)
+        })
+        .map(|migration| migration.version)
+        .collect();
+
+    if !pending.is_empty() {
+        return Err(SchemaError::Pending(pending));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{
+        Connection, Executor, PgConnection,
+        postgres::{PgConnectOptions, PgPoolOptions},
+    };
+    use uuid::Uuid;
+
+    use super::*;
+
+    // migrates a scratch database on the server `DATABASE_URL` points at, created with `tables`
+    async fn migrate_hand_made(tables: &str) -> Result<(), SchemaError> {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let name = format!("ws_chat_adopt_{}", Uuid::new_v4().simple());
+
+        let mut server = PgConnection::connect(&url).await.unwrap();
+        server
+            .execute(format!("create database {}", name).as_str())
+            .await
+            .unwrap();
+
+        let options = url.parse::<PgConnectOptions>().unwrap().database(&name);
+        let pool = PgPoolOptions::new().connect_with(options).await.unwrap();
+        sqlx::raw_sql(tables).execute(&pool).await.unwrap();
+
+        let result = match run(&pool).await {
+            Ok(()) => check(&pool).await,
+            Err(err) => Err(err),
+        };
+
+        pool.close().await;
+        server
+            .execute(format!("drop database {}", name).as_str())
+            .await
+            .unwrap();
+
+        result
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server, set DATABASE_URL"]
+    async fn hand_made_schema_is_adopted() {
+        let tables = r#"
+            create table accounts (id serial, account text, password text, created_at timestamptz);
+            create table users (id serial, account_id int, username text);
+            create table rooms (id uuid, room_name text, created_at timestamptz, closed_at timestamptz);
+            create table messages (id bigserial, room_id uuid, user_id int, content text, sent_at timestamptz);
+            insert into accounts(account, password) values('alice', 'secret');
+        "#;
+
+        migrate_hand_made(tables).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server, set DATABASE_URL"]
+    async fn missing_column_fails_naming_the_table() {
+        let tables = "create table accounts (id serial, account text, created_at timestamptz)";
+
+        let err = migrate_hand_made(tables).await.unwrap_err().to_string();
+
+        assert!(
+            err.starts_with("Could not adopt the existing schema"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("Existing table accounts has no password column"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres server, set DATABASE_URL"]
+    async fn duplicate_rows_fail_naming_the_constraint() {
+        let tables = r#"
+            create table accounts (id serial, account text, password text, created_at timestamptz);
+            insert into accounts(account, password) values('alice', 'one'), ('alice', 'two');
+        "#;
+
+        let err = migrate_hand_made(tables).await.unwrap_err().to_string();
+
+        assert!(err.contains("Could not add unique accounts"), "{}", err);
+    }
+}
@@ -0,0 +1,150 @@
+use futures_util::future::BoxFuture;
+use sqlx::{
+    Connection, Pool, Postgres,
+    postgres::{PgConnectOptions, PgPoolOptions},
+};
+
+use super::{
+    AccountStore, BotStore, IncomingWebhookStore, MessageStore, RoomStore, Storage, StorageError,
+    StorageFuture, TokenStore, TotpStore, WebhookStore,
+};
+use crate::config::DatabaseConfig;
+
+mod account;
+use account::AccountRepo;
+mod bot;
+use bot::BotRepo;
+mod message;
+use message::MessageRepo;
+pub mod migrate;
+mod room;
+use room::RoomRepo;
+mod token;
+use token::TokenRepo;
+mod totp;
+use totp::TotpRepo;
+mod webhook;
+use webhook::{IncomingWebhookRepo, WebhookRepo};
+
+// every repository holds a handle to the one pool the server shares
+pub struct PgStorage {
+    pool: Pool<Postgres>,
+    accounts: AccountRepo,
+    bots: BotRepo,
+    incoming_webhooks: IncomingWebhookRepo,
+    messages: MessageRepo,
+    rooms: RoomRepo,
+    tokens: TokenRepo,
+    totp: TotpRepo,
+    webhooks: WebhookRepo,
+}
+
+impl PgStorage {
+    pub async fn connect(config: &DatabaseConfig) -> PgStorage {
+        let connection_option = PgConnectOptions::new()
+            .host(&config.host)
+            .username(&config.user)
+            .password(&config.password)
+            .database(&config.name)
+            .ssl_mode(sqlx::postgres::PgSslMode::Require);
+
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.acquire_timeout())
+            .connect_with(connection_option)
+            .await
+            .map_err(|err| panic!("Database connection error: {}", err))
+            .unwrap();
+
+        tracing::info!("Database connected...");
+
+        PgStorage {
+            accounts: AccountRepo::new(pool.clone()),
+            bots: BotRepo::new(pool.clone()),
+            incoming_webhooks: IncomingWebhookRepo::new(pool.clone()),
+            messages: MessageRepo::new(pool.clone()),
+            rooms: RoomRepo::new(pool.clone()),
+            tokens: TokenRepo::new(pool.clone()),
+            totp: TotpRepo::new(pool.clone()),
+            webhooks: WebhookRepo::new(pool.clone()),
+            pool,
+        }
+    }
+}
+
+impl Storage for PgStorage {
+    fn accounts(&self) -> &dyn AccountStore {
+        &self.accounts
+    }
+
+    fn bots(&self) -> &dyn BotStore {
+        &self.bots
+    }
+
+    fn incoming_webhooks(&self) -> &dyn IncomingWebhookStore {
+        &self.incoming_webhooks
+    }
+
+    fn messages(&self) -> &dyn MessageStore {
+        &self.messages
+    }
+
+    fn rooms(&self) -> &dyn RoomStore {
+        &self.rooms
+    }
+
+    fn tokens(&self) -> &dyn TokenStore {
+        &self.tokens
+    }
+
+    fn totp(&self) -> &dyn TotpStore {
+        &self.totp
+    }
+
+    fn webhooks(&self) -> &dyn WebhookStore {
+        &self.webhooks
+    }
+
+    fn migrate(&self) -> BoxFuture<'_, Result<(), migrate::SchemaError>> {
+        Box::pin(migrate::run(&self.pool))
+    }
+
+    fn check_schema(&self) -> BoxFuture<'_, Result<(), migrate::SchemaError>> {
+        Box::pin(migrate::check(&self.pool))
+    }
+
+    fn ping(&self) -> StorageFuture<'_, ()> {
+        Box::pin(async move {
+            let mut connection = self.pool.acquire().await?;
+            connection.ping().await?;
+
+            Ok(())
+        })
+    }
+}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => StorageError::NotFound,
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => StorageError::Conflict,
+            err if is_transient(&err) => StorageError::Unavailable(Box::new(err)),
+            err => StorageError::Backend(Box::new(err)),
+        }
+    }
+}
+
+// connection trouble, timeouts and serialization conflicts may pass on a retry
+fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::Protocol(_) => true,
+        sqlx::Error::Database(err) => err.code().is_some_and(|code| {
+            code.starts_with("08")
+                || code.starts_with("53")
+                || code.starts_with("57P")
+                || code == "40001"
+                || code == "40P01"
+        }),
+        _ => false,
+    }
+}
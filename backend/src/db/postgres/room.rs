@@ -0,0 +1,66 @@
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::db::{RoomStore, StorageFuture};
+
+pub struct RoomRepo {
+    pool: Pool<Postgres>,
+}
+
+impl RoomRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        RoomRepo { pool }
+    }
+}
+
+impl RoomStore for RoomRepo {
+    fn create<'a>(&'a self, room_id: Uuid, room_name: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            sqlx::query!(
+                r#"
+                insert into rooms(id, room_name)
+                values($1, $2);
+                "#,
+                room_id,
+                room_name,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(())
+        })
+    }
+
+    fn close(&self, room_id: Uuid) -> StorageFuture<'_, ()> {
+        Box::pin(async move {
+            sqlx::query!(
+                r#"
+                update rooms set closed_at = now() where id = $1;
+                "#,
+                room_id,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(())
+        })
+    }
+
+    fn list_open(&self) -> StorageFuture<'_, Vec<(Uuid, String)>> {
+        Box::pin(async move {
+            let rows = sqlx::query!(
+                r#"
+                select id, room_name from rooms
+                where closed_at is null;
+                "#
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(rows
+                .into_iter()
+                .map(|row| (row.id, row.room_name))
+                .collect())
+        })
+    }
+}
@@ -0,0 +1,130 @@
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    api_token::{self, ApiToken, Scope, TokenOwner},
+    db::{StorageFuture, TokenStore},
+};
+
+pub struct TokenRepo {
+    pool: Pool<Postgres>,
+}
+
+impl TokenRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        TokenRepo { pool }
+    }
+}
+
+impl TokenStore for TokenRepo {
+    fn create<'a>(
+        &'a self,
+        user_id: i32,
+        name: &'a str,
+        scope: Scope,
+    ) -> StorageFuture<'a, (String, ApiToken)> {
+        Box::pin(async move {
+            let token = api_token::generate_token();
+
+            let row = sqlx::query!(
+                r#"
+                insert into api_tokens(user_id, name, token_hash, scope)
+                values($1, $2, $3, $4)
+                returning id::bigint as "id!", created_at::timestamptz as "created_at!"
+                "#,
+                user_id,
+                name,
+                api_token::hash_token(&token),
+                scope.as_str(),
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            let api_token = ApiToken {
+                id: row.id,
+                name: name.to_string(),
+                scope,
+                created_at: row.created_at,
+                last_used_at: None,
+            };
+
+            Ok((token, api_token))
+        })
+    }
+
+    fn list(&self, user_id: i32) -> StorageFuture<'_, Vec<ApiToken>> {
+        Box::pin(async move {
+            let rows = sqlx::query!(
+                r#"
+                select id::bigint as "id!", name, scope, created_at::timestamptz as "created_at!",
+                    last_used_at::timestamptz
+                from api_tokens
+                where user_id = $1 and revoked_at is null
+                order by created_at
+                "#,
+                user_id,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(rows
+                .into_iter()
+                .filter_map(|row| {
+                    Some(ApiToken {
+                        id: row.id,
+                        name: row.name,
+                        scope: Scope::parse(&row.scope)?,
+                        created_at: row.created_at,
+                        last_used_at: row.last_used_at,
+                    })
+                })
+                .collect())
+        })
+    }
+
+    fn revoke(&self, user_id: i32, id: i64) -> StorageFuture<'_, bool> {
+        Box::pin(async move {
+            let result = sqlx::query!(
+                r#"
+                update api_tokens set revoked_at = now()
+                where id = $1 and user_id = $2 and revoked_at is null
+                "#,
+                id,
+                user_id,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> StorageFuture<'a, Option<TokenOwner>> {
+        Box::pin(async move {
+            if !api_token::has_prefix(token) {
+                return Ok(None);
+            }
+
+            let row = sqlx::query!(
+                r#"
+                update api_tokens t set last_used_at = now()
+                from users u
+                where t.token_hash = $1 and t.revoked_at is null and u.id = t.user_id
+                returning t.id, t.user_id, u.username, u.is_bot, t.scope
+                "#,
+                api_token::hash_token(token),
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(row.and_then(|row| {
+                Some(TokenOwner {
+                    token_id: row.id,
+                    user_id: row.user_id,
+                    username: row.username,
+                    is_bot: row.is_bot,
+                    scope: Scope::parse(&row.scope)?,
+                })
+            }))
+        })
+    }
+}
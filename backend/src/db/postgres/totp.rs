@@ -0,0 +1,168 @@
+use sqlx::{Pool, Postgres};
+
+use crate::db::{StorageFuture, TotpSecret, TotpStore};
+
+pub struct TotpRepo {
+    pool: Pool<Postgres>,
+}
+
+impl TotpRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        TotpRepo { pool }
+    }
+}
+
+impl TotpStore for TotpRepo {
+    fn enroll<'a>(&'a self, user_id: i32, secret: &'a str) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let enrolled = sqlx::query_scalar!(
+                r#"
+                insert into account_totp(account_id, secret, enabled)
+                select account_id, $2, false from users where id = $1
+                on conflict (account_id) do update set secret = excluded.secret
+                where account_totp.enabled = false
+                returning account_id
+                "#,
+                user_id,
+                secret,
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(enrolled.is_some())
+        })
+    }
+
+    fn find(&self, user_id: i32, enabled: bool) -> StorageFuture<'_, Option<TotpSecret>> {
+        Box::pin(async move {
+            let secret = sqlx::query_as!(
+                TotpSecret,
+                r#"
+                select t.account_id, t.secret from account_totp t
+                join users u on u.account_id = t.account_id
+                where u.id = $1 and t.enabled = $2
+                "#,
+                user_id,
+                enabled,
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(secret)
+        })
+    }
+
+    fn enabled_secret(&self, account_id: i32) -> StorageFuture<'_, String> {
+        Box::pin(async move {
+            let secret = sqlx::query_scalar!(
+                r#"
+                select secret from account_totp
+                where account_id = $1 and enabled
+                "#,
+                account_id,
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            Ok(secret)
+        })
+    }
+
+    // one transaction
+    fn enable<'a>(&'a self, account_id: i32, code_hashes: &'a [String]) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            sqlx::query!(
+                "update account_totp set enabled = true where account_id = $1",
+                account_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!(
+                "delete from totp_recovery_codes where account_id = $1",
+                account_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                insert into totp_recovery_codes(account_id, code_hash)
+                select $1, unnest($2::text[])
+                "#,
+                account_id,
+                code_hashes,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    }
+
+    fn disable(&self, account_id: i32) -> StorageFuture<'_, ()> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            sqlx::query!(
+                "delete from totp_recovery_codes where account_id = $1",
+                account_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query!("delete from account_totp where account_id = $1", account_id)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+    }
+
+    // one statement, concurrent logins with the same code can't both pass
+    fn accept_step(&self, account_id: i32, step: u64) -> StorageFuture<'_, bool> {
+        Box::pin(async move {
+            let accepted = sqlx::query_scalar!(
+                r#"
+                update account_totp set last_step = $2
+                where account_id = $1 and (last_step is null or last_step < $2)
+                returning account_id
+                "#,
+                account_id,
+                step as i64,
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(accepted.is_some())
+        })
+    }
+
+    fn use_recovery_code<'a>(
+        &'a self,
+        account_id: i32,
+        code_hash: &'a str,
+    ) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let used = sqlx::query_scalar!(
+                r#"
+                update totp_recovery_codes set used_at = now()
+                where account_id = $1 and code_hash = $2 and used_at is null
+                returning id
+                "#,
+                account_id,
+                code_hash,
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(used.is_some())
+        })
+    }
+}
@@ -0,0 +1,300 @@
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use super::account::insert_bot_user;
+use crate::{
+    api_token::hash_token,
+    db::{IncomingWebhookStore, StorageFuture, WebhookStore},
+    webhook::{
+        self, EventKind, Target, Webhook, WebhookDelivery,
+        incoming::{self, IncomingWebhook, Integration},
+    },
+};
+
+pub struct WebhookRepo {
+    pool: Pool<Postgres>,
+}
+
+impl WebhookRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        WebhookRepo { pool }
+    }
+}
+
+impl WebhookStore for WebhookRepo {
+    fn create<'a>(
+        &'a self,
+        room_id: Uuid,
+        url: &'a str,
+        events: Vec<EventKind>,
+    ) -> StorageFuture<'a, (Webhook, String)> {
+        Box::pin(async move {
+            let secret = webhook::generate_secret();
+
+            let event_names: Vec<String> = events
+                .iter()
+                .map(|event| event.as_str().to_string())
+                .collect();
+
+            let row = sqlx::query!(
+                r#"
+                insert into room_webhooks(room_id, url, secret, events)
+                values($1, $2, $3, $4)
+                returning id::bigint as "id!", created_at::timestamptz as "created_at!"
+                "#,
+                room_id,
+                url,
+                &secret,
+                &event_names,
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+            let webhook = Webhook {
+                id: row.id,
+                url: url.to_string(),
+                events,
+                created_at: row.created_at,
+            };
+
+            Ok((webhook, secret))
+        })
+    }
+
+    fn list(&self, room_id: Uuid) -> StorageFuture<'_, Vec<Webhook>> {
+        Box::pin(async move {
+            let rows = sqlx::query!(
+                r#"
+                select id::bigint as "id!", url, events, created_at::timestamptz as "created_at!"
+                from room_webhooks
+                where room_id = $1 and deleted_at is null
+                order by created_at
+                "#,
+                room_id,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(rows
+                .into_iter()
+                .map(|row| Webhook {
+                    id: row.id,
+                    url: row.url,
+                    events: parse_events(row.events),
+                    created_at: row.created_at,
+                })
+                .collect())
+        })
+    }
+
+    fn delete(&self, room_id: Uuid, id: i64) -> StorageFuture<'_, bool> {
+        Box::pin(async move {
+            let result = sqlx::query!(
+                r#"
+                update room_webhooks set deleted_at = now()
+                where id = $1 and room_id = $2 and deleted_at is null
+                "#,
+                id,
+                room_id,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn list_deliveries(
+        &self,
+        room_id: Uuid,
+        webhook_id: i64,
+    ) -> StorageFuture<'_, Vec<WebhookDelivery>> {
+        Box::pin(async move {
+            let deliveries = sqlx::query_as!(
+                WebhookDelivery,
+                r#"
+                select d.id::bigint as "id!", d.delivery_id, d.event, d.attempt, d.status_code,
+                    d.error, d.succeeded, d.attempted_at::timestamptz as "attempted_at!"
+                from webhook_deliveries d
+                join room_webhooks w on w.id = d.webhook_id
+                where d.webhook_id = $1 and w.room_id = $2
+                order by d.id desc
+                limit 100
+                "#,
+                webhook_id,
+                room_id,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(deliveries)
+        })
+    }
+
+    fn targets(&self, room_id: Uuid, kind: EventKind) -> StorageFuture<'_, Vec<Target>> {
+        Box::pin(async move {
+            let targets = sqlx::query_as!(
+                Target,
+                r#"
+                select id::bigint as "id!", url, secret from room_webhooks
+                where room_id = $1 and $2 = any(events) and deleted_at is null
+                "#,
+                room_id,
+                kind.as_str(),
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(targets)
+        })
+    }
+
+    fn record_attempt<'a>(
+        &'a self,
+        webhook_id: i64,
+        delivery_id: Uuid,
+        kind: EventKind,
+        attempt: u32,
+        status_code: Option<u16>,
+        error: Option<&'a str>,
+    ) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            sqlx::query!(
+                r#"
+                insert into webhook_deliveries(webhook_id, delivery_id, event, attempt, status_code, error, succeeded)
+                values($1, $2, $3, $4, $5, $6, $7)
+                "#,
+                webhook_id,
+                delivery_id,
+                kind.as_str(),
+                attempt as i32,
+                status_code.map(i32::from),
+                error,
+                error.is_none(),
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(())
+        })
+    }
+}
+
+fn parse_events(events: Vec<String>) -> Vec<EventKind> {
+    events
+        .iter()
+        .filter_map(|event| EventKind::parse(event))
+        .collect()
+}
+
+pub struct IncomingWebhookRepo {
+    pool: Pool<Postgres>,
+}
+
+impl IncomingWebhookRepo {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        IncomingWebhookRepo { pool }
+    }
+}
+
+impl IncomingWebhookStore for IncomingWebhookRepo {
+    fn create<'a>(
+        &'a self,
+        room_id: Uuid,
+        owner_id: i32,
+        name: &'a str,
+    ) -> StorageFuture<'a, (IncomingWebhook, String)> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            let account = format!("hook:{}", Uuid::new_v4().simple());
+            let (user_id, _) = insert_bot_user(&mut tx, &account, name, owner_id).await?;
+            let token = incoming::generate_token();
+
+            let row = sqlx::query!(
+                r#"
+                insert into incoming_webhooks(room_id, user_id, name, token_hash)
+                values($1, $2, $3, $4)
+                returning id::bigint as "id!", created_at::timestamptz as "created_at!"
+                "#,
+                room_id,
+                user_id,
+                name,
+                hash_token(&token),
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+
+            let incoming_webhook = IncomingWebhook {
+                id: row.id,
+                name: name.to_string(),
+                user_id,
+                created_at: row.created_at,
+                last_used_at: None,
+            };
+
+            Ok((incoming_webhook, token))
+        })
+    }
+
+    fn list(&self, room_id: Uuid) -> StorageFuture<'_, Vec<IncomingWebhook>> {
+        Box::pin(async move {
+            let incoming_webhooks = sqlx::query_as!(
+                IncomingWebhook,
+                r#"
+                select id::bigint as "id!", name, user_id,
+                    created_at::timestamptz as "created_at!", last_used_at::timestamptz
+                from incoming_webhooks
+                where room_id = $1 and deleted_at is null
+                order by created_at
+                "#,
+                room_id,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+
+            Ok(incoming_webhooks)
+        })
+    }
+
+    fn delete(&self, room_id: Uuid, id: i64) -> StorageFuture<'_, bool> {
+        Box::pin(async move {
+            let result = sqlx::query!(
+                r#"
+                update incoming_webhooks set deleted_at = now()
+                where id = $1 and room_id = $2 and deleted_at is null
+                "#,
+                id,
+                room_id,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn authenticate<'a>(&'a self, token: &'a str) -> StorageFuture<'a, Option<Integration>> {
+        Box::pin(async move {
+            if !incoming::has_prefix(token) {
+                return Ok(None);
+            }
+
+            let integration = sqlx::query_as!(
+                Integration,
+                r#"
+                update incoming_webhooks set last_used_at = now()
+                where token_hash = $1 and deleted_at is null
+                returning room_id, user_id, name
+                "#,
+                hash_token(token),
+            )
+            .fetch_optional(&self.pool)
+            .await?;
+
+            Ok(integration)
+        })
+    }
+}
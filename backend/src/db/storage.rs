@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use std::{error::Error, fmt, net::IpAddr};
+use uuid::Uuid;
+
+use super::SchemaError;
+use crate::{
+    api_token::{ApiToken, Scope, TokenOwner},
+    bot::Bot,
+    webhook::{
+        EventKind, Target, Webhook, WebhookDelivery,
+        incoming::{IncomingWebhook, Integration},
+    },
+};
+
+pub type StorageFuture<'a, T> = BoxFuture<'a, Result<T, StorageError>>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    // a unique name or token is already taken
+    Conflict,
+    // the row a call needs does not exist
+    NotFound,
+    // connection trouble, timeouts and serialization conflicts that may pass on a retry
+    Unavailable(Box<dyn Error + Send + Sync>),
+    Backend(Box<dyn Error + Send + Sync>),
+}
+
+impl StorageError {
+    pub fn backend(message: &str) -> StorageError {
+        StorageError::Backend(message.into())
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, StorageError::Unavailable(_))
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Conflict => write!(f, "Row already exists"),
+            StorageError::NotFound => write!(f, "Row not found"),
+            StorageError::Unavailable(err) => write!(f, "Storage unavailable: {}", err),
+            StorageError::Backend(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+// who a username and password belong to
+pub struct Credentials {
+    pub user_id: i32,
+    pub username: String,
+    pub account_id: i32,
+    pub totp_enabled: bool,
+}
+
+pub struct TotpSecret {
+    pub account_id: i32,
+    pub secret: String,
+}
+
+pub struct NewMessage {
+    pub room_id: Uuid,
+    pub user_id: Option<i32>,
+    pub content: Option<String>,
+}
+
+// everything the server keeps outside of memory, `Db` hands it to handlers, rooms and workers
+pub trait Storage: Send + Sync {
+    fn accounts(&self) -> &dyn AccountStore;
+    fn bots(&self) -> &dyn BotStore;
+    fn incoming_webhooks(&self) -> &dyn IncomingWebhookStore;
+    fn messages(&self) -> &dyn MessageStore;
+    fn rooms(&self) -> &dyn RoomStore;
+    fn tokens(&self) -> &dyn TokenStore;
+    fn totp(&self) -> &dyn TotpStore;
+    fn webhooks(&self) -> &dyn WebhookStore;
+
+    fn migrate(&self) -> BoxFuture<'_, Result<(), SchemaError>>;
+    // refuses a schema that is behind, ahead of or different from this build
+    fn check_schema(&self) -> BoxFuture<'_, Result<(), SchemaError>>;
+    // round trip for the readiness probe
+    fn ping(&self) -> StorageFuture<'_, ()>;
+}
+
+pub trait AccountStore: Send + Sync {
+    // the account name doubles as the username, a taken one is a `Conflict`
+    fn create<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StorageFuture<'a, (i32, String)>;
+    // bots never log in with a password, a wrong one is `NotFound`
+    fn find_by_credentials<'a>(
+        &'a self,
+        account: &'a str,
+        password: &'a str,
+    ) -> StorageFuture<'a, Credentials>;
+    fn record_login_attempt<'a>(
+        &'a self,
+        account: &'a str,
+        ip: IpAddr,
+        outcome: &'a str,
+    ) -> StorageFuture<'a, ()>;
+    // id of the user with this username in any letter case, nicks may not take it
+    fn find_username<'a>(&'a self, username: &'a str) -> StorageFuture<'a, Option<i32>>;
+}
+
+pub trait BotStore: Send + Sync {
+    // bots only authenticate with the API token returned here
+    fn create<'a>(&'a self, owner_id: i32, name: &'a str) -> StorageFuture<'a, (Bot, String)>;
+    fn list(&self, owner_id: i32) -> StorageFuture<'_, Vec<Bot>>;
+    // only the owner's bots, anyone else's are not found
+    fn find(&self, owner_id: i32, bot_id: i32) -> StorageFuture<'_, Option<Bot>>;
+}
+
+pub trait IncomingWebhookStore: Send + Sync {
+    // every hook posts as its own bot user owned by the room owner, so messages keep a sender
+    fn create<'a>(
+        &'a self,
+        room_id: Uuid,
+        owner_id: i32,
+        name: &'a str,
+    ) -> StorageFuture<'a, (IncomingWebhook, String)>;
+    fn list(&self, room_id: Uuid) -> StorageFuture<'_, Vec<IncomingWebhook>>;
+    fn delete(&self, room_id: Uuid, id: i64) -> StorageFuture<'_, bool>;
+    fn authenticate<'a>(&'a self, token: &'a str) -> StorageFuture<'a, Option<Integration>>;
+}
+
+pub trait MessageStore: Send + Sync {
+    // ids follow the input order so acks can be matched back
+    fn insert_batch<'a>(
+        &'a self,
+        messages: &'a [NewMessage],
+    ) -> StorageFuture<'a, Vec<(i64, DateTime<Utc>)>>;
+}
+
+pub trait RoomStore: Send + Sync {
+    fn create<'a>(&'a self, room_id: Uuid, room_name: &'a str) -> StorageFuture<'a, ()>;
+    fn close(&self, room_id: Uuid) -> StorageFuture<'_, ()>;
+    fn list_open(&self) -> StorageFuture<'_, Vec<(Uuid, String)>>;
+}
+
+pub trait TokenStore: Send + Sync {
+    // tokens are shown once and only their hashes are stored
+    fn create<'a>(
+        &'a self,
+        user_id: i32,
+        name: &'a str,
+        scope: Scope,
+    ) -> StorageFuture<'a, (String, ApiToken)>;
+    fn list(&self, user_id: i32) -> StorageFuture<'_, Vec<ApiToken>>;
+    fn revoke(&self, user_id: i32, id: i64) -> StorageFuture<'_, bool>;
+    fn authenticate<'a>(&'a self, token: &'a str) -> StorageFuture<'a, Option<TokenOwner>>;
+}
+
+pub trait TotpStore: Send + Sync {
+    // a new enrollment replaces a pending one but never an enabled one
+    fn enroll<'a>(&'a self, user_id: i32, secret: &'a str) -> StorageFuture<'a, bool>;
+    // the secret of a user's enrollment, pending or enabled
+    fn find(&self, user_id: i32, enabled: bool) -> StorageFuture<'_, Option<TotpSecret>>;
+    fn enabled_secret(&self, account_id: i32) -> StorageFuture<'_, String>;
+    // enables and replaces the recovery codes at once
+    fn enable<'a>(&'a self, account_id: i32, code_hashes: &'a [String]) -> StorageFuture<'a, ()>;
+    fn disable(&self, account_id: i32) -> StorageFuture<'_, ()>;
+    // records the time step of an accepted code, false when it is not after the last one
+    fn accept_step(&self, account_id: i32, step: u64) -> StorageFuture<'_, bool>;
+    // burns the code, it cannot be used twice
+    fn use_recovery_code<'a>(
+        &'a self,
+        account_id: i32,
+        code_hash: &'a str,
+    ) -> StorageFuture<'a, bool>;
+}
+
+pub trait WebhookStore: Send + Sync {
+    // secrets are shown once on creation but kept in plain text, they are needed to sign
+    fn create<'a>(
+        &'a self,
+        room_id: Uuid,
+        url: &'a str,
+        events: Vec<EventKind>,
+    ) -> StorageFuture<'a, (Webhook, String)>;
+    fn list(&self, room_id: Uuid) -> StorageFuture<'_, Vec<Webhook>>;
+    fn delete(&self, room_id: Uuid, id: i64) -> StorageFuture<'_, bool>;
+    // newest attempts first
+    fn list_deliveries(
+        &self,
+        room_id: Uuid,
+        webhook_id: i64,
+    ) -> StorageFuture<'_, Vec<WebhookDelivery>>;
+    // the webhooks of a room subscribed to an event
+    fn targets(&self, room_id: Uuid, kind: EventKind) -> StorageFuture<'_, Vec<Target>>;
+    fn record_attempt<'a>(
+        &'a self,
+        webhook_id: i64,
+        delivery_id: Uuid,
+        kind: EventKind,
+        attempt: u32,
+        status_code: Option<u16>,
+        error: Option<&'a str>,
+    ) -> StorageFuture<'a, ()>;
+}
@@ -0,0 +1,313 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    sync::mpsc::{self, error::SendError},
+    task::JoinHandle,
+    time::{Instant, sleep},
+};
+use uuid::Uuid;
+
+use super::{Db, NewMessage, StorageError};
+use crate::{
+    config::MessagesConfig,
+    metrics::Metrics,
+    room_manager::{RoomCommand, RoomPublisher},
+};
+
+// message waiting in the queue with the room that publishes it once stored
+struct Queued {
+    command: RoomCommand,
+    publisher: RoomPublisher,
+}
+
+// message checked and waiting in a batch
+struct Pending {
+    room_id: Uuid,
+    command: RoomCommand,
+    publisher: RoomPublisher,
+}
+
+#[derive(Default)]
+struct WriterCounters {
+    stored: AtomicU64,
+    batches: AtomicU64,
+    retries: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WriterStats {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub stored: u64,
+    pub batches: u64,
+    pub retries: u64,
+    pub failed: u64,
+}
+
+// handle to the message writer, senders wait for room in the queue instead of dropping.
+// The queue lives in memory: messages still in it when the process dies are lost, and
+// their senders never get an ack. Making it durable is out of scope, a client that saw
+// no ack should treat the message as not sent.
+#[derive(Clone)]
+pub struct MessageWriter {
+    sender: mpsc::Sender<Queued>,
+    counters: Arc<WriterCounters>,
+}
+
+impl MessageWriter {
+    pub fn spawn(
+        db: Db,
+        config: &MessagesConfig,
+        metrics: Arc<Metrics>,
+    ) -> (MessageWriter, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(config.queue_capacity);
+        let counters = Arc::new(WriterCounters::default());
+        let writer = tokio::spawn(run_writer(
+            db,
+            config.clone(),
+            counters.clone(),
+            metrics,
+            receiver,
+        ));
+
+        tracing::info!("Listening on message...");
+
+        (MessageWriter { sender, counters }, writer)
+    }
+
+    pub async fn send(
+        &self,
+        command: RoomCommand,
+        publisher: RoomPublisher,
+    ) -> Result<(), SendError<RoomCommand>> {
+        self.sender
+            .send(Queued { command, publisher })
+            .await
+            .map_err(|SendError(queued)| SendError(queued.command))
+    }
+
+    // the queue closes when the writer task is gone
+    pub fn is_running(&self) -> bool {
+        !self.sender.is_closed()
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    // for readers that must not keep the writer running, e.g. the metrics listener
+    pub fn probe(&self) -> WriterProbe {
+        WriterProbe {
+            sender: self.sender.downgrade(),
+        }
+    }
+
+    pub fn stats(&self) -> WriterStats {
+        WriterStats {
+            queue_depth: self.queue_depth(),
+            queue_capacity: self.sender.max_capacity(),
+            stored: self.counters.stored.load(Ordering::Relaxed),
+            batches: self.counters.batches.load(Ordering::Relaxed),
+            retries: self.counters.retries.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// queue depth without holding the queue open, reads 0 once the writer is gone
+#[derive(Clone)]
+pub struct WriterProbe {
+    sender: mpsc::WeakSender<Queued>,
+}
+
+impl WriterProbe {
+    pub fn queue_depth(&self) -> usize {
+        self.sender
+            .upgrade()
+            .map_or(0, |sender| sender.max_capacity() - sender.capacity())
+    }
+}
+
+// the writer stops once every sender is dropped and the queue is drained
+async fn run_writer(
+    db: Db,
+    config: MessagesConfig,
+    counters: Arc<WriterCounters>,
+    metrics: Arc<Metrics>,
+    mut receiver: mpsc::Receiver<Queued>,
+) {
+    let mut queued = Vec::with_capacity(config.batch_size);
+
+    while receiver.recv_many(&mut queued, config.batch_size).await > 0 {
+        let mut batch = Vec::with_capacity(queued.len());
+
+        for Queued { command, publisher } in queued.drain(..) {
+            match command.room_id.as_deref().map(Uuid::from_str) {
+                Some(Ok(room_id)) => batch.push(Pending {
+                    room_id,
+                    command,
+                    publisher,
+                }),
+                _ => command.reply_error("BAD_REQUEST", "Invalid room id"),
+            }
+        }
+
+        if batch.is_empty() {
+            continue;
+        }
+
+        counters.batches.fetch_add(1, Ordering::Relaxed);
+
+        match insert_with_retry(&db, &config, &counters, &metrics, &batch).await {
+            Ok(rows) => ack(&counters, &batch, rows).await,
+            // one bad row fails the whole statement, store the rest one by one
+            Err(err) if batch.len() > 1 && !err.is_transient() => {
+                for pending in batch.chunks(1) {
+                    store(&db, &config, &counters, &metrics, pending).await;
+                }
+            }
+            Err(err) => fail(&counters, &batch, &err),
+        }
+    }
+}
+
+async fn store(
+    db: &Db,
+    config: &MessagesConfig,
+    counters: &WriterCounters,
+    metrics: &Metrics,
+    batch: &[Pending],
+) {
+    match insert_with_retry(db, config, counters, metrics, batch).await {
+        Ok(rows) => ack(counters, batch, rows).await,
+        Err(err) => fail(counters, batch, &err),
+    }
+}
+
+// stored messages are broadcast in queue order, then acked
+async fn ack(counters: &WriterCounters, batch: &[Pending], rows: Vec<(i64, DateTime<Utc>)>) {
+    counters
+        .stored
+        .fetch_add(batch.len() as u64, Ordering::Relaxed);
+
+    for (pending, (message_id, sent_at)) in batch.iter().zip(rows) {
+        pending.publisher.publish(&pending.command).await;
+        pending.command.reply_ack(message_id, sent_at);
+    }
+}
+
+fn fail(counters: &WriterCounters, batch: &[Pending], err: &StorageError) {
+    tracing::error!("Failed to insert {} messages: {}", batch.len(), err);
+
+    counters
+        .failed
+        .fetch_add(batch.len() as u64, Ordering::Relaxed);
+
+    for pending in batch {
+        pending
+            .command
+            .reply_error("NOT_STORED", "Message could not be stored");
+    }
+}
+
+// transient errors are retried with a doubling delay, the rest fail right away
+async fn insert_with_retry(
+    db: &Db,
+    config: &MessagesConfig,
+    counters: &WriterCounters,
+    metrics: &Metrics,
+    batch: &[Pending],
+) -> Result<Vec<(i64, DateTime<Utc>)>, StorageError> {
+    let messages: Vec<NewMessage> = batch
+        .iter()
+        .map(|pending| NewMessage {
+            room_id: pending.room_id,
+            user_id: pending.command.user_id,
+            content: pending.command.message.clone(),
+        })
+        .collect();
+    let mut attempt = 1;
+
+    loop {
+        let start = Instant::now();
+        let result = db.messages().insert_batch(&messages).await;
+        metrics.insert_finished(start.elapsed());
+
+        match result {
+            Err(err) if err.is_transient() && attempt < config.max_attempts => {
+                tracing::warn!("Retrying {} messages: {}", batch.len(), err);
+
+                counters.retries.fetch_add(1, Ordering::Relaxed);
+                sleep(config.retry_delay() * 2u32.saturating_pow(attempt - 1)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::WebhookConfig,
+        db::MemoryStorage,
+        room_manager::{Reply, RoomHistory},
+        webhook::WebhookDispatcher,
+    };
+    use tokio::sync::{Mutex, broadcast};
+
+    #[tokio::test]
+    async fn only_stored_messages_are_broadcast() {
+        let db: Db = Arc::new(MemoryStorage::new());
+        let room_id = Uuid::new_v4();
+        db.rooms().create(room_id, "lobby").await.unwrap();
+
+        let metrics = Metrics::build();
+        let (writer, _task) =
+            MessageWriter::spawn(db.clone(), &MessagesConfig::default(), metrics.clone());
+        let (subscriber_sender, mut subscriber) = broadcast::channel(8);
+        let history = Arc::new(Mutex::new(RoomHistory::new(8)));
+        let publisher = RoomPublisher {
+            room_id,
+            history: history.clone(),
+            subscriber_sender,
+            webhooks: WebhookDispatcher::build(db, WebhookConfig::default()),
+            metrics,
+        };
+        let (reply_sender, mut replies) = mpsc::channel(8);
+        let send = |room_id: Uuid, message: &str, request_id: &str| {
+            RoomCommand::send(1, "alice".into(), room_id.to_string(), message.into())
+                .with_reply(Some(request_id.into()), reply_sender.clone())
+        };
+
+        // the memory storage refuses messages for rooms it does not know
+        writer
+            .send(send(Uuid::new_v4(), "lost", "1"), publisher.clone())
+            .await
+            .unwrap();
+        match replies.recv().await.unwrap() {
+            Reply::Error { code, .. } => assert_eq!(code, "NOT_STORED"),
+            reply => panic!("expected NOT_STORED, got {:?}", reply),
+        }
+        assert!(subscriber.try_recv().is_err());
+        assert_eq!(history.lock().await.last_seq(), 0);
+
+        writer
+            .send(send(room_id, "kept", "2"), publisher)
+            .await
+            .unwrap();
+        assert!(matches!(replies.recv().await.unwrap(), Reply::Ack { .. }));
+        let event = subscriber.try_recv().unwrap();
+        assert_eq!(event.message.as_deref(), Some("kept"));
+        assert_eq!(event.seq, Some(1));
+    }
+}
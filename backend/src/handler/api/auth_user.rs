@@ -0,0 +1,199 @@
+use axum::{
+    Json,
+    extract::{FromRequestParts, Request},
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::CookieJar;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    api_token::Scope,
+    handler::api::ApiResponse,
+    room_manager::RoomManager,
+    router::AppState,
+    session::{SessionInfo, SessionManager, SessionWatch},
+};
+
+// the signed in user behind a request, resolved from the session cookie or an API token
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: i32,
+    pub username: String,
+    // bots only authenticate with API tokens
+    pub is_bot: bool,
+    // sessions always have the admin scope
+    pub scope: Scope,
+    pub token_id: Option<i64>,
+    pub session_id: Option<String>,
+    pub session: Option<SessionInfo>,
+}
+
+impl AuthUser {
+    // id and name as shown to a room, bots are told apart by `is_bot` rather than their name
+    pub fn user(&self) -> (i32, String) {
+        (self.user_id, self.username.clone())
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), (StatusCode, Json<ApiResponse<()>>)> {
+        if self.scope.allows(scope) {
+            return Ok(());
+        }
+
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(
+                "INSUFFICIENT_SCOPE",
+                &format!("This operation needs the {} scope", scope.as_str()),
+            )),
+        ))
+    }
+
+    // bots only enter rooms their owner added them to
+    pub async fn require_room(
+        &self,
+        room_manager: &Arc<RoomManager>,
+        room_id: &str,
+    ) -> Result<(), (StatusCode, Json<ApiResponse<()>>)> {
+        if !self.is_bot || room_manager.clone().has_bot(room_id, self.user_id).await {
+            return Ok(());
+        }
+
+        Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(
+                "NOT_INVITED",
+                "The bot was not added to this room",
+            )),
+        ))
+    }
+
+    // id of a live room the user created, `action` completes "Only the room owner can ..."
+    pub async fn require_owner(
+        &self,
+        room_manager: &Arc<RoomManager>,
+        room_id: &str,
+        action: &str,
+    ) -> Result<Uuid, (StatusCode, Json<ApiResponse<()>>)> {
+        let (Some((owner_id, _limits)), Ok(room_uuid)) = (
+            room_manager.clone().settings(room_id).await,
+            Uuid::parse_str(room_id),
+        ) else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+            ));
+        };
+
+        if owner_id != self.user_id {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::<()>::error(
+                    "FORBIDDEN",
+                    &format!("Only the room owner can {}", action),
+                )),
+            ));
+        }
+
+        Ok(room_uuid)
+    }
+
+    // sockets of a session close with it, token sockets close when their token is revoked
+    pub async fn watch(&self, session_manager: &Arc<SessionManager>) -> Option<SessionWatch> {
+        match (&self.session_id, self.token_id) {
+            (Some(session_id), _) => session_manager.watch(session_id).await,
+            (None, Some(token_id)) => Some(
+                session_manager
+                    .watch_token(token_id, self.user(), self.is_bot, self.scope)
+                    .await,
+            ),
+            (None, None) => None,
+        }
+    }
+}
+
+fn unauthorized() -> (StatusCode, Json<ApiResponse<()>>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(ApiResponse::<()>::unauthorized()),
+    )
+}
+
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = (StatusCode, Json<ApiResponse<()>>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        // already resolved by require_auth
+        if let Some(auth_user) = parts.extensions.get::<AuthUser>() {
+            return Ok(auth_user.clone());
+        }
+
+        // scripts and bots send a personal API token instead of the cookie
+        if let Some(authorization) = parts.headers.get(AUTHORIZATION) {
+            let token = authorization
+                .to_str()
+                .ok()
+                .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                .ok_or_else(unauthorized)?;
+
+            let token_owner = app_state
+                .db
+                .tokens()
+                .authenticate(token)
+                .await
+                .map_err(|err| {
+                    tracing::error!("Failed to check api token: {}", err);
+
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+                    )
+                })?
+                .ok_or_else(unauthorized)?;
+
+            return Ok(AuthUser {
+                user_id: token_owner.user_id,
+                username: token_owner.username,
+                is_bot: token_owner.is_bot,
+                scope: token_owner.scope,
+                token_id: Some(token_owner.token_id),
+                session_id: None,
+                session: None,
+            });
+        }
+
+        let jar = CookieJar::from_headers(&parts.headers);
+        let session_id = jar
+            .get("session_id")
+            .map(|cookie| cookie.value().to_string())
+            .ok_or_else(unauthorized)?;
+
+        let (user_id, username, session) = app_state
+            .session_manager
+            .authenticate(&session_id)
+            .await
+            .ok_or_else(unauthorized)?;
+
+        Ok(AuthUser {
+            user_id,
+            username,
+            is_bot: false,
+            scope: Scope::Admin,
+            token_id: None,
+            session_id: Some(session_id),
+            session: Some(session),
+        })
+    }
+}
+
+// route layer, rejects the request before any handler runs
+pub async fn require_auth(auth_user: AuthUser, mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(auth_user);
+
+    next.run(request).await
+}
@@ -0,0 +1,263 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use std::sync::Arc;
+
+use crate::{
+    api_token::Scope,
+    db::{StorageError, TotpSecret},
+    handler::api::{ApiResponse, AuthUser, post::TotpCode},
+    room_manager::RoomCommand,
+    router::AppState,
+    two_factor,
+};
+
+pub async fn disable_totp(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(totp_code): Json<TotpCode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let internal_error = |err: StorageError| {
+        tracing::error!("Failed to disable totp: {}", err);
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+        )
+    };
+
+    let Some(TotpSecret { account_id, secret }) = app_state
+        .db
+        .totp()
+        .find(auth_user.user_id, true)
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "TOTP_NOT_ENABLED",
+                "Two-factor authentication is not enabled",
+            )),
+        ));
+    };
+
+    // disabling needs the second factor too
+    let verified =
+        two_factor::verify_second_factor(&app_state.db, account_id, &secret, &totp_code.code)
+            .await
+            .map_err(internal_error)?;
+
+    if !verified {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error(
+                "INVALID_TOTP",
+                "The authentication code is incorrect",
+            )),
+        ));
+    }
+
+    app_state
+        .db
+        .totp()
+        .disable(account_id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(ApiResponse::<()>::success(
+        "Two-factor authentication disabled",
+    )))
+}
+
+pub async fn revoke_session(
+    Path(id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    if app_state
+        .session_manager
+        .revoke_session(auth_user.user_id, &id)
+        .await
+    {
+        Ok(Json(ApiResponse::<()>::success("Session revoked")))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Session not found")),
+        ))
+    }
+}
+
+// log out everywhere, including the current session
+pub async fn revoke_all_sessions(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let revoked = app_state
+        .session_manager
+        .revoke_all_sessions(auth_user.user_id)
+        .await;
+
+    Ok(Json(ApiResponse::<()>::success(&format!(
+        "{} sessions revoked",
+        revoked
+    ))))
+}
+
+pub async fn revoke_token(
+    Path(id): Path<i64>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let revoked = app_state
+        .db
+        .tokens()
+        .revoke(auth_user.user_id, id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to revoke api token: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    if revoked {
+        app_state.session_manager.revoke_token(id).await;
+
+        Ok(Json(ApiResponse::<()>::success("Token revoked")))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Token not found")),
+        ))
+    }
+}
+
+// the bot can no longer join, connections it already has are kicked
+pub async fn remove_room_bot(
+    Path((room_id, bot_id)): Path<(String, i32)>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_manager = app_state.room_manager.clone();
+    let (Some((owner_id, _limits)), Some(bots)) = (
+        room_manager.clone().settings(&room_id).await,
+        room_manager.clone().bots(&room_id).await,
+    ) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+        ));
+    };
+
+    if owner_id != auth_user.user_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(
+                "FORBIDDEN",
+                "Only the room owner can remove bots",
+            )),
+        ));
+    }
+
+    if bots.lock().await.remove(&bot_id) {
+        if let Some((channel_sender, _broadcast_receiver)) = room_manager.join(&room_id).await {
+            let _ = channel_sender.send(RoomCommand::kick(bot_id)).await;
+        }
+
+        Ok(Json(ApiResponse::<()>::success(
+            "Bot removed from the room",
+        )))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Bot not found")),
+        ))
+    }
+}
+
+pub async fn delete_webhook(
+    Path((room_id, id)): Path<(String, i64)>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let deleted = app_state
+        .db
+        .webhooks()
+        .delete(room_id, id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to delete webhook: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    if deleted {
+        Ok(Json(ApiResponse::<()>::success("Webhook deleted")))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Webhook not found")),
+        ))
+    }
+}
+
+pub async fn delete_incoming_webhook(
+    Path((room_id, id)): Path<(String, i64)>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let deleted = app_state
+        .db
+        .incoming_webhooks()
+        .delete(room_id, id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to delete incoming webhook: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    if deleted {
+        Ok(Json(ApiResponse::<()>::success("Webhook deleted")))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Webhook not found")),
+        ))
+    }
+}
@@ -1,25 +1,47 @@
 use axum::{
-    Extension, Json,
+    Json,
     extract::{
-        Query, State,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
     },
-    http::StatusCode,
-    response::IntoResponse,
 };
 use axum_extra::extract::CookieJar;
 use futures_util::{
     SinkExt,
-    stream::{SplitStream, StreamExt},
+    stream::{self, SplitStream, StreamExt},
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        Mutex,
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+    time::{Instant, timeout},
 };
-use sqlx::Row;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{broadcast, mpsc};
 
 use crate::{
-    handler::api::{ApiResponse, Room, StreamCommand, StreamMethod},
-    room_manager::{self, RoomCommand},
+    api_token::{ApiToken, Scope},
+    bot::Bot,
+    db::WriterStats,
+    handler::api::{ApiResponse, AuthUser, PolledEvent, PolledEvents, Room},
+    metrics::Metrics,
+    protocol::{Protocol, SUPPORTED_PROTOCOLS, ServerEvent, v2::ServerFrame},
+    rate_limit::{MAX_SLOW_MODE_SECS, RoomLimits, TokenBucket, Violation},
+    room_manager::{Method, Reply, RoomCommand},
     router::AppState,
+    session::{SessionEnd, SessionInfo, SessionWatch},
+    webhook::{Webhook, WebhookDelivery, incoming::IncomingWebhook},
 };
 
 pub async fn logout(
@@ -37,56 +59,32 @@ pub async fn logout(
     Err((StatusCode::UNAUTHORIZED, Json(ApiResponse::unauthorized())))
 }
 
-pub async fn auth(
-    State(app_state): State<Arc<AppState>>,
-    jar: CookieJar,
-) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
-    if let Some(session_cookie) = jar.get("session_id") {
-        let session_id = session_cookie.value();
-
-        match app_state.session_manager.check_session(session_id).await {
-            Some(_) => Ok(Json(ApiResponse::<()>::success("Authorized"))),
-            None => Err((
-                StatusCode::UNAUTHORIZED,
-                Json(ApiResponse::<()>::unauthorized()),
-            )),
-        }
-    } else {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(ApiResponse::<()>::error(
-                "Unauthorized",
-                "Invalid Session ID Header",
-            )),
-        ));
+pub async fn auth(auth_user: AuthUser) -> impl IntoResponse {
+    match auth_user.session {
+        Some(session) => Json(ApiResponse::<SessionInfo>::success_with_data(
+            "Authorized",
+            session,
+        )),
+        None => Json(ApiResponse::<SessionInfo>::success("Authorized")),
     }
 }
 
 pub async fn create_room(
     ws: WebSocketUpgrade,
     Query(params): Query<HashMap<String, String>>,
-    jar: CookieJar,
-    Extension(db_message_sender): Extension<mpsc::Sender<RoomCommand>>,
+    auth_user: AuthUser,
     State(app_state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
-    // check auth
-    let user = if let Some(session_cookie) = jar.get("session_id") {
-        let session_id = session_cookie.value();
-
-        match app_state.session_manager.check_session(session_id).await {
-            Some((id, username)) => Some((id, username)),
-            None => None,
-        }
-    } else {
-        None
-    };
+    // the socket both reads and sends
+    auth_user.require(Scope::Chat)?;
 
-    if user.is_none() {
+    // the socket lives only as long as its session
+    let Some(session_watch) = auth_user.watch(&app_state.session_manager).await else {
         return Err((
             StatusCode::UNAUTHORIZED,
             Json(ApiResponse::<()>::unauthorized()),
         ));
-    }
+    };
 
     // check params
     let room_name = match params.get("room_name") {
@@ -102,18 +100,45 @@ pub async fn create_room(
         }
     };
 
-    let user = user.unwrap();
     let room_manager = app_state.room_manager.clone();
 
+    let mut limits = room_manager.config.limits.clone();
+    if let Some(slow_mode_secs) = params.get("slow_mode").and_then(|secs| secs.parse::<u64>().ok()) {
+        limits.slow_mode_secs = slow_mode_secs.min(MAX_SLOW_MODE_SECS);
+    }
+
     // create room
     match room_manager
-        .create(app_state.pool.clone(), &room_name, db_message_sender)
+        .clone()
+        .create(
+            app_state.db.clone(),
+            &room_name,
+            auth_user.user_id,
+            limits,
+            app_state.message_writer.clone(),
+        )
         .await
     {
         Ok((channel_sender, broadcast_receiver, room_id)) => {
+            let Some((_owner_id, limits)) = room_manager.settings(&room_id).await else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+                ));
+            };
+
             // upgrade
-            Ok(ws.on_upgrade(|stream| {
-                handle_ws(user, room_id, stream, channel_sender, broadcast_receiver)
+            let metrics = app_state.metrics.clone();
+            Ok(ws.protocols(SUPPORTED_PROTOCOLS).on_upgrade(|stream| {
+                handle_ws(
+                    room_id,
+                    stream,
+                    channel_sender,
+                    broadcast_receiver,
+                    limits,
+                    session_watch,
+                    metrics,
+                )
             }))
         }
         Err(_) => Err((
@@ -129,27 +154,19 @@ pub async fn create_room(
 pub async fn join_room(
     ws: WebSocketUpgrade,
     Query(params): Query<HashMap<String, String>>,
-    jar: CookieJar,
+    auth_user: AuthUser,
     State(app_state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
-    // check auth
-    let user = if let Some(session_cookie) = jar.get("session_id") {
-        let session_id = session_cookie.value();
+    // read-only tokens may follow a room, sends are checked per frame
+    auth_user.require(Scope::Read)?;
 
-        match app_state.session_manager.check_session(session_id).await {
-            Some((id, username)) => Some((id, username)),
-            None => None,
-        }
-    } else {
-        None
-    };
-
-    if user.is_none() {
+    // the socket lives only as long as its session
+    let Some(session_watch) = auth_user.watch(&app_state.session_manager).await else {
         return Err((
             StatusCode::UNAUTHORIZED,
             Json(ApiResponse::<()>::unauthorized()),
         ));
-    }
+    };
 
     // check params
     let room_id = match params.get("room_id") {
@@ -165,14 +182,28 @@ pub async fn join_room(
         }
     };
 
-    let user = user.unwrap();
     let room_manager = app_state.room_manager.clone();
-
-    match room_manager.join(&room_id).await {
-        Some((channel_sender, broadcast_receiver)) => Ok(ws.on_upgrade(|stream| {
-            handle_ws(user, room_id, stream, channel_sender, broadcast_receiver)
-        })),
-        None => Err((
+    auth_user.require_room(&room_manager, &room_id).await?;
+
+    match (
+        room_manager.clone().join(&room_id).await,
+        room_manager.settings(&room_id).await,
+    ) {
+        (Some((channel_sender, broadcast_receiver)), Some((_owner_id, limits))) => {
+            let metrics = app_state.metrics.clone();
+            Ok(ws.protocols(SUPPORTED_PROTOCOLS).on_upgrade(|stream| {
+                handle_ws(
+                    room_id,
+                    stream,
+                    channel_sender,
+                    broadcast_receiver,
+                    limits,
+                    session_watch,
+                    metrics,
+                )
+            }))
+        }
+        _ => Err((
             StatusCode::BAD_REQUEST,
             Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
         )),
@@ -180,57 +211,83 @@ pub async fn join_room(
 }
 
 async fn handle_ws(
-    user: (i32, String),
     room_id: String,
     stream: WebSocket,
     channel_sender: mpsc::Sender<RoomCommand>,
     mut broadcast_receiver: broadcast::Receiver<RoomCommand>,
+    limits: Arc<Mutex<RoomLimits>>,
+    session_watch: SessionWatch,
+    metrics: Arc<Metrics>,
 ) {
+    let _connected = metrics.socket_connected();
     let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
+    let (reply_sender, mut reply_receiver) = mpsc::channel::<Reply>(32);
+    let protocol = Protocol::from_selected(stream.protocol());
     let (mut stream_sender, stream_receiver) = stream.split();
-    let user = user.clone();
+    let user = session_watch.user().clone();
+    let mut session_end = session_watch.clone();
 
-    // listening room broadcast
+    // listening room broadcast and replies for this connection
     tokio::spawn(async move {
-        while let Ok(command) = broadcast_receiver.recv().await {
-            match command.method {
-                room_manager::Method::Join => {
-                    let stream_command = StreamCommand::join(command.user.unwrap());
-
-                    if let Err(err) = stream_sender.send(Message::text(stream_command)).await {
-                        //TODO: handle error
-                        eprintln!("Error on join: {}", err.to_string());
-                    }
-                }
-                room_manager::Method::Send => {
-                    let stream_command = if user.0 == command.user_id.unwrap() {
-                        StreamCommand::send_by_self(command.user.unwrap(), command.message.unwrap())
-                    } else {
-                        StreamCommand::send_by_others(
-                            command.user.unwrap(),
-                            command.message.unwrap(),
-                        )
+        loop {
+            let event = tokio::select! {
+                result = broadcast_receiver.recv() => {
+                    let command = match result {
+                        Ok(command) => command,
+                        Err(err) => {
+                            if let RecvError::Lagged(_) = err {
+                                metrics.broadcast_lagged();
+                            }
+
+                            break;
+                        }
                     };
 
-                    if let Err(err) = stream_sender.send(Message::text(stream_command)).await {
-                        eprintln!("Error on send message: {}", err.to_string());
-                    }
-                }
-                room_manager::Method::Leave => {
-                    let stream_command = StreamCommand::leave(command.user.unwrap());
+                    // room closed or this user was kicked
+                    if command.ends_connection(user.0) {
+                        let close_frame = matches!(command.method, Method::Close).then(|| CloseFrame {
+                            code: close_code::AWAY,
+                            reason: command.message.clone().unwrap_or_default().into(),
+                        });
 
-                    //TODO: handle error correct
-                    if let Err(err) = stream_sender.send(Message::text(stream_command)).await {
-                        println!("Error on leave: {}", err.to_string());
+                        if let Some(event) = ServerEvent::from_room_command(command, user.0) {
+                            let _ = stream_sender.send(protocol.encode(event)).await;
+                        }
+                        if let Some(close_frame) = close_frame {
+                            let _ = stream_sender.send(Message::Close(Some(close_frame))).await;
+                        }
+                        let _ = shutdown_sender.send(()).await;
 
                         break;
                     }
+
+                    match ServerEvent::from_room_command(command, user.0) {
+                        Some(event) => event,
+                        None => continue,
+                    }
                 }
-                room_manager::Method::Close => {
-                    let _ = shutdown_sender.send(());
+                Some(reply) = reply_receiver.recv() => ServerEvent::from(reply),
+                end = session_end.ended() => {
+                    // logged out, revoked or expired, or the server is going away
+                    let close_frame = CloseFrame {
+                        code: match end {
+                            SessionEnd::Shutdown => close_code::AWAY,
+                            _ => close_code::POLICY,
+                        },
+                        reason: end.reason().into(),
+                    };
+
+                    let _ = stream_sender.send(Message::Close(Some(close_frame))).await;
+                    let _ = shutdown_sender.send(()).await;
 
                     break;
                 }
+            };
+
+            if let Err(err) = stream_sender.send(protocol.encode(event)).await {
+                tracing::error!("Error on send stream: {}", err);
+
+                break;
             }
         }
     });
@@ -239,51 +296,69 @@ async fn handle_ws(
         _ = shutdown_receiver.recv() => {}
         _ = async {
             // read message from client
-            handle_stream_receiver(stream_receiver, channel_sender, room_id, user).await;
+            handle_stream_receiver(stream_receiver, protocol, channel_sender, reply_sender, room_id, session_watch, limits).await;
         } => {}
     }
 }
 
 async fn handle_stream_receiver(
     mut stream_receiver: SplitStream<WebSocket>,
+    protocol: Protocol,
     channel_sender: mpsc::Sender<RoomCommand>,
+    reply_sender: mpsc::Sender<Reply>,
     room_id: String,
-    user: (i32, String),
+    session_watch: SessionWatch,
+    limits: Arc<Mutex<RoomLimits>>,
 ) {
+    let user = session_watch.user().clone();
+    let mut bucket = TokenBucket::new(limits.lock().await.connection_burst);
+
     while let Some(message_result) = stream_receiver.next().await {
         match message_result {
-            Ok(Message::Text(text)) => {
-                //parse StreamCommand and send RoomCommand to room;
-                if let Ok(stream_commnad) = Json::<StreamCommand>::from_bytes(text.as_bytes()) {
-                    match stream_commnad.method {
-                        StreamMethod::Join => {
-                            let room_command = RoomCommand::join(user.1.clone());
-
-                            if let Err(_err) = channel_sender.send(room_command).await {
-                                break;
-                            };
-                        }
-                        StreamMethod::Send => {
-                            let room_command = RoomCommand::send(
-                                user.0,
-                                user.1.clone(),
-                                room_id.clone(),
-                                stream_commnad.message.clone(),
-                            );
-
-                            if let Err(_err) = channel_sender.send(room_command).await {
-                                break;
-                            };
-                        }
-                        _ => {}
+            Ok(message @ (Message::Text(_) | Message::Binary(_))) => {
+                session_watch.touch().await;
+
+                //parse client frame and send RoomCommand to room;
+                let room_command = match protocol.decode(&message) {
+                    Some(event) => event
+                        .into_room_command(&user, &room_id, reply_sender.clone())
+                        .by_bot(session_watch.is_bot()),
+                    None => continue,
+                };
+
+                // per-connection limit, user and room limits are applied by the room task
+                if let Method::Send = room_command.method {
+                    if !session_watch.scope().allows(Scope::Send) {
+                        room_command.reply_error(
+                            "INSUFFICIENT_SCOPE",
+                            "This operation needs the send scope",
+                        );
+                        continue;
+                    }
+
+                    let limits = limits.lock().await;
+
+                    if let Err(retry) = bucket.try_take(
+                        limits.connection_burst,
+                        limits.connection_per_minute,
+                        Instant::now(),
+                    ) {
+                        let violation = Violation::RateLimited(retry);
+
+                        room_command.reply_error(violation.code(), &violation.message());
+                        continue;
                     }
+                }
+
+                if let Err(_err) = channel_sender.send(room_command).await {
+                    break;
                 };
             }
             Ok(Message::Close(_frame)) => {
                 break;
             }
             Ok(_) => {
-                unimplemented!();
+                // ping and pong are answered by axum
             }
             Err(_) => {
                 break;
@@ -292,58 +367,461 @@ async fn handle_stream_receiver(
     }
 
     // send leave message
-    let _ = channel_sender.send(RoomCommand::leave(user.1)).await;
+    let _ = channel_sender
+        .send(RoomCommand::leave(user.0, user.1).by_bot(session_watch.is_bot()))
+        .await;
 }
 
 pub async fn rooms(
-    jar: CookieJar,
+    auth_user: AuthUser,
     State(app_state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
-    // check auth
-    if let Some(session_cookie) = jar.get("session_id") {
-        let session_id = session_cookie.value();
+    auth_user.require(Scope::Read)?;
 
-        match app_state.session_manager.check_session(session_id).await {
-            Some(_) => {
-                let query_str = r#"
-                    select id, room_name from rooms
-                    where closed_at is null;
-                "#;
-
-                let rows = sqlx::query(query_str)
-                    .fetch_all(&app_state.pool)
-                    .await
-                    .map_err(|err| {
-                        tracing::error!("Failed to fetch rooms: {:?}", err);
-
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Json(ApiResponse::<()>::error(
-                                "INTERNAL_SERVER_ERROR",
-                                "Failed to fetch rooms",
-                            )),
-                        )
-                    })?;
-
-                let rooms: Vec<Room> = rows
-                    .into_iter()
-                    .map(|row| Room::new(row.get(0), row.get(1)))
-                    .collect();
-
-                return Ok(Json(ApiResponse::<Vec<Room>>::success_with_data("", rooms)));
-            }
-            None => Err((
-                StatusCode::UNAUTHORIZED,
-                Json(ApiResponse::<()>::unauthorized()),
+    let rooms = app_state.db.rooms().list_open().await.map_err(|err| {
+        tracing::error!("Failed to fetch rooms: {:?}", err);
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::<()>::error(
+                "INTERNAL_SERVER_ERROR",
+                "Failed to fetch rooms",
             )),
+        )
+    })?;
+
+    let rooms: Vec<Room> = rooms
+        .into_iter()
+        .map(|(room_id, room_name)| Room::new(room_id, room_name))
+        .collect();
+
+    Ok(Json(ApiResponse::<Vec<Room>>::success_with_data("", rooms)))
+}
+
+pub async fn room_events(
+    Path(room_id): Path<String>,
+    headers: HeaderMap,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Read)?;
+
+    let room_manager = app_state.room_manager.clone();
+    auth_user.require_room(&room_manager, &room_id).await?;
+
+    let (channel_sender, broadcast_receiver, history) = match (
+        room_manager.clone().join(&room_id).await,
+        room_manager.history(&room_id).await,
+    ) {
+        (Some((channel_sender, broadcast_receiver)), Some(history)) => {
+            (channel_sender, broadcast_receiver, history)
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+            ));
+        }
+    };
+
+    // reconnecting EventSource clients resume after the last event they saw
+    let (missed, last_seq, reset) = match headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+    {
+        Some(last_seq) => {
+            let replay = history.lock().await.after(last_seq);
+            (replay.events, last_seq.min(replay.last_seq), replay.reset)
         }
-    } else {
+        None => (Vec::new(), history.lock().await.last_seq(), false),
+    };
+
+    // the stream ends with its session
+    let Some(session_watch) = auth_user.watch(&app_state.session_manager).await else {
         return Err((
             StatusCode::UNAUTHORIZED,
-            Json(ApiResponse::<()>::error(
-                "Unauthorized",
-                "Invalid Session ID Header",
-            )),
+            Json(ApiResponse::<()>::unauthorized()),
         ));
+    };
+
+    let (user_id, user) = auth_user.user();
+    let _ = channel_sender
+        .send(RoomCommand::join(user_id, user.clone()).by_bot(auth_user.is_bot))
+        .await;
+
+    let state = RoomEventStream {
+        missed: missed.into_iter(),
+        broadcast_receiver,
+        last_seq,
+        reset,
+        session_watch,
+        _leave_guard: LeaveOnDrop {
+            channel_sender,
+            user_id,
+            user,
+            is_bot: auth_user.is_bot,
+        },
+        metrics: app_state.metrics.clone(),
+        closed: false,
+    };
+
+    let stream = stream::unfold(state, move |mut state| async move {
+        if state.closed {
+            return None;
+        }
+
+        if std::mem::take(&mut state.reset) {
+            return Some((Ok::<_, Infallible>(reset_event()), state));
+        }
+
+        loop {
+            let command = match state.missed.next() {
+                Some(command) => command,
+                None => tokio::select! {
+                    result = state.broadcast_receiver.recv() => match result {
+                        Ok(command) => command,
+                        Err(RecvError::Lagged(_)) => {
+                            state.metrics.broadcast_lagged();
+                            return Some((Ok(reset_event()), state));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    },
+                    end = state.session_watch.ended() => {
+                        state.closed = true;
+                        let event = Event::default().event("close").data(end.reason());
+
+                        return Some((Ok::<_, Infallible>(event), state));
+                    }
+                },
+            };
+
+            // skip events already replayed from history
+            if let Some(seq) = command.seq {
+                if seq <= state.last_seq {
+                    continue;
+                }
+                state.last_seq = seq;
+            }
+
+            let seq = command.seq;
+            let ends = command.ends_connection(user_id);
+            let reason = command.message.clone().unwrap_or_default();
+            let event = match ServerEvent::from_room_command(command, user_id) {
+                Some(server_event) => Event::default()
+                    .id(seq.unwrap_or_default().to_string())
+                    .json_data(ServerFrame::from(server_event))
+                    .unwrap(),
+                None if ends => Event::default().event("close").data(reason),
+                None => continue,
+            };
+            state.closed = ends;
+
+            return Some((Ok(event), state));
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+pub async fn poll_room(
+    Path(room_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Read)?;
+
+    // check params
+    let after = params
+        .get("after")
+        .and_then(|after| after.parse::<u64>().ok())
+        .unwrap_or_default();
+    let wait = params
+        .get("timeout")
+        .and_then(|timeout| timeout.parse::<u64>().ok())
+        .unwrap_or(25)
+        .min(30);
+
+    let room_manager = app_state.room_manager.clone();
+    auth_user.require_room(&room_manager, &room_id).await?;
+
+    let (mut broadcast_receiver, history) = match (
+        room_manager.clone().join(&room_id).await,
+        room_manager.history(&room_id).await,
+    ) {
+        (Some((_channel_sender, broadcast_receiver)), Some(history)) => {
+            (broadcast_receiver, history)
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+            ));
+        }
+    };
+
+    let mut closed = false;
+    let mut replay = history.lock().await.after(after);
+
+    // nothing new yet, hold the request until the room broadcasts
+    if replay.events.is_empty() && !replay.reset {
+        closed = match timeout(Duration::from_secs(wait), broadcast_receiver.recv()).await {
+            Ok(Ok(command)) => matches!(command.method, Method::Close),
+            Ok(Err(RecvError::Closed)) => true,
+            Ok(Err(RecvError::Lagged(_))) => {
+                app_state.metrics.broadcast_lagged();
+                false
+            }
+            _ => false,
+        };
+
+        replay = history.lock().await.after(after);
     }
+
+    // a reset client starts over from the newest event
+    let next = replay
+        .events
+        .iter()
+        .filter_map(|command| command.seq)
+        .max()
+        .unwrap_or(if replay.reset { replay.last_seq } else { after });
+    let events = replay
+        .events
+        .into_iter()
+        .filter_map(|command| {
+            let seq = command.seq?;
+            let server_event = ServerEvent::from_room_command(command, auth_user.user_id)?;
+
+            Some(PolledEvent::new(seq, ServerFrame::from(server_event)))
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::<PolledEvents>::success_with_data(
+        "",
+        PolledEvents::new(events, next, closed, replay.reset),
+    )))
+}
+
+// the stream skipped events, the client reloads the history before it goes on
+fn reset_event() -> Event {
+    Event::default()
+        .event("reset")
+        .data("Missed events are no longer in the room history")
+}
+
+struct RoomEventStream {
+    missed: std::vec::IntoIter<RoomCommand>,
+    broadcast_receiver: broadcast::Receiver<RoomCommand>,
+    last_seq: u64,
+    reset: bool,
+    session_watch: SessionWatch,
+    _leave_guard: LeaveOnDrop,
+    metrics: Arc<Metrics>,
+    closed: bool,
+}
+
+// announce the leave when a streaming response is dropped by the client
+struct LeaveOnDrop {
+    channel_sender: mpsc::Sender<RoomCommand>,
+    user_id: i32,
+    user: String,
+    is_bot: bool,
+}
+
+impl Drop for LeaveOnDrop {
+    fn drop(&mut self) {
+        let channel_sender = self.channel_sender.clone();
+        let user_id = self.user_id;
+        let user = std::mem::take(&mut self.user);
+        let leave = RoomCommand::leave(user_id, user).by_bot(self.is_bot);
+
+        tokio::spawn(async move {
+            let _ = channel_sender.send(leave).await;
+        });
+    }
+}
+
+pub async fn room_limits(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Read)?;
+
+    match app_state.room_manager.clone().settings(&room_id).await {
+        Some((_owner_id, limits)) => Ok(Json(ApiResponse::<RoomLimits>::success_with_data(
+            "",
+            limits.lock().await.clone(),
+        ))),
+        None => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+        )),
+    }
+}
+
+pub async fn sessions(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let sessions = app_state
+        .session_manager
+        .list_sessions(
+            auth_user.user_id,
+            auth_user.session_id.as_deref().unwrap_or_default(),
+        )
+        .await;
+
+    Ok(Json(ApiResponse::<Vec<SessionInfo>>::success_with_data(
+        "", sessions,
+    )))
+}
+
+pub async fn tokens(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let tokens = app_state
+        .db
+        .tokens()
+        .list(auth_user.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to fetch api tokens: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok(Json(ApiResponse::<Vec<ApiToken>>::success_with_data(
+        "", tokens,
+    )))
+}
+
+pub async fn bots(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let bots = app_state
+        .db
+        .bots()
+        .list(auth_user.user_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to fetch bots: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok(Json(ApiResponse::<Vec<Bot>>::success_with_data("", bots)))
+}
+
+pub async fn webhooks(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let webhooks = app_state.db.webhooks().list(room_id).await.map_err(|err| {
+        tracing::error!("Failed to fetch webhooks: {}", err);
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+        )
+    })?;
+
+    Ok(Json(ApiResponse::<Vec<Webhook>>::success_with_data(
+        "", webhooks,
+    )))
+}
+
+pub async fn webhook_deliveries(
+    Path((room_id, id)): Path<(String, i64)>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let deliveries = app_state
+        .db
+        .webhooks()
+        .list_deliveries(room_id, id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to fetch webhook deliveries: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok(Json(
+        ApiResponse::<Vec<WebhookDelivery>>::success_with_data("", deliveries),
+    ))
+}
+
+pub async fn incoming_webhooks(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let incoming_webhooks = app_state
+        .db
+        .incoming_webhooks()
+        .list(room_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to fetch incoming webhooks: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok(Json(
+        ApiResponse::<Vec<IncomingWebhook>>::success_with_data("", incoming_webhooks),
+    ))
+}
+
+// health of the message writer, a growing queue means the database falls behind
+pub async fn message_writer_stats(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Read)?;
+
+    Ok(Json(ApiResponse::<WriterStats>::success_with_data(
+        "",
+        app_state.message_writer.stats(),
+    )))
 }
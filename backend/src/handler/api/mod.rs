@@ -1,21 +1,60 @@
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 
+mod auth_user;
+pub use auth_user::AuthUser;
+pub use auth_user::require_auth;
+
 mod get;
 pub use get::auth;
+pub use get::bots;
 pub use get::create_room;
+pub use get::incoming_webhooks;
 pub use get::join_room;
 pub use get::logout;
+pub use get::message_writer_stats;
+pub use get::poll_room;
+pub use get::room_events;
+pub use get::room_limits;
 pub use get::rooms;
+pub use get::sessions;
+pub use get::tokens;
+pub use get::webhook_deliveries;
+pub use get::webhooks;
 
 mod post;
+pub use post::add_room_bot;
+pub use post::create_bot;
+pub use post::create_incoming_webhook;
+pub use post::create_token;
+pub use post::create_webhook;
+pub use post::enroll_totp;
 pub use post::login;
+pub use post::login_totp;
+pub use post::post_incoming_webhook;
+pub use post::send_message;
 pub use post::signup;
+pub use post::verify_totp;
 use uuid::Uuid;
 
+use crate::{
+    api_token::ApiToken,
+    bot::Bot,
+    protocol::v2::ServerFrame,
+    webhook::{Webhook, incoming::IncomingWebhook},
+};
+
 mod patch;
+pub use patch::update_limits;
 
 mod delete;
+pub use delete::delete_incoming_webhook;
+pub use delete::delete_webhook;
+pub use delete::disable_totp;
+pub use delete::remove_room_bot;
+pub use delete::revoke_all_sessions;
+pub use delete::revoke_session;
+pub use delete::revoke_token;
 
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
@@ -56,6 +95,15 @@ where
         }
     }
 
+    pub fn error_with_data(code: &str, msg: &str, data: T) -> ApiResponse<T> {
+        ApiResponse {
+            status: "error".into(),
+            code: code.into(),
+            message: msg.into(),
+            data: Some(data),
+        }
+    }
+
     pub fn unauthorized() -> ApiResponse<T> {
         ApiResponse {
             status: "error".into(),
@@ -66,82 +114,164 @@ where
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct StreamCommand {
-    method: StreamMethod,
-    message: String,
-    sender: String,
-    is_self: bool,
+#[derive(Debug, Serialize)]
+pub struct Room {
+    room_id: String,
+    room_name: String,
+}
+
+impl Room {
+    pub fn new(room_id: Uuid, room_name: String) -> Self {
+        Room {
+            room_id: room_id.to_string(),
+            room_name,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolledEvents {
+    events: Vec<PolledEvent>,
+    // pass as `after` on the next poll
+    next: u64,
+    closed: bool,
+    // events since `after` were dropped from the room history, reload it before going on
+    reset: bool,
 }
 
-impl StreamCommand {
-    pub fn join(user: String) -> String {
-        let message = format!("User {} join the room", user);
+impl PolledEvents {
+    pub fn new(events: Vec<PolledEvent>, next: u64, closed: bool, reset: bool) -> Self {
+        PolledEvents {
+            events,
+            next,
+            closed,
+            reset,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolledEvent {
+    seq: u64,
+    #[serde(flatten)]
+    frame: ServerFrame,
+}
+
+impl PolledEvent {
+    pub fn new(seq: u64, frame: ServerFrame) -> Self {
+        PolledEvent { seq, frame }
+    }
+}
 
-        let stream_command = StreamCommand {
-            method: StreamMethod::Join,
-            message: message,
-            sender: "System".into(),
-            is_self: false,
-        };
+#[derive(Debug, Serialize)]
+pub struct MessageReceipt {
+    request_id: String,
+    message_id: i64,
+    sent_at: DateTime<Utc>,
+}
 
-        serde_json::to_string(&stream_command).unwrap()
+impl MessageReceipt {
+    pub fn new(request_id: String, message_id: i64, sent_at: DateTime<Utc>) -> Self {
+        MessageReceipt {
+            request_id,
+            message_id,
+            sent_at,
+        }
     }
+}
 
-    pub fn send_by_others(user: String, message: String) -> String {
-        let stream_command = StreamCommand {
-            method: StreamMethod::Send,
-            message: message,
-            sender: user,
-            is_self: false,
-        };
+#[derive(Debug, Serialize)]
+pub struct LoginChallenge {
+    challenge: String,
+}
 
-        serde_json::to_string(&stream_command).unwrap()
+impl LoginChallenge {
+    pub fn new(challenge: String) -> Self {
+        LoginChallenge { challenge }
     }
+}
 
-    pub fn send_by_self(user: String, message: String) -> String {
-        let stream_command = StreamCommand {
-            method: StreamMethod::Send,
-            message: message,
-            sender: user,
-            is_self: true,
-        };
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    secret: String,
+    provisioning_uri: String,
+}
 
-        serde_json::to_string(&stream_command).unwrap()
+impl TotpEnrollment {
+    pub fn new(secret: String, provisioning_uri: String) -> Self {
+        TotpEnrollment {
+            secret,
+            provisioning_uri,
+        }
     }
+}
 
-    pub fn leave(user: String) -> String {
-        let message = format!("User {} leave the room", user);
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
 
-        let stream_command = StreamCommand {
-            method: StreamMethod::Leave,
-            message: message,
-            sender: "System".into(),
-            is_self: false,
-        };
+impl RecoveryCodes {
+    pub fn new(recovery_codes: Vec<String>) -> Self {
+        RecoveryCodes { recovery_codes }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiToken {
+    // the plain token, only returned on creation
+    token: String,
+    #[serde(flatten)]
+    api_token: ApiToken,
+}
 
-        serde_json::to_string(&stream_command).unwrap()
+impl CreatedApiToken {
+    pub fn new(token: String, api_token: ApiToken) -> Self {
+        CreatedApiToken { token, api_token }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub enum StreamMethod {
-    Send,
-    Join,
-    Leave,
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    // HMAC key for the X-WsChat-Signature header, only returned on creation
+    secret: String,
+}
+
+impl CreatedWebhook {
+    pub fn new(webhook: Webhook, secret: String) -> Self {
+        CreatedWebhook { webhook, secret }
+    }
 }
 
 #[derive(Debug, Serialize)]
-pub struct Room {
-    room_id: String,
-    room_name: String,
+pub struct CreatedIncomingWebhook {
+    #[serde(flatten)]
+    incoming_webhook: IncomingWebhook,
+    // path to POST messages to, it carries the token and is only returned on creation
+    url: String,
 }
 
-impl Room {
-    pub fn new(room_id: Uuid, room_name: String) -> Self {
-        Room {
-            room_id: room_id.to_string(),
-            room_name,
+impl CreatedIncomingWebhook {
+    pub fn new(incoming_webhook: IncomingWebhook, token: String) -> Self {
+        CreatedIncomingWebhook {
+            incoming_webhook,
+            url: format!("/api/hooks/{}", token),
         }
     }
 }
+
+#[derive(Debug, Serialize)]
+pub struct CreatedBot {
+    #[serde(flatten)]
+    bot: Bot,
+    // the bot's chat token, only returned on creation
+    token: String,
+}
+
+impl CreatedBot {
+    pub fn new(bot: Bot, token: String) -> Self {
+        CreatedBot { bot, token }
+    }
+}
@@ -0,0 +1,49 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use std::sync::Arc;
+
+use crate::{
+    api_token::Scope,
+    handler::api::{ApiResponse, AuthUser},
+    rate_limit::{RoomLimits, RoomLimitsPatch},
+    router::AppState,
+};
+
+pub async fn update_limits(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(patch): Json<RoomLimitsPatch>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let Some((owner_id, limits)) = app_state.room_manager.clone().settings(&room_id).await else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+        ));
+    };
+
+    // only the room creator may change its limits
+    if owner_id != auth_user.user_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(
+                "FORBIDDEN",
+                "Only the room owner can change its limits",
+            )),
+        ));
+    }
+
+    let mut limits = limits.lock().await;
+    limits.apply(patch);
+
+    Ok(Json(ApiResponse::<RoomLimits>::success_with_data(
+        "Room limits updated",
+        limits.clone(),
+    )))
+}
@@ -1,58 +1,64 @@
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 use axum::{
     Json,
-    extract::State,
+    extract::{ConnectInfo, Path, State},
     http::{HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
 use serde::Deserialize;
-use sqlx::{Error, Row};
+use tokio::{sync::mpsc, time::timeout};
+use uuid::Uuid;
 
-use crate::{handler::api::ApiResponse, router::AppState};
+use crate::{
+    api_token::Scope,
+    bot::Bot,
+    db::{Credentials, StorageError, TotpSecret},
+    handler::api::{
+        ApiResponse, AuthUser, CreatedApiToken, CreatedBot, CreatedIncomingWebhook,
+        CreatedWebhook, LoginChallenge, MessageReceipt, RecoveryCodes, TotpEnrollment,
+    },
+    protocol::ClientEvent,
+    room_manager::Reply,
+    router::AppState,
+    session::ClientInfo,
+    two_factor,
+    webhook::{self, EventKind},
+};
 
 pub async fn signup(
     State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
     account: Json<Account>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
-    let query_str = r#"
-      WITH new_account AS(
-      insert into accounts(account, password) values($1, $2)
-      returning id, account
-      )
-    
-      insert into users(account_id, username)
-      select id, account from new_account
-      returning id, username
-    "#;
-
-    let row = sqlx::query(query_str)
-        .bind(&account.account)
-        .bind(&account.password)
-        .fetch_one(&app_state.pool)
+    let (user_id, user_name) = app_state
+        .db
+        .accounts()
+        .create(&account.account, &account.password)
         .await
         .map_err(|err| match err {
-            Error::Database(db_err) => {
-                if db_err.is_unique_violation() {
-                  return (
-                    StatusCode::CONFLICT, 
-                    Json(ApiResponse::error(
-                      "DUPLICATE_ENTRY", 
-                      "The provided account name is already taken. Please choose a different one.",
-                    ))
-                  );
-                }
-
+            StorageError::Conflict => (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::error(
+                    "DUPLICATE_ENTRY",
+                    "The provided account name is already taken. Please choose a different one.",
+                )),
+            ),
+            StorageError::Backend(_) | StorageError::Unavailable(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")))
             },
             _ => (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error("INTERNAL_SERVER_ERROR", &err.to_string()))),
         })?;
 
-    let user_id: i32 = row.get(0);
-    let user_name: String = row.get(1);
-
     //create sesion and set session_id in cookie
     let session_manage = app_state.session_manager.clone();
-    let session_id = session_manage.new_session(user_id, user_name).await;
+    let session_id = session_manage
+        .new_session(user_id, user_name, ClientInfo::new(addr.ip(), &request_headers))
+        .await;
     
     let mut headers = HeaderMap::new();
     let cookie_value = format!("session_id={}; HttpOnly; Path=/; Secure", session_id);
@@ -67,27 +73,51 @@ pub async fn signup(
 
 pub async fn login(
     State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
     account: Json<Account>,
-) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
-    let query_str = r#"
-      select u.id, u.username from accounts a
-      left join users u on u.account_id = a.id
-      where a.account = $1 and a.password = $2
-    "#;
-
-    let row = sqlx::query(query_str)
-        .bind(&account.account)
-        .bind(&account.password)
-        .fetch_one(&app_state.pool)
-        .await
+) -> Result<Response, (StatusCode, Json<ApiResponse<()>>)> {
+    let ip = addr.ip();
+
+    // progressive lockout after repeated failures
+    let mut login_attempt = match app_state.login_guard.begin(&account.account, ip) {
+        Ok(login_attempt) => login_attempt,
+        Err(wait) => {
+            audit_login(&app_state, &account.account, ip, "locked").await;
+
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiResponse::error(
+                    "TOO_MANY_ATTEMPTS",
+                    &format!(
+                        "Too many failed login attempts, retry in {}s",
+                        wait.as_secs() + 1
+                    ),
+                )),
+            ));
+        }
+    };
+
+    let result = app_state
+        .db
+        .accounts()
+        .find_by_credentials(&account.account, &account.password)
+        .await;
+
+    if let Err(StorageError::NotFound) = result {
+        login_attempt.failed();
+        audit_login(&app_state, &account.account, ip, "invalid_credentials").await;
+    }
+
+    let credentials = result
         .map_err(|err| match err {
-            Error::Database(_db_err) => {
+            StorageError::Backend(_) | StorageError::Unavailable(_) => {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
                 )
             }
-            Error::RowNotFound => 
+            StorageError::NotFound =>
               (
                 StatusCode::NOT_FOUND,
                 Json(ApiResponse::error(
@@ -104,12 +134,41 @@ pub async fn login(
             ),
         })?;
 
-    let user_id: i32 = row.get(0);
-    let username:String = row.get(1);
+    let Credentials {
+        user_id,
+        username,
+        account_id,
+        totp_enabled,
+    } = credentials;
+
+    // the session is only issued once the second factor is verified
+    if totp_enabled {
+        audit_login(&app_state, &account.account, ip, "totp_required").await;
+
+        let challenge = app_state
+            .pending_logins
+            .create(account_id, account.account.clone(), user_id, username)
+            .await;
+
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error_with_data(
+                "TOTP_REQUIRED",
+                "Enter the code from your authenticator app",
+                LoginChallenge::new(challenge),
+            )),
+        )
+            .into_response());
+    }
+
+    login_attempt.succeeded();
+    audit_login(&app_state, &account.account, ip, "success").await;
 
     //create sesion and set session_id in cookie
     let session_manage = app_state.session_manager.clone();
-    let session_id = session_manage.new_session(user_id, username).await;
+    let session_id = session_manage
+        .new_session(user_id, username, ClientInfo::new(ip, &request_headers))
+        .await;
     
     let mut headers = HeaderMap::new();
     let cookie_value = format!("session_id={}; HttpOnly; Path=/; Secure", session_id);
@@ -119,11 +178,695 @@ pub async fn login(
         StatusCode::OK,
         headers,
         Json(ApiResponse::<()>::success("Login")),
+    )
+        .into_response())
+}
+
+pub async fn login_totp(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+    Json(totp_login): Json<TotpLogin>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    let ip = addr.ip();
+
+    let Some(pending) = app_state.pending_logins.take(&totp_login.challenge).await else {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error(
+                "INVALID_CHALLENGE",
+                "Login challenge expired, please log in again",
+            )),
+        ));
+    };
+
+    // guessing codes counts as failed logins
+    let mut login_attempt = match app_state.login_guard.begin(&pending.account, ip) {
+        Ok(login_attempt) => login_attempt,
+        Err(wait) => {
+            audit_login(&app_state, &pending.account, ip, "locked").await;
+            app_state
+                .pending_logins
+                .put_back(&totp_login.challenge, pending)
+                .await;
+
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiResponse::error(
+                    "TOO_MANY_ATTEMPTS",
+                    &format!(
+                        "Too many failed login attempts, retry in {}s",
+                        wait.as_secs() + 1
+                    ),
+                )),
+            ));
+        }
+    };
+
+    let internal_error = |err: StorageError| {
+        tracing::error!("Failed to verify second factor: {}", err);
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+        )
+    };
+
+    let secret = app_state
+        .db
+        .totp()
+        .enabled_secret(pending.account_id)
+        .await
+        .map_err(internal_error)?;
+
+    let verified = two_factor::verify_second_factor(
+        &app_state.db,
+        pending.account_id,
+        &secret,
+        &totp_login.code,
+    )
+    .await
+    .map_err(internal_error)?;
+
+    if !verified {
+        login_attempt.failed();
+        audit_login(&app_state, &pending.account, ip, "invalid_totp").await;
+        app_state
+            .pending_logins
+            .put_back(&totp_login.challenge, pending)
+            .await;
+
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error(
+                "INVALID_TOTP",
+                "The authentication code is incorrect",
+            )),
+        ));
+    }
+
+    login_attempt.succeeded();
+    audit_login(&app_state, &pending.account, ip, "success").await;
+
+    //create sesion and set session_id in cookie
+    let session_manage = app_state.session_manager.clone();
+    let session_id = session_manage
+        .new_session(
+            pending.user_id,
+            pending.username,
+            ClientInfo::new(ip, &request_headers),
+        )
+        .await;
+
+    let mut headers = HeaderMap::new();
+    let cookie_value = format!("session_id={}; HttpOnly; Path=/; Secure", session_id);
+    headers.insert("Set-Cookie", HeaderValue::from_str(&cookie_value).unwrap());
+
+    Ok((
+        StatusCode::OK,
+        headers,
+        Json(ApiResponse::<()>::success("Login")),
+    ))
+}
+
+pub async fn enroll_totp(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let secret = two_factor::generate_secret();
+    let Some(provisioning_uri) = two_factor::provisioning_uri(&secret, &auth_user.username)
+    else {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+        ));
+    };
+
+    // a new enrollment replaces a pending one but never an enabled one
+    let enrolled = app_state
+        .db
+        .totp()
+        .enroll(auth_user.user_id, &secret)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to enroll totp: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    if !enrolled {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error(
+                "TOTP_ALREADY_ENABLED",
+                "Two-factor authentication is already enabled",
+            )),
+        ));
+    }
+
+    Ok(Json(ApiResponse::<TotpEnrollment>::success_with_data(
+        "Scan the provisioning uri and verify a code to enable two-factor authentication",
+        TotpEnrollment::new(secret, provisioning_uri),
+    )))
+}
+
+pub async fn verify_totp(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(totp_code): Json<TotpCode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let internal_error = |err: StorageError| {
+        tracing::error!("Failed to enable totp: {}", err);
+
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+        )
+    };
+
+    let Some(TotpSecret { account_id, secret }) = app_state
+        .db
+        .totp()
+        .find(auth_user.user_id, false)
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "TOTP_NOT_ENROLLED",
+                "Start an enrollment before verifying a code",
+            )),
+        ));
+    };
+
+    // the code used to enable can't be replayed to log in
+    let accepted = match two_factor::verify_code(&secret, &totp_code.code) {
+        Some(step) => app_state
+            .db
+            .totp()
+            .accept_step(account_id, step)
+            .await
+            .map_err(internal_error)?,
+        None => false,
+    };
+
+    if !accepted {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error(
+                "INVALID_TOTP",
+                "The authentication code is incorrect",
+            )),
+        ));
+    }
+
+    // enable and replace the recovery codes in one transaction
+    let recovery_codes = two_factor::generate_recovery_codes();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| two_factor::hash_recovery_code(code))
+        .collect();
+
+    app_state
+        .db
+        .totp()
+        .enable(account_id, &code_hashes)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(ApiResponse::<RecoveryCodes>::success_with_data(
+        "Two-factor authentication enabled, store the recovery codes somewhere safe",
+        RecoveryCodes::new(recovery_codes),
+    )))
+}
+
+// keep every login attempt for later review
+async fn audit_login(app_state: &AppState, account: &str, ip: IpAddr, outcome: &str) {
+    let _ = app_state
+        .db
+        .accounts()
+        .record_login_attempt(account, ip, outcome)
+        .await
+        .map_err(|err| tracing::error!("Failed to audit login attempt: {}", err));
+}
+
+pub async fn send_message(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(room_message): Json<RoomMessage>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Send)?;
+
+    let room_not_alive = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+        )
+    };
+
+    let room_manager = app_state.room_manager.clone();
+    auth_user.require_room(&room_manager, &room_id).await?;
+
+    let (channel_sender, _broadcast_receiver) =
+        room_manager.join(&room_id).await.ok_or_else(room_not_alive)?;
+
+    let (reply_sender, reply_receiver) = mpsc::channel(1);
+    let request_id = room_message
+        .request_id
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let room_command = ClientEvent::Send {
+        message: room_message.content,
+        request_id: Some(request_id),
+    }
+    .into_room_command(&auth_user.user(), &room_id, reply_sender)
+    .by_bot(auth_user.is_bot);
+
+    channel_sender
+        .send(room_command)
+        .await
+        .map_err(|_err| room_not_alive())?;
+
+    await_receipt(reply_receiver, app_state.config.server.ack_timeout()).await
+}
+
+// CI and other integrations post with the hook token instead of a session
+pub async fn post_incoming_webhook(
+    Path(token): Path<String>,
+    State(app_state): State<Arc<AppState>>,
+    Json(room_message): Json<RoomMessage>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    let integration = app_state
+        .db
+        .incoming_webhooks()
+        .authenticate(&token)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to authenticate incoming webhook: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    let Some(integration) = integration else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Webhook not found")),
+        ));
+    };
+
+    let room_not_alive = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+        )
+    };
+
+    let room_id = integration.room_id.to_string();
+    let (channel_sender, _broadcast_receiver) = app_state
+        .room_manager
+        .clone()
+        .join(&room_id)
+        .await
+        .ok_or_else(room_not_alive)?;
+
+    // stored through the room task and db writer like any other message
+    let (reply_sender, reply_receiver) = mpsc::channel(1);
+    let request_id = room_message
+        .request_id
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let user = (integration.user_id, integration.name);
+    let room_command = ClientEvent::Send {
+        message: room_message.content,
+        request_id: Some(request_id),
+    }
+    .into_room_command(&user, &room_id, reply_sender)
+    .by_bot(true);
+
+    channel_sender
+        .send(room_command)
+        .await
+        .map_err(|_err| room_not_alive())?;
+
+    await_receipt(reply_receiver, app_state.config.server.ack_timeout()).await
+}
+
+// wait for the same ack a WebSocket client would receive
+async fn await_receipt(
+    mut reply_receiver: mpsc::Receiver<Reply>,
+    ack_timeout: Duration,
+) -> Result<Json<ApiResponse<MessageReceipt>>, (StatusCode, Json<ApiResponse<()>>)> {
+    match timeout(ack_timeout, reply_receiver.recv()).await {
+        Ok(Some(Reply::Ack {
+            request_id,
+            message_id,
+            sent_at,
+        })) => Ok(Json(ApiResponse::<MessageReceipt>::success_with_data(
+            "Message sent",
+            MessageReceipt::new(request_id, message_id, sent_at),
+        ))),
+        // slash command answered privately
+        Ok(Some(Reply::Notice { message, .. })) => {
+            Ok(Json(ApiResponse::<MessageReceipt>::success(&message)))
+        }
+        Ok(Some(Reply::Error { code, message, .. })) => {
+            let status = match code.as_str() {
+                "RATE_LIMITED" | "SLOW_MODE" | "MUTED" => StatusCode::TOO_MANY_REQUESTS,
+                "BAD_COMMAND" | "UNKNOWN_COMMAND" => StatusCode::BAD_REQUEST,
+                "FORBIDDEN" => StatusCode::FORBIDDEN,
+                "NOT_FOUND" => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+
+            Err((status, Json(ApiResponse::error(&code, &message))))
+        }
+        _ => Err((
+            StatusCode::GATEWAY_TIMEOUT,
+            Json(ApiResponse::error(
+                "MESSAGE_PENDING",
+                "Message was not acknowledged in time",
+            )),
+        )),
+    }
+}
+
+pub async fn create_token(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(new_token): Json<NewApiToken>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let name = new_token.name.trim();
+
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("BAD_REQUEST", "Token name is required")),
+        ));
+    }
+
+    let (token, api_token) = app_state
+        .db
+        .tokens()
+        .create(auth_user.user_id, name, new_token.scope)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to create api token: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::<CreatedApiToken>::success_with_data(
+            "Store the token somewhere safe, it is not shown again",
+            CreatedApiToken::new(token, api_token),
+        )),
+    ))
+}
+
+pub async fn create_bot(
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(new_bot): Json<NewBot>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    if auth_user.is_bot {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("FORBIDDEN", "Bots cannot create bots")),
+        ));
+    }
+
+    let name = new_bot.name.trim();
+
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("BAD_REQUEST", "Bot name is required")),
+        ));
+    }
+
+    let (bot, token) = app_state
+        .db
+        .bots()
+        .create(auth_user.user_id, name)
+        .await
+        .map_err(|err| match err {
+            StorageError::Conflict => (
+                StatusCode::CONFLICT,
+                Json(ApiResponse::error(
+                    "DUPLICATE_ENTRY",
+                    "The provided bot name is already taken",
+                )),
+            ),
+            err => {
+                tracing::error!("Failed to create bot: {}", err);
+
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+                )
+            }
+        })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::<CreatedBot>::success_with_data(
+            "Store the bot token somewhere safe, it is not shown again",
+            CreatedBot::new(bot, token),
+        )),
+    ))
+}
+
+pub async fn add_room_bot(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(room_bot): Json<RoomBot>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_manager = app_state.room_manager.clone();
+    let (Some((owner_id, _limits)), Some(bots)) = (
+        room_manager.clone().settings(&room_id).await,
+        room_manager.bots(&room_id).await,
+    ) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error("BAD_REQUEST", "Room is not alive")),
+        ));
+    };
+
+    // only the room creator may add bots
+    if owner_id != auth_user.user_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::error(
+                "FORBIDDEN",
+                "Only the room owner can add bots",
+            )),
+        ));
+    }
+
+    let bot = app_state
+        .db
+        .bots()
+        .find(auth_user.user_id, room_bot.bot_id)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to fetch bot: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    let Some(bot) = bot else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("NOT_FOUND", "Bot not found")),
+        ));
+    };
+
+    bots.lock().await.insert(bot.id);
+
+    Ok(Json(ApiResponse::<Bot>::success_with_data(
+        "Bot added to the room",
+        bot,
+    )))
+}
+
+pub async fn create_incoming_webhook(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(new_incoming_webhook): Json<NewIncomingWebhook>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let name = new_incoming_webhook.name.trim();
+
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "BAD_REQUEST",
+                "Webhook name is required",
+            )),
+        ));
+    }
+
+    let (incoming_webhook, token) = app_state
+        .db
+        .incoming_webhooks()
+        .create(room_id, auth_user.user_id, name)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to create incoming webhook: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::<CreatedIncomingWebhook>::success_with_data(
+            "Store the webhook url somewhere safe, it is not shown again",
+            CreatedIncomingWebhook::new(incoming_webhook, token),
+        )),
+    ))
+}
+
+pub async fn create_webhook(
+    Path(room_id): Path<String>,
+    auth_user: AuthUser,
+    State(app_state): State<Arc<AppState>>,
+    Json(new_webhook): Json<NewWebhook>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ApiResponse<()>>)> {
+    auth_user.require(Scope::Admin)?;
+
+    let room_id = auth_user
+        .require_owner(&app_state.room_manager, &room_id, "manage webhooks")
+        .await?;
+
+    let url = new_webhook.url.trim();
+
+    // internal services are off limits unless the config allows them
+    if let Err(err) =
+        webhook::target::check(url, app_state.config.webhook.allow_private_targets).await
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error("BAD_REQUEST", &err.to_string())),
+        ));
+    }
+
+    let mut events = new_webhook.events;
+    events.sort_by_key(|event| event.as_str());
+    events.dedup();
+
+    if events.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(
+                "BAD_REQUEST",
+                "Select at least one event",
+            )),
+        ));
+    }
+
+    let (webhook, secret) = app_state
+        .db
+        .webhooks()
+        .create(room_id, url, events)
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to create webhook: {}", err);
+
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("INTERNAL_SERVER_ERROR", "")),
+            )
+        })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::<CreatedWebhook>::success_with_data(
+            "Store the secret somewhere safe, it is not shown again",
+            CreatedWebhook::new(webhook, secret),
+        )),
     ))
 }
 
+#[derive(Deserialize)]
+pub struct TotpLogin {
+    challenge: String,
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct RoomMessage {
+    content: String,
+    request_id: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct Account {
     account: String,
     password: String,
 }
+
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    name: String,
+    scope: Scope,
+}
+
+#[derive(Deserialize)]
+pub struct NewBot {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct RoomBot {
+    bot_id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct NewIncomingWebhook {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    url: String,
+    events: Vec<EventKind>,
+}
@@ -1,15 +1,56 @@
 mod api;
+//auth
+pub use api::require_auth;
+
 //get
 pub use api::auth;
+pub use api::bots;
 pub use api::create_room;
+pub use api::incoming_webhooks;
 pub use api::join_room;
 pub use api::logout;
+pub use api::message_writer_stats;
+pub use api::poll_room;
+pub use api::room_events;
+pub use api::room_limits;
 pub use api::rooms;
+pub use api::sessions;
+pub use api::tokens;
+pub use api::webhook_deliveries;
+pub use api::webhooks;
 
 //post
+pub use api::add_room_bot;
+pub use api::create_bot;
+pub use api::create_incoming_webhook;
+pub use api::create_token;
+pub use api::create_webhook;
+pub use api::enroll_totp;
 pub use api::login;
+pub use api::login_totp;
+pub use api::post_incoming_webhook;
+pub use api::send_message;
 pub use api::signup;
+pub use api::verify_totp;
+
+//patch
+pub use api::update_limits;
+
+//delete
+pub use api::delete_incoming_webhook;
+pub use api::delete_webhook;
+pub use api::disable_totp;
+pub use api::remove_room_bot;
+pub use api::revoke_all_sessions;
+pub use api::revoke_session;
+pub use api::revoke_token;
 
 mod static_file;
 //get
 pub use static_file::home;
+
+mod monitoring;
+//get
+pub use monitoring::healthz;
+pub use monitoring::metrics;
+pub use monitoring::readyz;
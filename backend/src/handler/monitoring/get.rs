@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::State,
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::IntoResponse,
+};
+
+use crate::{
+    handler::api::ApiResponse,
+    health::Readiness,
+    metrics::Snapshot,
+    router::{AppState, MetricsState},
+};
+
+// the process answers, nothing else is checked
+pub async fn healthz() -> impl IntoResponse {
+    Json(ApiResponse::<()>::success("Alive"))
+}
+
+// 503 while draining or when a dependency is down, the checks tell which
+pub async fn readyz(State(app_state): State<Arc<AppState>>) -> impl IntoResponse {
+    let readiness = app_state
+        .health
+        .readiness(
+            &app_state.db,
+            &app_state.message_writer,
+            &app_state.session_manager,
+        )
+        .await;
+
+    if readiness.is_ready() {
+        return (
+            StatusCode::OK,
+            Json(ApiResponse::<Readiness>::success_with_data(
+                "Ready", readiness,
+            )),
+        );
+    }
+
+    let message = if readiness.draining {
+        "Server is shutting down"
+    } else {
+        "Not ready"
+    };
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<Readiness>::error_with_data(
+            "NOT_READY",
+            message,
+            readiness,
+        )),
+    )
+}
+
+pub async fn metrics(State(metrics_state): State<Arc<MetricsState>>) -> impl IntoResponse {
+    let snapshot = Snapshot {
+        rooms: metrics_state.room_manager.rooms.lock().await.len(),
+        sessions: metrics_state.session_manager.count().await,
+        writer_queue_depth: metrics_state.message_writer.queue_depth(),
+    };
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics_state.metrics.render(snapshot),
+    )
+}
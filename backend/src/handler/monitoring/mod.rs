@@ -0,0 +1,4 @@
+mod get;
+pub use get::healthz;
+pub use get::metrics;
+pub use get::readyz;
@@ -0,0 +1,127 @@
+use std::{
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use axum_server::tls_rustls::RustlsConfig;
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::{
+    config::TlsConfig,
+    db::{Db, MessageWriter},
+    session::SessionManager,
+};
+
+// a probe that hangs is as bad as a failed one
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// what /readyz knows beyond the managers, set by the server as it starts and stops
+pub struct Health {
+    draining: AtomicBool,
+    tls: OnceLock<TlsConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Check {
+    Ok,
+    Failed,
+    // served without TLS, e.g. in tests
+    Disabled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub draining: bool,
+    pub database: Check,
+    pub message_writer: Check,
+    pub session_checker: Check,
+    pub tls: Check,
+}
+
+impl Health {
+    pub fn build() -> Arc<Health> {
+        Arc::new(Health {
+            draining: AtomicBool::new(false),
+            tls: OnceLock::new(),
+        })
+    }
+
+    // readiness fails from here on, the server keeps serving until it closes the listener
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn serve_tls(&self, tls: TlsConfig) {
+        let _ = self.tls.set(tls);
+    }
+
+    pub async fn readiness(
+        &self,
+        db: &Db,
+        message_writer: &MessageWriter,
+        session_manager: &SessionManager,
+    ) -> Readiness {
+        let database = match timeout(CHECK_TIMEOUT, db.ping()).await {
+            Ok(Ok(())) => Check::Ok,
+            Ok(Err(err)) => {
+                tracing::warn!("Readiness database check failed: {}", err);
+                Check::Failed
+            }
+            Err(_) => Check::Failed,
+        };
+
+        Readiness {
+            draining: self.draining.load(Ordering::Relaxed),
+            database,
+            message_writer: Check::from(message_writer.is_running()),
+            session_checker: Check::from(session_manager.checker_running()),
+            tls: self.check_tls().await,
+        }
+    }
+
+    // the certificate and key on disk still load, e.g. after a secret rotation
+    async fn check_tls(&self) -> Check {
+        let Some(tls) = self.tls.get() else {
+            return Check::Disabled;
+        };
+
+        match timeout(
+            CHECK_TIMEOUT,
+            RustlsConfig::from_pem_file(&tls.cert, &tls.key),
+        )
+        .await
+        {
+            Ok(Ok(_)) => Check::Ok,
+            Ok(Err(err)) => {
+                tracing::warn!("Readiness TLS check failed: {}", err);
+                Check::Failed
+            }
+            Err(_) => Check::Failed,
+        }
+    }
+}
+
+impl From<bool> for Check {
+    fn from(ok: bool) -> Self {
+        if ok { Check::Ok } else { Check::Failed }
+    }
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        !self.draining
+            && [
+                self.database,
+                self.message_writer,
+                self.session_checker,
+                self.tls,
+            ]
+            .iter()
+            .all(|check| *check != Check::Failed)
+    }
+}
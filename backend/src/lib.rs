@@ -0,0 +1,16 @@
+pub mod api_token;
+pub mod bot;
+pub mod config;
+pub mod db;
+pub mod handler;
+pub mod health;
+pub mod login_guard;
+pub mod metrics;
+pub mod protocol;
+pub mod rate_limit;
+pub mod room_manager;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod two_factor;
+pub mod webhook;
@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::{Instant, interval};
+
+use crate::config::LoginConfig;
+
+// failures are forgotten after this long without a new one
+const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+// how often forgotten failures are dropped from memory
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// failed attempts per account and per client ip, with exponential backoff
+pub struct LoginGuard {
+    // one lock for both maps, a login checks and reserves its attempt in one step
+    tracked: Mutex<Tracked>,
+    account_free_attempts: u32,
+    ip_free_attempts: u32,
+    max_tracked: usize,
+}
+
+#[derive(Default)]
+struct Tracked {
+    accounts: HashMap<String, Attempts>,
+    ips: HashMap<IpAddr, Attempts>,
+}
+
+impl LoginGuard {
+    pub fn build(config: &LoginConfig) -> Arc<LoginGuard> {
+        Arc::new(LoginGuard {
+            tracked: Mutex::new(Tracked::default()),
+            account_free_attempts: config.account_free_attempts,
+            ip_free_attempts: config.ip_free_attempts,
+            max_tracked: config.max_tracked,
+        })
+    }
+
+    // drop forgotten failures in the background instead of on every login
+    pub fn run_sweeper(self: &Arc<Self>) {
+        let login_guard = self.clone();
+
+        tokio::spawn(async move {
+            let mut sweep = interval(SWEEP_INTERVAL);
+
+            loop {
+                sweep.tick().await;
+                login_guard.sweep(Instant::now());
+            }
+        });
+    }
+
+    // Err with the remaining wait when the account or ip is locked, otherwise the attempt
+    // counts as failed until it is resolved, so parallel guesses can't pass the same check
+    pub fn begin(self: &Arc<Self>, account: &str, ip: IpAddr) -> Result<LoginAttempt, Duration> {
+        let now = Instant::now();
+        let mut tracked = self.tracked.lock().unwrap();
+
+        let account_wait = tracked
+            .accounts
+            .get(account)
+            .and_then(|attempts| attempts.remaining(now));
+        let ip_wait = tracked
+            .ips
+            .get(&ip)
+            .and_then(|attempts| attempts.remaining(now));
+
+        if let Some(wait) = account_wait.max(ip_wait) {
+            return Err(wait);
+        }
+
+        attempts_for(&mut tracked.accounts, account.to_string(), self.max_tracked)
+            .fail(now, self.account_free_attempts);
+        attempts_for(&mut tracked.ips, ip, self.max_tracked).fail(now, self.ip_free_attempts);
+
+        Ok(LoginAttempt {
+            login_guard: self.clone(),
+            account: account.to_string(),
+            ip,
+            resolved: false,
+        })
+    }
+
+    fn sweep(&self, now: Instant) {
+        let mut tracked = self.tracked.lock().unwrap();
+
+        tracked
+            .accounts
+            .retain(|_account, attempts| !attempts.is_stale(now));
+        tracked.ips.retain(|_ip, attempts| !attempts.is_stale(now));
+    }
+
+    // give back a reserved attempt that did not fail
+    fn release(&self, account: &str, ip: IpAddr) {
+        let mut tracked = self.tracked.lock().unwrap();
+
+        refund(&mut tracked.accounts, account, self.account_free_attempts);
+        refund(&mut tracked.ips, &ip, self.ip_free_attempts);
+    }
+
+    // the ip keeps its history so one valid account can't reset it
+    fn succeed(&self, account: &str, ip: IpAddr) {
+        let mut tracked = self.tracked.lock().unwrap();
+
+        tracked.accounts.remove(account);
+        refund(&mut tracked.ips, &ip, self.ip_free_attempts);
+    }
+}
+
+// a full map makes room by forgetting the entry that failed longest ago
+fn attempts_for<K: Eq + Hash + Clone>(
+    map: &mut HashMap<K, Attempts>,
+    key: K,
+    max_tracked: usize,
+) -> &mut Attempts {
+    if !map.contains_key(&key) && map.len() >= max_tracked {
+        let oldest = map
+            .iter()
+            .min_by_key(|(_key, attempts)| attempts.last_failure)
+            .map(|(key, _attempts)| key.clone());
+
+        if let Some(oldest) = oldest {
+            map.remove(&oldest);
+        }
+    }
+
+    map.entry(key).or_default()
+}
+
+fn refund<K, Q>(map: &mut HashMap<K, Attempts>, key: &Q, free_attempts: u32)
+where
+    K: Eq + Hash + std::borrow::Borrow<Q>,
+    Q: Eq + Hash + ?Sized,
+{
+    if let Some(attempts) = map.get_mut(key) {
+        attempts.refund(free_attempts);
+
+        if attempts.failures == 0 {
+            map.remove(key);
+        }
+    }
+}
+
+// a login in progress, released on drop unless it failed or succeeded
+pub struct LoginAttempt {
+    login_guard: Arc<LoginGuard>,
+    account: String,
+    ip: IpAddr,
+    resolved: bool,
+}
+
+impl LoginAttempt {
+    // the reserved attempt stays counted
+    pub fn failed(&mut self) {
+        self.resolved = true;
+    }
+
+    pub fn succeeded(&mut self) {
+        if !std::mem::replace(&mut self.resolved, true) {
+            self.login_guard.succeed(&self.account, self.ip);
+        }
+    }
+}
+
+impl Drop for LoginAttempt {
+    fn drop(&mut self) {
+        if !self.resolved {
+            self.login_guard.release(&self.account, self.ip);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Attempts {
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    fn fail(&mut self, now: Instant, free_attempts: u32) {
+        self.failures += 1;
+        self.last_failure = Some(now);
+
+        if self.failures > free_attempts {
+            let exponent = (self.failures - free_attempts - 1).min(16);
+            let delay = (BASE_DELAY * 2u32.pow(exponent)).min(MAX_LOCKOUT);
+
+            self.locked_until = Some(now + delay);
+        }
+    }
+
+    // a lock the refunded attempt caused goes with it
+    fn refund(&mut self, free_attempts: u32) {
+        self.failures = self.failures.saturating_sub(1);
+
+        if self.failures <= free_attempts {
+            self.locked_until = None;
+        }
+    }
+
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        self.last_failure
+            .is_none_or(|last_failure| now.duration_since(last_failure) > FORGET_AFTER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::time::advance;
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn guard(max_tracked: usize) -> Arc<LoginGuard> {
+        LoginGuard::build(&LoginConfig {
+            account_free_attempts: 2,
+            ip_free_attempts: 5,
+            max_tracked,
+        })
+    }
+
+    fn fail(login_guard: &Arc<LoginGuard>, account: &str, ip: IpAddr) -> Result<(), Duration> {
+        login_guard.begin(account, ip)?.failed();
+
+        Ok(())
+    }
+
+    fn failures(login_guard: &LoginGuard, account: &str) -> Option<u32> {
+        let tracked = login_guard.tracked.lock().unwrap();
+
+        tracked
+            .accounts
+            .get(account)
+            .map(|attempts| attempts.failures)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lockout_doubles_after_the_free_attempts() {
+        let login_guard = guard(100);
+
+        fail(&login_guard, "alice", IP).unwrap();
+        fail(&login_guard, "alice", IP).unwrap();
+        fail(&login_guard, "alice", IP).unwrap();
+        assert_eq!(login_guard.begin("alice", IP).err(), Some(BASE_DELAY));
+
+        advance(BASE_DELAY).await;
+        fail(&login_guard, "alice", IP).unwrap();
+        assert_eq!(login_guard.begin("alice", IP).err(), Some(BASE_DELAY * 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn open_attempts_count_before_they_fail() {
+        let login_guard = guard(100);
+
+        // logins in flight count as failed, the next one waits as if they had failed
+        let first = login_guard.begin("alice", IP).unwrap();
+        let second = login_guard.begin("alice", IP).unwrap();
+        let third = login_guard.begin("alice", IP).unwrap();
+        assert!(login_guard.begin("alice", IP).is_err());
+
+        drop((first, second, third));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unresolved_attempts_are_released() {
+        let login_guard = guard(100);
+
+        fail(&login_guard, "alice", IP).unwrap();
+        drop(login_guard.begin("alice", IP).unwrap());
+
+        assert_eq!(failures(&login_guard, "alice"), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn success_forgets_the_account_but_not_the_ip() {
+        let login_guard = guard(100);
+
+        fail(&login_guard, "alice", IP).unwrap();
+        fail(&login_guard, "alice", IP).unwrap();
+        login_guard.begin("alice", IP).unwrap().succeeded();
+
+        assert_eq!(failures(&login_guard, "alice"), None);
+        let tracked = login_guard.tracked.lock().unwrap();
+        assert_eq!(tracked.ips[&IP].failures, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ip_is_locked_across_accounts() {
+        let login_guard = guard(100);
+
+        for account in ["a", "b", "c", "d", "e", "f"] {
+            fail(&login_guard, account, IP).unwrap();
+        }
+
+        assert!(login_guard.begin("g", IP).is_err());
+        assert!(
+            login_guard
+                .begin("g", IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+                .is_ok()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sweep_drops_forgotten_failures() {
+        let login_guard = guard(100);
+
+        fail(&login_guard, "alice", IP).unwrap();
+        advance(FORGET_AFTER / 2).await;
+        fail(&login_guard, "bob", IP).unwrap();
+
+        advance(FORGET_AFTER / 2 + Duration::from_secs(1)).await;
+        login_guard.sweep(Instant::now());
+
+        assert_eq!(failures(&login_guard, "alice"), None);
+        assert_eq!(failures(&login_guard, "bob"), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_map_forgets_the_oldest_failure() {
+        let login_guard = guard(2);
+
+        for account in ["a", "b", "c"] {
+            fail(&login_guard, account, IP).unwrap();
+            advance(Duration::from_secs(1)).await;
+        }
+
+        let tracked = login_guard.tracked.lock().unwrap();
+        assert_eq!(tracked.accounts.len(), 2);
+        assert!(!tracked.accounts.contains_key("a"));
+    }
+}
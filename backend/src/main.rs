@@ -1,12 +1,7 @@
+use clap::Parser;
 use dotenv::dotenv;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod db;
-mod handler;
-mod room_manager;
-mod router;
-mod server;
-mod session;
+use ws_chat_room::{config, server};
 
 #[tokio::main]
 async fn main() {
@@ -19,5 +14,17 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    server::run().await;
+    let cli = config::Cli::parse();
+    let config = match config::Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    match cli.command {
+        Some(config::Command::Migrate) => server::migrate(config).await,
+        None => server::run(config).await,
+    }
 }
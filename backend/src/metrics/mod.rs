@@ -0,0 +1,177 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use tokio::time::Instant;
+
+// everything `/metrics` exposes, one registry per app so tests don't share counters
+pub struct Metrics {
+    registry: Registry,
+    rooms: IntGauge,
+    sessions: IntGauge,
+    sockets: IntGauge,
+    room_messages: IntCounterVec,
+    broadcast_lagged: IntCounter,
+    writer_queue_depth: IntGauge,
+    insert_seconds: Histogram,
+    http_request_seconds: HistogramVec,
+}
+
+// values that are read when the scrape comes in instead of tracked on every change
+pub struct Snapshot {
+    pub rooms: usize,
+    pub sessions: usize,
+    pub writer_queue_depth: usize,
+}
+
+impl Metrics {
+    pub fn build() -> Arc<Metrics> {
+        let registry = Registry::new();
+
+        let metrics = Metrics {
+            rooms: register(&registry, IntGauge::new("ws_chat_rooms", "Open chat rooms")),
+            sessions: register(
+                &registry,
+                IntGauge::new("ws_chat_sessions", "Signed in sessions"),
+            ),
+            sockets: register(
+                &registry,
+                IntGauge::new("ws_chat_sockets", "Connected room WebSockets"),
+            ),
+            room_messages: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("ws_chat_room_messages_total", "Messages sent per room"),
+                    &["room_id"],
+                ),
+            ),
+            broadcast_lagged: register(
+                &registry,
+                IntCounter::new(
+                    "ws_chat_broadcast_lagged_total",
+                    "Times a room subscriber fell behind and missed broadcasts",
+                ),
+            ),
+            writer_queue_depth: register(
+                &registry,
+                IntGauge::new(
+                    "ws_chat_writer_queue_depth",
+                    "Messages waiting for the message writer",
+                ),
+            ),
+            insert_seconds: register(
+                &registry,
+                Histogram::with_opts(HistogramOpts::new(
+                    "ws_chat_writer_insert_seconds",
+                    "Duration of one message batch insert",
+                )),
+            ),
+            http_request_seconds: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "ws_chat_http_request_duration_seconds",
+                        "HTTP request durations by route",
+                    ),
+                    &["method", "route", "status"],
+                ),
+            ),
+            registry,
+        };
+
+        Arc::new(metrics)
+    }
+
+    // held for as long as a WebSocket is connected
+    pub fn socket_connected(self: &Arc<Self>) -> SocketGuard {
+        self.sockets.inc();
+
+        SocketGuard {
+            metrics: self.clone(),
+        }
+    }
+
+    pub fn message_sent(&self, room_id: &str) {
+        self.room_messages.with_label_values(&[room_id]).inc();
+    }
+
+    // closed rooms never come back, drop their series
+    pub fn room_closed(&self, room_id: &str) {
+        let _ = self.room_messages.remove_label_values(&[room_id]);
+    }
+
+    pub fn broadcast_lagged(&self) {
+        self.broadcast_lagged.inc();
+    }
+
+    pub fn insert_finished(&self, elapsed: Duration) {
+        self.insert_seconds.observe(elapsed.as_secs_f64());
+    }
+
+    // Prometheus text format
+    pub fn render(&self, snapshot: Snapshot) -> String {
+        self.rooms.set(snapshot.rooms as i64);
+        self.sessions.set(snapshot.sessions as i64);
+        self.writer_queue_depth
+            .set(snapshot.writer_queue_depth as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+// names are fixed, a failed registration is a bug
+fn register<M>(registry: &Registry, metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.unwrap();
+    registry.register(Box::new(metric.clone())).unwrap();
+
+    metric
+}
+
+pub struct SocketGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for SocketGuard {
+    fn drop(&mut self) {
+        self.metrics.sockets.dec();
+    }
+}
+
+// request durations labelled with the route pattern, unmatched paths share one label
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    metrics
+        .http_request_seconds
+        .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
@@ -0,0 +1,386 @@
+use axum::{extract::ws::Message, http::HeaderValue};
+use chrono::{DateTime, Utc};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::mpsc;
+
+use crate::room_manager::{Method, Reply, RoomCommand};
+
+pub mod v1;
+pub mod v2;
+
+// Sec-WebSocket-Protocol names, newest first so the server prefers it
+pub const SUPPORTED_PROTOCOLS: [&str; 4] = [V2_MSGPACK, V2_JSON, V1_MSGPACK, V1_JSON];
+
+const V1_JSON: &str = "ws_chat.v1";
+const V1_MSGPACK: &str = "ws_chat.v1.msgpack";
+const V2_JSON: &str = "ws_chat.v2";
+const V2_MSGPACK: &str = "ws_chat.v2.msgpack";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+// JSON travels in Text frames, MessagePack in Binary frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: Version,
+    pub encoding: Encoding,
+}
+
+impl Protocol {
+    // clients that don't negotiate a subprotocol speak the original format
+    pub fn from_selected(selected: Option<&HeaderValue>) -> Self {
+        let (version, encoding) = match selected.and_then(|value| value.to_str().ok()) {
+            Some(V1_MSGPACK) => (Version::V1, Encoding::MessagePack),
+            Some(V2_JSON) => (Version::V2, Encoding::Json),
+            Some(V2_MSGPACK) => (Version::V2, Encoding::MessagePack),
+            _ => (Version::V1, Encoding::Json),
+        };
+
+        Protocol { version, encoding }
+    }
+
+    pub fn encode(&self, event: ServerEvent) -> Message {
+        match self.version {
+            Version::V1 => self.encoding.serialize(&v1::StreamCommand::from(event)),
+            Version::V2 => self.encoding.serialize(&v2::ServerFrame::from(event)),
+        }
+    }
+
+    // None when the frame is not valid for the negotiated protocol
+    pub fn decode(&self, message: &Message) -> Option<ClientEvent> {
+        match self.version {
+            Version::V1 => self
+                .encoding
+                .deserialize::<v1::StreamCommand>(message)
+                .and_then(v1::StreamCommand::into_event),
+            Version::V2 => self
+                .encoding
+                .deserialize::<v2::ClientFrame>(message)
+                .map(ClientEvent::from),
+        }
+    }
+}
+
+impl Encoding {
+    fn serialize<T: Serialize>(&self, frame: &T) -> Message {
+        match self {
+            Encoding::Json => Message::text(serde_json::to_string(frame).unwrap()),
+            Encoding::MessagePack => Message::binary(rmp_serde::to_vec_named(frame).unwrap()),
+        }
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, message: &Message) -> Option<T> {
+        match (self, message) {
+            (Encoding::Json, Message::Text(text)) => serde_json::from_str(text.as_str()).ok(),
+            (Encoding::MessagePack, Message::Binary(bytes)) => rmp_serde::from_slice(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+// frame sent by a client, independent of the wire format
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    Join,
+    Send {
+        message: String,
+        request_id: Option<String>,
+    },
+}
+
+impl ClientEvent {
+    // shared by every transport so the room sees the same commands
+    pub fn into_room_command(
+        self,
+        user: &(i32, String),
+        room_id: &str,
+        reply: mpsc::Sender<Reply>,
+    ) -> RoomCommand {
+        match self {
+            ClientEvent::Join => RoomCommand::join(user.0, user.1.clone()),
+            ClientEvent::Send {
+                message,
+                request_id,
+            } => RoomCommand::send(user.0, user.1.clone(), room_id.to_string(), message)
+                .with_reply(request_id, reply),
+        }
+    }
+}
+
+// frame delivered to one connection, already resolved for its receiver
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    Join {
+        user: String,
+        is_bot: bool,
+    },
+    Leave {
+        user: String,
+        is_bot: bool,
+    },
+    Message {
+        sender: String,
+        is_bot: bool,
+        message: String,
+        is_self: bool,
+        request_id: Option<String>,
+    },
+    // system text, either for the whole room or a private command reply
+    Notice {
+        message: String,
+        request_id: Option<String>,
+    },
+    Ack {
+        request_id: String,
+        message_id: i64,
+        sent_at: DateTime<Utc>,
+    },
+    Error {
+        request_id: Option<String>,
+        code: String,
+        message: String,
+    },
+}
+
+impl ServerEvent {
+    // room broadcast as seen by `user_id`, None when there is nothing to deliver,
+    // whether the connection ends is up to `RoomCommand::ends_connection`
+    pub fn from_room_command(command: RoomCommand, user_id: i32) -> Option<Self> {
+        match command.method {
+            Method::Join => Some(ServerEvent::Join {
+                user: command.user.unwrap_or_default(),
+                is_bot: command.is_bot,
+            }),
+            Method::Leave => Some(ServerEvent::Leave {
+                user: command.user.unwrap_or_default(),
+                is_bot: command.is_bot,
+            }),
+            Method::Send => {
+                let is_self = command.user_id == Some(user_id);
+
+                Some(ServerEvent::Message {
+                    sender: command.user.unwrap_or_default(),
+                    is_bot: command.is_bot,
+                    message: command.message.unwrap_or_default(),
+                    is_self,
+                    request_id: if is_self { command.request_id } else { None },
+                })
+            }
+            Method::Notice => Some(ServerEvent::Notice {
+                message: command.message.unwrap_or_default(),
+                request_id: None,
+            }),
+            Method::Kick if command.user_id == Some(user_id) => Some(ServerEvent::Error {
+                request_id: None,
+                code: "KICKED".into(),
+                message: "You were kicked from the room".into(),
+            }),
+            Method::Kick | Method::Close => None,
+        }
+    }
+}
+
+impl From<Reply> for ServerEvent {
+    fn from(reply: Reply) -> Self {
+        match reply {
+            Reply::Ack {
+                request_id,
+                message_id,
+                sent_at,
+            } => ServerEvent::Ack {
+                request_id,
+                message_id,
+                sent_at,
+            },
+            Reply::Notice {
+                request_id,
+                message,
+            } => ServerEvent::Notice {
+                message,
+                request_id,
+            },
+            Reply::Error {
+                request_id,
+                code,
+                message,
+            } => ServerEvent::Error {
+                request_id,
+                code,
+                message,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    const ENCODINGS: [Encoding; 2] = [Encoding::Json, Encoding::MessagePack];
+
+    fn server_events() -> Vec<ServerEvent> {
+        vec![
+            ServerEvent::Join {
+                user: "alice".into(),
+                is_bot: false,
+            },
+            ServerEvent::Leave {
+                user: "alice".into(),
+                is_bot: false,
+            },
+            ServerEvent::Message {
+                sender: "alice".into(),
+                is_bot: false,
+                message: "hello".into(),
+                is_self: true,
+                request_id: Some("r1".into()),
+            },
+            ServerEvent::Message {
+                sender: "ci".into(),
+                is_bot: true,
+                message: "build passed".into(),
+                is_self: false,
+                request_id: None,
+            },
+            ServerEvent::Notice {
+                message: "alice is now al".into(),
+                request_id: None,
+            },
+            ServerEvent::Ack {
+                request_id: "r1".into(),
+                message_id: 42,
+                sent_at: Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap(),
+            },
+            ServerEvent::Error {
+                request_id: Some("r2".into()),
+                code: "RATE_LIMITED".into(),
+                message: "Slow down".into(),
+            },
+        ]
+    }
+
+    fn protocol(version: Version, encoding: Encoding) -> Protocol {
+        Protocol { version, encoding }
+    }
+
+    #[test]
+    fn subprotocol_selects_version_and_encoding() {
+        let selected =
+            |name: &'static str| Protocol::from_selected(Some(&HeaderValue::from_static(name)));
+
+        assert_eq!(selected(V1_JSON), protocol(Version::V1, Encoding::Json));
+        assert_eq!(
+            selected(V1_MSGPACK),
+            protocol(Version::V1, Encoding::MessagePack)
+        );
+        assert_eq!(selected(V2_JSON), protocol(Version::V2, Encoding::Json));
+        assert_eq!(
+            selected(V2_MSGPACK),
+            protocol(Version::V2, Encoding::MessagePack)
+        );
+        assert_eq!(
+            Protocol::from_selected(None),
+            protocol(Version::V1, Encoding::Json)
+        );
+    }
+
+    #[test]
+    fn v2_server_frames_round_trip() {
+        for encoding in ENCODINGS {
+            let protocol = protocol(Version::V2, encoding);
+
+            for event in server_events() {
+                let message = protocol.encode(event.clone());
+                let frame: v2::ServerFrame = encoding.deserialize(&message).unwrap();
+
+                assert_eq!(frame, v2::ServerFrame::from(event));
+            }
+        }
+    }
+
+    #[test]
+    fn v1_server_frames_round_trip() {
+        for encoding in ENCODINGS {
+            let protocol = protocol(Version::V1, encoding);
+
+            for event in server_events() {
+                let message = protocol.encode(event.clone());
+                let frame: v1::StreamCommand = encoding.deserialize(&message).unwrap();
+
+                assert_eq!(frame, v1::StreamCommand::from(event));
+            }
+        }
+    }
+
+    #[test]
+    fn v2_client_frames_decode() {
+        for encoding in ENCODINGS {
+            let protocol = protocol(Version::V2, encoding);
+            let send = v2::ClientFrame::Send {
+                content: "hello".into(),
+                request_id: Some("r1".into()),
+            };
+
+            assert_eq!(
+                protocol.decode(&encoding.serialize(&v2::ClientFrame::Join)),
+                Some(ClientEvent::Join)
+            );
+            assert_eq!(
+                protocol.decode(&encoding.serialize(&send)),
+                Some(ClientEvent::Send {
+                    message: "hello".into(),
+                    request_id: Some("r1".into()),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn v1_client_frames_decode() {
+        for encoding in ENCODINGS {
+            let protocol = protocol(Version::V1, encoding);
+            // the fields old clients actually send
+            let send = json!({ "method": "Send", "message": "hello", "request_id": "r1" });
+            let ack = json!({ "method": "Ack", "message": "" });
+
+            assert_eq!(
+                protocol.decode(&encoding.serialize(&send)),
+                Some(ClientEvent::Send {
+                    message: "hello".into(),
+                    request_id: Some("r1".into()),
+                })
+            );
+            // server-only methods are not accepted from clients
+            assert_eq!(protocol.decode(&encoding.serialize(&ack)), None);
+        }
+    }
+
+    #[test]
+    fn frames_in_the_other_encoding_are_refused() {
+        let join = v2::ClientFrame::Join;
+
+        assert_eq!(
+            protocol(Version::V2, Encoding::Json).decode(&Encoding::MessagePack.serialize(&join)),
+            None
+        );
+        assert_eq!(
+            protocol(Version::V2, Encoding::MessagePack).decode(&Encoding::Json.serialize(&join)),
+            None
+        );
+        assert_eq!(
+            protocol(Version::V2, Encoding::Json).decode(&Message::text("not json")),
+            None
+        );
+    }
+}
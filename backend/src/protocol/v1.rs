@@ -0,0 +1,121 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{ClientEvent, ServerEvent};
+
+// the original StreamCommand format, kept frozen for deployed clients
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct StreamCommand {
+    method: StreamMethod,
+    message: String,
+    #[serde(default)]
+    sender: String,
+    #[serde(default)]
+    is_self: bool,
+    // client-supplied id echoed back in Ack/Error frames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sent_at: Option<DateTime<Utc>>,
+}
+
+impl StreamCommand {
+    fn new(method: StreamMethod, message: String, sender: String, is_self: bool) -> Self {
+        StreamCommand {
+            method,
+            message,
+            sender,
+            is_self,
+            request_id: None,
+            message_id: None,
+            sent_at: None,
+        }
+    }
+
+    pub fn into_event(self) -> Option<ClientEvent> {
+        match self.method {
+            StreamMethod::Join => Some(ClientEvent::Join),
+            StreamMethod::Send => Some(ClientEvent::Send {
+                message: self.message,
+                request_id: self.request_id,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl From<ServerEvent> for StreamCommand {
+    fn from(event: ServerEvent) -> Self {
+        match event {
+            ServerEvent::Join { user, .. } => {
+                let message = format!("User {} join the room", user);
+
+                StreamCommand::new(StreamMethod::Join, message, "System".into(), false)
+            }
+            ServerEvent::Leave { user, .. } => {
+                let message = format!("User {} leave the room", user);
+
+                StreamCommand::new(StreamMethod::Leave, message, "System".into(), false)
+            }
+            // the frozen format has no bot flag
+            ServerEvent::Message {
+                sender,
+                message,
+                is_self,
+                request_id,
+                ..
+            } => {
+                let mut stream_command =
+                    StreamCommand::new(StreamMethod::Send, message, sender, is_self);
+                stream_command.request_id = request_id;
+
+                stream_command
+            }
+            ServerEvent::Notice {
+                message,
+                request_id,
+            } => {
+                let mut stream_command =
+                    StreamCommand::new(StreamMethod::Send, message, "System".into(), false);
+                stream_command.request_id = request_id;
+
+                stream_command
+            }
+            ServerEvent::Ack {
+                request_id,
+                message_id,
+                sent_at,
+            } => {
+                let mut stream_command =
+                    StreamCommand::new(StreamMethod::Ack, String::new(), "System".into(), true);
+                stream_command.request_id = Some(request_id);
+                stream_command.message_id = Some(message_id);
+                stream_command.sent_at = Some(sent_at);
+
+                stream_command
+            }
+            ServerEvent::Error {
+                request_id,
+                message,
+                ..
+            } => {
+                let mut stream_command =
+                    StreamCommand::new(StreamMethod::Error, message, "System".into(), true);
+                stream_command.request_id = request_id;
+
+                stream_command
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub enum StreamMethod {
+    Send,
+    Join,
+    Leave,
+    Ack,
+    Error,
+}
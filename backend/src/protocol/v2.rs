@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{ClientEvent, ServerEvent};
+
+// explicitly typed frames, new protocol features land here
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Join,
+    Send {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Joined {
+        user: String,
+        // set for bot accounts, never inferred from the name
+        #[serde(default)]
+        bot: bool,
+    },
+    Left {
+        user: String,
+        #[serde(default)]
+        bot: bool,
+    },
+    Message {
+        sender: String,
+        #[serde(default)]
+        bot: bool,
+        content: String,
+        own: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    Notice {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    Ack {
+        request_id: String,
+        message_id: i64,
+        sent_at: DateTime<Utc>,
+    },
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+        code: String,
+        message: String,
+    },
+}
+
+impl From<ServerEvent> for ServerFrame {
+    fn from(event: ServerEvent) -> Self {
+        match event {
+            ServerEvent::Join { user, is_bot } => ServerFrame::Joined { user, bot: is_bot },
+            ServerEvent::Leave { user, is_bot } => ServerFrame::Left { user, bot: is_bot },
+            ServerEvent::Message {
+                sender,
+                is_bot,
+                message,
+                is_self,
+                request_id,
+            } => ServerFrame::Message {
+                sender,
+                bot: is_bot,
+                content: message,
+                own: is_self,
+                request_id,
+            },
+            ServerEvent::Notice {
+                message,
+                request_id,
+            } => ServerFrame::Notice {
+                content: message,
+                request_id,
+            },
+            ServerEvent::Ack {
+                request_id,
+                message_id,
+                sent_at,
+            } => ServerFrame::Ack {
+                request_id,
+                message_id,
+                sent_at,
+            },
+            ServerEvent::Error {
+                request_id,
+                code,
+                message,
+            } => ServerFrame::Error {
+                request_id,
+                code,
+                message,
+            },
+        }
+    }
+}
+
+impl From<ClientFrame> for ClientEvent {
+    fn from(frame: ClientFrame) -> Self {
+        match frame {
+            ClientFrame::Join => ClientEvent::Join,
+            ClientFrame::Send {
+                content,
+                request_id,
+            } => ClientEvent::Send {
+                message: content,
+                request_id,
+            },
+        }
+    }
+}
@@ -0,0 +1,499 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+// violations older than this are forgotten
+const VIOLATION_WINDOW: Duration = Duration::from_secs(60);
+// repeated mutes double in length up to this factor
+const MAX_MUTE_FACTOR: u32 = 32;
+// mutes escalate again from the start after this long without one
+const MUTE_WINDOW: Duration = Duration::from_secs(60 * 60);
+// longest slow mode and base mute a room can set, larger values are clamped
+pub const MAX_SLOW_MODE_SECS: u64 = 24 * 60 * 60;
+pub const MAX_MUTE_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomLimits {
+    pub connection_burst: u32,
+    pub connection_per_minute: u32,
+    pub user_burst: u32,
+    pub user_per_minute: u32,
+    pub room_burst: u32,
+    pub room_per_minute: u32,
+    // minimum seconds between two messages of the same user, 0 disables slow mode
+    pub slow_mode_secs: u64,
+    // violations within a minute before the user is muted
+    pub mute_after: u32,
+    pub mute_secs: u64,
+}
+
+impl Default for RoomLimits {
+    fn default() -> Self {
+        RoomLimits {
+            connection_burst: 10,
+            connection_per_minute: 60,
+            user_burst: 10,
+            user_per_minute: 60,
+            room_burst: 100,
+            room_per_minute: 1200,
+            slow_mode_secs: 0,
+            mute_after: 5,
+            mute_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoomLimitsPatch {
+    connection_burst: Option<u32>,
+    connection_per_minute: Option<u32>,
+    user_burst: Option<u32>,
+    user_per_minute: Option<u32>,
+    room_burst: Option<u32>,
+    room_per_minute: Option<u32>,
+    slow_mode_secs: Option<u64>,
+    mute_after: Option<u32>,
+    mute_secs: Option<u64>,
+}
+
+impl RoomLimits {
+    pub fn apply(&mut self, patch: RoomLimitsPatch) {
+        let RoomLimitsPatch {
+            connection_burst,
+            connection_per_minute,
+            user_burst,
+            user_per_minute,
+            room_burst,
+            room_per_minute,
+            slow_mode_secs,
+            mute_after,
+            mute_secs,
+        } = patch;
+
+        self.connection_burst = connection_burst.unwrap_or(self.connection_burst).max(1);
+        self.connection_per_minute = connection_per_minute
+            .unwrap_or(self.connection_per_minute)
+            .max(1);
+        self.user_burst = user_burst.unwrap_or(self.user_burst).max(1);
+        self.user_per_minute = user_per_minute.unwrap_or(self.user_per_minute).max(1);
+        self.room_burst = room_burst.unwrap_or(self.room_burst).max(1);
+        self.room_per_minute = room_per_minute.unwrap_or(self.room_per_minute).max(1);
+        self.slow_mode_secs = slow_mode_secs
+            .unwrap_or(self.slow_mode_secs)
+            .min(MAX_SLOW_MODE_SECS);
+        self.mute_after = mute_after.unwrap_or(self.mute_after).max(1);
+        self.mute_secs = mute_secs.unwrap_or(self.mute_secs).min(MAX_MUTE_SECS);
+    }
+}
+
+// capacity and rate are passed on every call so limit changes apply immediately
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(burst: u32) -> Self {
+        TokenBucket {
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    // take one token, or return how long until one is available
+    pub fn try_take(&mut self, burst: u32, per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let per_sec = per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * per_sec).min(burst as f64);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+    }
+}
+
+#[derive(Debug)]
+pub enum Violation {
+    RateLimited(Duration),
+    RoomBusy(Duration),
+    SlowMode(Duration),
+    Muted(Duration),
+}
+
+impl Violation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Violation::RateLimited(_) | Violation::RoomBusy(_) => "RATE_LIMITED",
+            Violation::SlowMode(_) => "SLOW_MODE",
+            Violation::Muted(_) => "MUTED",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Violation::RateLimited(retry) => {
+                format!(
+                    "You are sending too fast, retry in {}s",
+                    retry.as_secs() + 1
+                )
+            }
+            Violation::RoomBusy(retry) => {
+                format!("Room is too busy, retry in {}s", retry.as_secs() + 1)
+            }
+            Violation::SlowMode(retry) => {
+                format!("Slow mode is on, wait {}s", retry.as_secs() + 1)
+            }
+            Violation::Muted(remaining) => {
+                format!("You are muted for {}s", remaining.as_secs() + 1)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UserState {
+    bucket: TokenBucket,
+    last_message: Option<Instant>,
+    violations: Vec<Instant>,
+    muted_until: Option<Instant>,
+    mutes: u32,
+    // end of the last escalated mute, the mute window counts from here
+    escalated_until: Option<Instant>,
+}
+
+impl UserState {
+    fn new(limits: &RoomLimits) -> Self {
+        UserState {
+            bucket: TokenBucket::new(limits.user_burst),
+            last_message: None,
+            violations: Vec::new(),
+            muted_until: None,
+            mutes: 0,
+            escalated_until: None,
+        }
+    }
+}
+
+// per-room and per-user limits, owned by the room task
+pub struct RoomLimiter {
+    room_bucket: TokenBucket,
+    users: HashMap<i32, UserState>,
+}
+
+impl RoomLimiter {
+    pub fn new(limits: &RoomLimits) -> Self {
+        RoomLimiter {
+            room_bucket: TokenBucket::new(limits.room_burst),
+            users: HashMap::new(),
+        }
+    }
+
+    // mute set by the room owner, does not count towards escalation
+    pub fn mute(&mut self, user_id: i32, duration: Duration, limits: &RoomLimits) {
+        let user = self
+            .users
+            .entry(user_id)
+            .or_insert_with(|| UserState::new(limits));
+
+        user.muted_until = Some(Instant::now() + duration);
+    }
+
+    pub fn check(
+        &mut self,
+        user_id: i32,
+        limits: &RoomLimits,
+        now: Instant,
+    ) -> Result<(), Violation> {
+        let user = self
+            .users
+            .entry(user_id)
+            .or_insert_with(|| UserState::new(limits));
+
+        if let Some(muted_until) = user.muted_until {
+            if muted_until > now {
+                return Err(Violation::Muted(muted_until - now));
+            }
+            user.muted_until = None;
+        }
+
+        // limits from the config file are not clamped like patched ones
+        let slow_mode = Duration::from_secs(limits.slow_mode_secs.min(MAX_SLOW_MODE_SECS));
+        let slowed_until = user
+            .last_message
+            .and_then(|last| last.checked_add(slow_mode));
+        let violation = match slowed_until {
+            Some(until) if now < until => Some(Violation::SlowMode(until - now)),
+            _ => user
+                .bucket
+                .try_take(limits.user_burst, limits.user_per_minute, now)
+                .err()
+                .map(Violation::RateLimited),
+        };
+
+        if let Some(violation) = violation {
+            // repeated abuse escalates to a mute
+            user.violations
+                .retain(|violated_at| now.duration_since(*violated_at) < VIOLATION_WINDOW);
+            user.violations.push(now);
+
+            if user.violations.len() as u32 >= limits.mute_after {
+                if user
+                    .escalated_until
+                    .is_some_and(|until| now.saturating_duration_since(until) >= MUTE_WINDOW)
+                {
+                    user.mutes = 0;
+                }
+
+                let factor = 2u32.saturating_pow(user.mutes).min(MAX_MUTE_FACTOR);
+                let mute =
+                    Duration::from_secs(limits.mute_secs.min(MAX_MUTE_SECS)).saturating_mul(factor);
+                let until = now.checked_add(mute);
+
+                user.violations.clear();
+                user.mutes = user.mutes.saturating_add(1);
+                user.muted_until = until;
+                user.escalated_until = until;
+
+                return Err(Violation::Muted(mute));
+            }
+
+            return Err(violation);
+        }
+
+        self.room_bucket
+            .try_take(limits.room_burst, limits.room_per_minute, now)
+            .map_err(Violation::RoomBusy)?;
+
+        user.last_message = Some(now);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn limits() -> RoomLimits {
+        RoomLimits {
+            user_burst: 2,
+            user_per_minute: 60,
+            mute_after: 3,
+            mute_secs: 10,
+            ..RoomLimits::default()
+        }
+    }
+
+    // sends until the user is muted and returns the mute
+    fn abuse(limiter: &mut RoomLimiter, limits: &RoomLimits, now: Instant) -> Duration {
+        loop {
+            if let Err(Violation::Muted(mute)) = limiter.check(1, limits, now) {
+                return mute;
+            }
+        }
+    }
+
+    #[test]
+    fn bucket_allows_the_burst_then_waits_for_a_token() {
+        let mut bucket = TokenBucket::new(2);
+        let now = Instant::now();
+
+        assert!(bucket.try_take(2, 60, now).is_ok());
+        assert!(bucket.try_take(2, 60, now).is_ok());
+        assert_eq!(bucket.try_take(2, 60, now), Err(SECOND));
+    }
+
+    #[test]
+    fn bucket_refills_at_the_rate_up_to_the_burst() {
+        let mut bucket = TokenBucket::new(2);
+        let now = Instant::now();
+        bucket.try_take(2, 60, now).unwrap();
+        bucket.try_take(2, 60, now).unwrap();
+
+        // half a token after half a second
+        let wait = bucket.try_take(2, 60, now + SECOND / 2).unwrap_err();
+        assert_eq!(wait, SECOND / 2);
+
+        // a long pause still only refills the burst
+        let later = now + SECOND * 60;
+        assert!(bucket.try_take(2, 60, later).is_ok());
+        assert!(bucket.try_take(2, 60, later).is_ok());
+        assert!(bucket.try_take(2, 60, later).is_err());
+    }
+
+    #[test]
+    fn bucket_follows_changed_limits() {
+        let mut bucket = TokenBucket::new(10);
+        let now = Instant::now();
+
+        // a lower burst caps the tokens that are already there
+        assert!(bucket.try_take(1, 60, now).is_ok());
+        assert!(bucket.try_take(1, 60, now).is_err());
+    }
+
+    #[test]
+    fn user_is_rate_limited_after_the_burst() {
+        let limits = limits();
+        let mut limiter = RoomLimiter::new(&limits);
+        let now = Instant::now();
+
+        assert!(limiter.check(1, &limits, now).is_ok());
+        assert!(limiter.check(1, &limits, now).is_ok());
+        assert!(matches!(
+            limiter.check(1, &limits, now),
+            Err(Violation::RateLimited(_))
+        ));
+
+        // other users have their own bucket
+        assert!(limiter.check(2, &limits, now).is_ok());
+    }
+
+    #[test]
+    fn slow_mode_spaces_out_messages() {
+        let limits = RoomLimits {
+            slow_mode_secs: 5,
+            ..limits()
+        };
+        let mut limiter = RoomLimiter::new(&limits);
+        let now = Instant::now();
+
+        assert!(limiter.check(1, &limits, now).is_ok());
+        assert!(matches!(
+            limiter.check(1, &limits, now + SECOND * 2),
+            Err(Violation::SlowMode(wait)) if wait == SECOND * 3
+        ));
+        assert!(limiter.check(1, &limits, now + SECOND * 5).is_ok());
+    }
+
+    #[test]
+    fn busy_room_limits_every_user() {
+        let limits = RoomLimits {
+            room_burst: 2,
+            ..limits()
+        };
+        let mut limiter = RoomLimiter::new(&limits);
+        let now = Instant::now();
+
+        assert!(limiter.check(1, &limits, now).is_ok());
+        assert!(limiter.check(2, &limits, now).is_ok());
+        assert!(matches!(
+            limiter.check(3, &limits, now),
+            Err(Violation::RoomBusy(_))
+        ));
+    }
+
+    #[test]
+    fn repeated_violations_mute_for_longer_each_time() {
+        let limits = limits();
+        let mut limiter = RoomLimiter::new(&limits);
+        let now = Instant::now();
+
+        let mute = abuse(&mut limiter, &limits, now);
+        assert_eq!(mute, SECOND * 10);
+        assert!(matches!(
+            limiter.check(1, &limits, now + SECOND * 9),
+            Err(Violation::Muted(_))
+        ));
+
+        let now = now + mute;
+        assert_eq!(abuse(&mut limiter, &limits, now), SECOND * 20);
+    }
+
+    #[test]
+    fn violations_outside_the_window_are_forgotten() {
+        let limits = limits();
+        let mut limiter = RoomLimiter::new(&limits);
+        let now = Instant::now();
+
+        // two violations, then a third one after the window
+        for _ in 0..4 {
+            let _ = limiter.check(1, &limits, now);
+        }
+        let later = now + VIOLATION_WINDOW;
+        assert!(limiter.check(1, &limits, later).is_ok());
+        assert!(limiter.check(1, &limits, later).is_ok());
+        assert!(matches!(
+            limiter.check(1, &limits, later),
+            Err(Violation::RateLimited(_))
+        ));
+    }
+
+    #[test]
+    fn mute_escalation_decays_after_the_window() {
+        let limits = limits();
+        let mut limiter = RoomLimiter::new(&limits);
+        let mut now = Instant::now();
+
+        for expected in [10, 20, 40] {
+            let mute = abuse(&mut limiter, &limits, now);
+            assert_eq!(mute, SECOND * expected);
+            now += mute;
+        }
+
+        // behaved for a whole window after the last mute ended
+        now += MUTE_WINDOW;
+        assert_eq!(abuse(&mut limiter, &limits, now), SECOND * 10);
+    }
+
+    #[test]
+    fn owner_mute_does_not_escalate() {
+        let limits = limits();
+        let mut limiter = RoomLimiter::new(&limits);
+
+        limiter.mute(1, SECOND * 300, &limits);
+        assert!(matches!(
+            limiter.check(1, &limits, Instant::now()),
+            Err(Violation::Muted(_))
+        ));
+
+        let now = Instant::now() + SECOND * 300;
+        assert_eq!(abuse(&mut limiter, &limits, now), SECOND * 10);
+    }
+
+    #[test]
+    fn patched_durations_are_clamped() {
+        let mut limits = RoomLimits::default();
+        let patch: RoomLimitsPatch = serde_json::from_value(serde_json::json!({
+            "slow_mode_secs": u64::MAX,
+            "mute_secs": u64::MAX,
+        }))
+        .unwrap();
+
+        limits.apply(patch);
+
+        assert_eq!(limits.slow_mode_secs, MAX_SLOW_MODE_SECS);
+        assert_eq!(limits.mute_secs, MAX_MUTE_SECS);
+    }
+
+    #[test]
+    fn huge_configured_durations_do_not_overflow() {
+        let limits = RoomLimits {
+            slow_mode_secs: u64::MAX,
+            mute_secs: u64::MAX,
+            ..limits()
+        };
+        let mut limiter = RoomLimiter::new(&limits);
+        let now = Instant::now();
+
+        assert!(limiter.check(1, &limits, now).is_ok());
+        assert!(matches!(
+            limiter.check(1, &limits, now),
+            Err(Violation::SlowMode(_))
+        ));
+        assert_eq!(
+            abuse(&mut limiter, &limits, now),
+            SECOND * MAX_MUTE_SECS as u32
+        );
+    }
+}
@@ -0,0 +1,583 @@
+use std::{collections::HashMap, time::Duration};
+
+use rand::Rng;
+
+use crate::{
+    rate_limit::{MAX_MUTE_SECS, RoomLimiter, RoomLimits},
+    room_manager::RoomCommand,
+};
+
+const MAX_NICK_LEN: usize = 32;
+const MAX_TOPIC_LEN: usize = 200;
+const DEFAULT_MUTE_SECS: u64 = 60;
+
+// what a slash command does to the room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    // stored and broadcast like a normal message
+    Say(String),
+    // system notice for everyone in the room
+    Announce(String),
+    // private notice for the sender only
+    Reply(String),
+    // private error for the sender only
+    Deny { code: &'static str, message: String },
+    // disconnect a user and tell the room
+    Kick { user_id: i32, notice: String },
+    // take a nick once the room task checked it is no one's username
+    Rename { nick: String, notice: String },
+}
+
+impl Effect {
+    pub fn deny(code: &'static str, message: &str) -> Self {
+        Effect::Deny {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn usage(spec: &CommandSpec) -> Self {
+        Effect::deny("BAD_COMMAND", &format!("Usage: {}", spec.usage))
+    }
+}
+
+// the arguments of a `/name args` message and who sent it
+pub struct Invocation<'a> {
+    pub args: &'a str,
+    pub user_id: i32,
+    // nick in this room, or the account name
+    pub user: &'a str,
+    pub account: &'a str,
+}
+
+// what the room task lends to a command while it runs
+pub struct CommandContext<'a> {
+    pub owner_id: i32,
+    pub limiter: &'a mut RoomLimiter,
+    pub limits: &'a RoomLimits,
+}
+
+struct CommandSpec {
+    name: &'static str,
+    usage: &'static str,
+    description: &'static str,
+    owner_only: bool,
+    run: fn(&mut RoomCommands, &Invocation, &mut CommandContext) -> Effect,
+}
+
+const REGISTRY: &[CommandSpec] = &[
+    CommandSpec {
+        name: "me",
+        usage: "/me <action>",
+        description: "describe what you are doing",
+        owner_only: false,
+        run: me,
+    },
+    CommandSpec {
+        name: "topic",
+        usage: "/topic [text]",
+        description: "show the room topic, the owner can change it",
+        owner_only: false,
+        run: topic,
+    },
+    CommandSpec {
+        name: "nick",
+        usage: "/nick [name]",
+        description: "change your name in this room, no name resets it",
+        owner_only: false,
+        run: nick,
+    },
+    CommandSpec {
+        name: "kick",
+        usage: "/kick <user>",
+        description: "disconnect a user from the room",
+        owner_only: true,
+        run: kick,
+    },
+    CommandSpec {
+        name: "mute",
+        usage: "/mute <user> [seconds]",
+        description: "stop a user from sending for a while",
+        owner_only: true,
+        run: mute,
+    },
+    CommandSpec {
+        name: "roll",
+        usage: "/roll [NdM]",
+        description: "roll dice, 1d6 by default",
+        owner_only: false,
+        run: roll,
+    },
+    CommandSpec {
+        name: "help",
+        usage: "/help",
+        description: "list the available commands",
+        owner_only: false,
+        run: help,
+    },
+];
+
+// per-room command state, owned by the room task
+#[derive(Debug, Default)]
+pub struct RoomCommands {
+    topic: Option<String>,
+    nicks: HashMap<i32, String>,
+    // lowercase names seen in the room, used to resolve command targets
+    members: HashMap<String, i32>,
+}
+
+impl RoomCommands {
+    pub fn new() -> Self {
+        RoomCommands::default()
+    }
+
+    pub fn remember(&mut self, user_id: i32, user: &str) {
+        self.members.insert(user.to_lowercase(), user_id);
+    }
+
+    // the previous nick is freed for others, the account name stays
+    pub fn rename(&mut self, user_id: i32, account: &str, nick: &str) {
+        self.forget_nick(user_id, account);
+        self.nicks.insert(user_id, nick.to_string());
+        self.remember(user_id, nick);
+    }
+
+    fn forget_nick(&mut self, user_id: i32, account: &str) -> Option<String> {
+        let old = self.nicks.remove(&user_id)?;
+        let name = old.to_lowercase();
+
+        if name != account.to_lowercase() && self.members.get(&name) == Some(&user_id) {
+            self.members.remove(&name);
+        }
+
+        Some(old)
+    }
+
+    // name shown for a user's messages in this room
+    pub fn nick(&self, user_id: i32) -> Option<&String> {
+        self.nicks.get(&user_id)
+    }
+
+    // None when the message is not a registered command, e.g. `/shrug` or a bot's command,
+    // `//text` sends `/text` as is
+    pub fn intercept(&mut self, command: &RoomCommand, ctx: &mut CommandContext) -> Option<Effect> {
+        let message = command.message.as_deref()?.trim_start();
+        let body = message.strip_prefix('/')?;
+
+        if body.starts_with('/') {
+            return Some(Effect::Say(body.to_string()));
+        }
+
+        let (name, args) = match body.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (body.trim_end(), ""),
+        };
+
+        let name = name.to_lowercase();
+        let spec = REGISTRY.iter().find(|spec| spec.name == name)?;
+
+        let user_id = command.user_id.unwrap_or_default();
+        let account = command.user.as_deref().unwrap_or_default();
+        let nick = self.nicks.get(&user_id).cloned();
+        let invocation = Invocation {
+            args,
+            user_id,
+            user: nick.as_deref().unwrap_or(account),
+            account,
+        };
+
+        if spec.owner_only && user_id != ctx.owner_id {
+            return Some(Effect::deny(
+                "FORBIDDEN",
+                &format!("Only the room owner can use /{}", spec.name),
+            ));
+        }
+
+        Some((spec.run)(self, &invocation, ctx))
+    }
+
+    fn target(&self, name: &str) -> Option<i32> {
+        let name = name.trim_start_matches('@').to_lowercase();
+
+        self.members.get(&name).copied()
+    }
+}
+
+fn spec(name: &str) -> &'static CommandSpec {
+    REGISTRY.iter().find(|spec| spec.name == name).unwrap()
+}
+
+fn me(_commands: &mut RoomCommands, invocation: &Invocation, _ctx: &mut CommandContext) -> Effect {
+    if invocation.args.is_empty() {
+        return Effect::usage(spec("me"));
+    }
+
+    Effect::Say(format!("* {} {}", invocation.user, invocation.args))
+}
+
+fn topic(commands: &mut RoomCommands, invocation: &Invocation, ctx: &mut CommandContext) -> Effect {
+    if invocation.args.is_empty() {
+        return match &commands.topic {
+            Some(topic) => Effect::Reply(format!("Topic: {}", topic)),
+            None => Effect::Reply("No topic set".into()),
+        };
+    }
+
+    if invocation.user_id != ctx.owner_id {
+        return Effect::deny("FORBIDDEN", "Only the room owner can change the topic");
+    }
+
+    if invocation.args.chars().count() > MAX_TOPIC_LEN {
+        return Effect::deny(
+            "BAD_COMMAND",
+            &format!("Topics are at most {} characters", MAX_TOPIC_LEN),
+        );
+    }
+
+    commands.topic = Some(invocation.args.to_string());
+
+    Effect::Announce(format!(
+        "{} set the topic to: {}",
+        invocation.user, invocation.args
+    ))
+}
+
+fn nick(commands: &mut RoomCommands, invocation: &Invocation, _ctx: &mut CommandContext) -> Effect {
+    let nick = invocation.args;
+
+    if nick.is_empty() {
+        return match commands.forget_nick(invocation.user_id, invocation.account) {
+            Some(old) => {
+                Effect::Announce(format!("{} is now known as {}", old, invocation.account))
+            }
+            None => Effect::usage(spec("nick")),
+        };
+    }
+
+    // no impersonating system notices
+    if nick.chars().count() > MAX_NICK_LEN || nick.eq_ignore_ascii_case("system") {
+        return Effect::deny("BAD_COMMAND", "That name is not allowed");
+    }
+
+    // names of other members are taken
+    if commands
+        .target(nick)
+        .is_some_and(|user_id| user_id != invocation.user_id)
+    {
+        return Effect::deny("BAD_COMMAND", "That name is already in use");
+    }
+
+    Effect::Rename {
+        nick: nick.to_string(),
+        notice: format!("{} is now known as {}", invocation.user, nick),
+    }
+}
+
+fn kick(commands: &mut RoomCommands, invocation: &Invocation, _ctx: &mut CommandContext) -> Effect {
+    let Some(name) = invocation.args.split_whitespace().next() else {
+        return Effect::usage(spec("kick"));
+    };
+
+    match commands.target(name) {
+        Some(user_id) if user_id == invocation.user_id => {
+            Effect::deny("BAD_COMMAND", "You cannot kick yourself")
+        }
+        Some(user_id) => Effect::Kick {
+            user_id,
+            notice: format!("{} was kicked by {}", name, invocation.user),
+        },
+        None => Effect::deny("NOT_FOUND", &format!("No user named {} in this room", name)),
+    }
+}
+
+fn mute(commands: &mut RoomCommands, invocation: &Invocation, ctx: &mut CommandContext) -> Effect {
+    let mut args = invocation.args.split_whitespace();
+    let Some(name) = args.next() else {
+        return Effect::usage(spec("mute"));
+    };
+
+    let secs = match args.next() {
+        Some(secs) => match secs.parse::<u64>() {
+            Ok(secs) if (1..=MAX_MUTE_SECS).contains(&secs) => secs,
+            _ => return Effect::usage(spec("mute")),
+        },
+        None => DEFAULT_MUTE_SECS,
+    };
+
+    match commands.target(name) {
+        Some(user_id) if user_id == invocation.user_id => {
+            Effect::deny("BAD_COMMAND", "You cannot mute yourself")
+        }
+        Some(user_id) => {
+            ctx.limiter
+                .mute(user_id, Duration::from_secs(secs), ctx.limits);
+
+            Effect::Announce(format!(
+                "{} was muted for {}s by {}",
+                name, secs, invocation.user
+            ))
+        }
+        None => Effect::deny("NOT_FOUND", &format!("No user named {} in this room", name)),
+    }
+}
+
+fn roll(
+    _commands: &mut RoomCommands,
+    invocation: &Invocation,
+    _ctx: &mut CommandContext,
+) -> Effect {
+    let dice = invocation.args.split_whitespace().next().unwrap_or("1d6");
+    let parsed = dice
+        .to_lowercase()
+        .split_once('d')
+        .and_then(|(count, sides)| {
+            let count: u32 = if count.is_empty() {
+                1
+            } else {
+                count.parse().ok()?
+            };
+
+            Some((count, sides.parse::<u32>().ok()?))
+        });
+
+    let Some((count, sides)) =
+        parsed.filter(|(count, sides)| (1..=20).contains(count) && (2..=1000).contains(sides))
+    else {
+        return Effect::deny(
+            "BAD_COMMAND",
+            "Roll between 1 and 20 dice with 2 to 1000 sides, e.g. /roll 2d6",
+        );
+    };
+
+    let mut rng = rand::rng();
+    let rolls: Vec<u32> = (0..count).map(|_| rng.random_range(1..=sides)).collect();
+    let total: u32 = rolls.iter().sum();
+    let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+
+    Effect::Announce(format!(
+        "{} rolled {}d{}: {} = {}",
+        invocation.user,
+        count,
+        sides,
+        rolls.join(" + "),
+        total
+    ))
+}
+
+fn help(_commands: &mut RoomCommands, invocation: &Invocation, ctx: &mut CommandContext) -> Effect {
+    let is_owner = invocation.user_id == ctx.owner_id;
+    let lines: Vec<String> = REGISTRY
+        .iter()
+        .filter(|spec| is_owner || !spec.owner_only)
+        .map(|spec| format!("{} - {}", spec.usage, spec.description))
+        .collect();
+
+    Effect::Reply(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: i32 = 1;
+    const BOB: i32 = 2;
+
+    struct Room {
+        commands: RoomCommands,
+        limiter: RoomLimiter,
+        limits: RoomLimits,
+    }
+
+    impl Room {
+        fn new() -> Self {
+            let limits = RoomLimits::default();
+            let mut commands = RoomCommands::new();
+            commands.remember(OWNER, "alice");
+            commands.remember(BOB, "bob");
+
+            Room {
+                commands,
+                limiter: RoomLimiter::new(&limits),
+                limits,
+            }
+        }
+
+        fn run(&mut self, user_id: i32, message: &str) -> Option<Effect> {
+            let account = if user_id == OWNER { "alice" } else { "bob" };
+            let command = RoomCommand::send(
+                user_id,
+                account.to_string(),
+                "room".to_string(),
+                message.to_string(),
+            );
+            let mut ctx = CommandContext {
+                owner_id: OWNER,
+                limiter: &mut self.limiter,
+                limits: &self.limits,
+            };
+
+            self.commands.intercept(&command, &mut ctx)
+        }
+
+        fn code(&mut self, user_id: i32, message: &str) -> &'static str {
+            match self.run(user_id, message) {
+                Some(Effect::Deny { code, .. }) => code,
+                effect => panic!("{} was not denied: {:?}", message, effect),
+            }
+        }
+    }
+
+    #[test]
+    fn unregistered_commands_are_plain_messages() {
+        let mut room = Room::new();
+
+        assert_eq!(room.run(BOB, "hello"), None);
+        assert_eq!(room.run(BOB, "/shrug"), None);
+        assert_eq!(room.run(BOB, "/usr/bin/env is missing"), None);
+        assert_eq!(room.run(BOB, "/"), None);
+    }
+
+    #[test]
+    fn double_slash_sends_the_text() {
+        let mut room = Room::new();
+
+        assert_eq!(
+            room.run(BOB, "//roll 2d6"),
+            Some(Effect::Say("/roll 2d6".into()))
+        );
+    }
+
+    #[test]
+    fn names_ignore_case_and_args_are_trimmed() {
+        let mut room = Room::new();
+
+        assert_eq!(
+            room.run(BOB, "  /ME   waves  "),
+            Some(Effect::Say("* bob waves".into()))
+        );
+        assert_eq!(room.code(BOB, "/me"), "BAD_COMMAND");
+    }
+
+    #[test]
+    fn owner_only_commands_are_refused() {
+        let mut room = Room::new();
+
+        assert_eq!(room.code(BOB, "/kick alice"), "FORBIDDEN");
+        assert_eq!(room.code(BOB, "/mute alice"), "FORBIDDEN");
+        assert_eq!(room.code(BOB, "/topic rust"), "FORBIDDEN");
+        assert_eq!(
+            room.run(BOB, "/topic"),
+            Some(Effect::Reply("No topic set".into()))
+        );
+
+        assert_eq!(
+            room.run(OWNER, "/topic rust"),
+            Some(Effect::Announce("alice set the topic to: rust".into()))
+        );
+        assert_eq!(
+            room.run(BOB, "/topic"),
+            Some(Effect::Reply("Topic: rust".into()))
+        );
+    }
+
+    #[test]
+    fn help_lists_owner_commands_for_the_owner() {
+        let mut room = Room::new();
+
+        let Some(Effect::Reply(help)) = room.run(BOB, "/help") else {
+            panic!("no help");
+        };
+        assert!(help.contains("/roll"));
+        assert!(!help.contains("/kick"));
+
+        let Some(Effect::Reply(help)) = room.run(OWNER, "/help") else {
+            panic!("no help");
+        };
+        assert!(help.contains("/kick"));
+    }
+
+    #[test]
+    fn kick_resolves_members_by_name() {
+        let mut room = Room::new();
+
+        assert_eq!(
+            room.run(OWNER, "/kick @Bob"),
+            Some(Effect::Kick {
+                user_id: BOB,
+                notice: "@Bob was kicked by alice".into(),
+            })
+        );
+        assert_eq!(room.code(OWNER, "/kick alice"), "BAD_COMMAND");
+        assert_eq!(room.code(OWNER, "/kick carol"), "NOT_FOUND");
+        assert_eq!(room.code(OWNER, "/kick"), "BAD_COMMAND");
+    }
+
+    #[test]
+    fn mute_checks_the_duration() {
+        let mut room = Room::new();
+
+        assert_eq!(room.code(OWNER, "/mute bob 0"), "BAD_COMMAND");
+        assert_eq!(room.code(OWNER, "/mute bob soon"), "BAD_COMMAND");
+        assert_eq!(room.code(OWNER, "/mute bob 86401"), "BAD_COMMAND");
+        assert_eq!(
+            room.run(OWNER, "/mute bob"),
+            Some(Effect::Announce("bob was muted for 60s by alice".into()))
+        );
+    }
+
+    #[test]
+    fn roll_checks_the_dice() {
+        let mut room = Room::new();
+
+        assert_eq!(room.code(BOB, "/roll 0d6"), "BAD_COMMAND");
+        assert_eq!(room.code(BOB, "/roll 21d6"), "BAD_COMMAND");
+        assert_eq!(room.code(BOB, "/roll 1d1"), "BAD_COMMAND");
+        assert_eq!(room.code(BOB, "/roll dice"), "BAD_COMMAND");
+        assert!(matches!(
+            room.run(BOB, "/roll d20"),
+            Some(Effect::Announce(notice)) if notice.starts_with("bob rolled 1d20: ")
+        ));
+    }
+
+    #[test]
+    fn nick_is_checked_before_the_rename() {
+        let mut room = Room::new();
+
+        assert_eq!(room.code(BOB, "/nick System"), "BAD_COMMAND");
+        assert_eq!(
+            room.code(BOB, &format!("/nick {}", "b".repeat(33))),
+            "BAD_COMMAND"
+        );
+        assert_eq!(room.code(BOB, "/nick ALICE"), "BAD_COMMAND");
+        assert_eq!(room.code(BOB, "/nick"), "BAD_COMMAND");
+
+        // the room task takes the nick once no account has it
+        assert_eq!(
+            room.run(BOB, "/nick robert"),
+            Some(Effect::Rename {
+                nick: "robert".into(),
+                notice: "bob is now known as robert".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn rename_frees_the_previous_nick() {
+        let mut room = Room::new();
+
+        room.commands.rename(BOB, "bob", "robert");
+        assert_eq!(room.commands.target("robert"), Some(BOB));
+        assert_eq!(room.commands.nick(BOB).map(String::as_str), Some("robert"));
+
+        room.commands.rename(BOB, "bob", "bobby");
+        assert_eq!(room.commands.target("robert"), None);
+        assert_eq!(room.commands.target("bob"), Some(BOB));
+
+        assert_eq!(
+            room.run(BOB, "/nick"),
+            Some(Effect::Announce("bobby is now known as bob".into()))
+        );
+        assert_eq!(room.commands.target("bobby"), None);
+        assert_eq!(room.commands.nick(BOB), None);
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use crate::room_manager::RoomCommand;
+
+// recent room broadcasts, numbered so polling transports can resume
+pub struct RoomHistory {
+    next_seq: u64,
+    capacity: usize,
+    events: VecDeque<RoomCommand>,
+}
+
+// what a resuming client missed
+#[derive(Debug)]
+pub struct Replay {
+    pub events: Vec<RoomCommand>,
+    // some missed events are no longer kept, or the cursor is from before the room restarted
+    pub reset: bool,
+    // the newest event so far, where a reset client continues from
+    pub last_seq: u64,
+}
+
+impl RoomHistory {
+    pub fn new(capacity: usize) -> Self {
+        RoomHistory {
+            next_seq: 1,
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // assign the next sequence number and keep a copy without the reply channel
+    pub fn record(&mut self, command: &mut RoomCommand) {
+        command.seq = Some(self.next_seq);
+        self.next_seq += 1;
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(command.without_reply());
+    }
+
+    pub fn after(&self, seq: u64) -> Replay {
+        let oldest = self
+            .events
+            .front()
+            .and_then(|command| command.seq)
+            .unwrap_or(self.next_seq);
+
+        Replay {
+            events: self
+                .events
+                .iter()
+                .filter(|command| command.seq.is_some_and(|event_seq| event_seq > seq))
+                .cloned()
+                .collect(),
+            reset: seq.saturating_add(1) < oldest || seq > self.last_seq(),
+            last_seq: self.last_seq(),
+        }
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_with(capacity: usize, events: usize) -> RoomHistory {
+        let mut history = RoomHistory::new(capacity);
+
+        for _ in 0..events {
+            history.record(&mut RoomCommand::join(1, "alice".to_string()));
+        }
+
+        history
+    }
+
+    fn seqs(replay: &Replay) -> Vec<u64> {
+        replay
+            .events
+            .iter()
+            .filter_map(|command| command.seq)
+            .collect()
+    }
+
+    #[test]
+    fn replays_the_events_after_the_cursor() {
+        let history = history_with(4, 3);
+        let replay = history.after(1);
+
+        assert_eq!(seqs(&replay), vec![2, 3]);
+        assert!(!replay.reset);
+        assert_eq!(replay.last_seq, 3);
+    }
+
+    #[test]
+    fn an_up_to_date_cursor_gets_nothing() {
+        let history = history_with(4, 3);
+        let replay = history.after(3);
+
+        assert!(replay.events.is_empty());
+        assert!(!replay.reset);
+    }
+
+    #[test]
+    fn an_empty_room_is_not_a_gap() {
+        let replay = RoomHistory::new(4).after(0);
+
+        assert!(replay.events.is_empty());
+        assert!(!replay.reset);
+        assert_eq!(replay.last_seq, 0);
+    }
+
+    #[test]
+    fn events_that_fell_out_are_a_reset() {
+        // 1 to 3 are gone, 4 to 6 are kept
+        let history = history_with(3, 6);
+
+        let replay = history.after(2);
+        assert_eq!(seqs(&replay), vec![4, 5, 6]);
+        assert!(replay.reset);
+
+        // exactly the oldest kept event is next, nothing is missing
+        let replay = history.after(3);
+        assert_eq!(seqs(&replay), vec![4, 5, 6]);
+        assert!(!replay.reset);
+    }
+
+    #[test]
+    fn a_cursor_from_the_future_is_a_reset() {
+        // e.g. a client of a room that was created again after a restart
+        let history = history_with(4, 2);
+        let replay = history.after(10);
+
+        assert!(replay.events.is_empty());
+        assert!(replay.reset);
+        assert_eq!(replay.last_seq, 2);
+    }
+}
@@ -1,146 +1,236 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::Arc,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
-use sqlx::{Error, Pool, Postgres};
+use chrono::{DateTime, Utc};
 use tokio::{
-    sync::{Mutex, broadcast, mpsc},
-    time::sleep,
+    sync::{
+        Mutex, broadcast,
+        mpsc::{self, error::TrySendError},
+    },
+    time::{Instant, sleep, sleep_until},
 };
 use uuid::Uuid;
 
+use crate::{
+    config::RoomConfig,
+    db::{Db, MessageWriter, StorageError},
+    metrics::Metrics,
+    rate_limit::{RoomLimiter, RoomLimits},
+    webhook::WebhookDispatcher,
+};
+
+const IDLE_CLOSE_REASON: &str = "Room closed";
+
+mod commands;
+use commands::{CommandContext, Effect, RoomCommands};
+mod history;
+pub use history::{Replay, RoomHistory};
+
 pub struct RoomManager {
     pub rooms: Arc<Mutex<HashMap<String, RoomState>>>,
-    pub idle: Duration,
+    pub config: RoomConfig,
+    pub webhooks: Arc<WebhookDispatcher>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl RoomManager {
-    pub fn build(idle: Duration) -> Arc<RoomManager> {
+    pub fn build(
+        config: RoomConfig,
+        webhooks: Arc<WebhookDispatcher>,
+        metrics: Arc<Metrics>,
+    ) -> Arc<RoomManager> {
         Arc::new(RoomManager {
             rooms: Arc::new(Mutex::new(HashMap::new())),
-            idle,
+            config,
+            webhooks,
+            metrics,
         })
     }
 
     pub async fn create(
         self: Arc<Self>,
-        pool: Pool<Postgres>,
+        db: Db,
         room_name: &str,
-        db_message_sender: mpsc::Sender<RoomCommand>,
+        owner_id: i32,
+        limits: RoomLimits,
+        message_writer: MessageWriter,
     ) -> Result<
         (
             mpsc::Sender<RoomCommand>,
             broadcast::Receiver<RoomCommand>,
             String,
         ),
-        Error,
+        StorageError,
     > {
-        let (channel_sender, channel_receiver) = mpsc::channel(128);
-        let (subscriber_sender, subscriber_receiver) = broadcast::channel(128);
+        let (channel_sender, channel_receiver) = mpsc::channel(self.config.channel_capacity);
+        let (subscriber_sender, subscriber_receiver) =
+            broadcast::channel(self.config.broadcast_capacity);
+        let room_state = RoomState {
+            channel_sender: channel_sender.clone(),
+            subscriber_sender,
+            history: Arc::new(Mutex::new(RoomHistory::new(self.config.history_size))),
+            owner_id,
+            limits: Arc::new(Mutex::new(limits)),
+            bots: Arc::new(Mutex::new(HashSet::new())),
+        };
 
         //create room_id
         let room_id = Uuid::new_v4();
         let room_manager = self.clone();
         let mut rooms = room_manager.rooms.lock().await;
-        rooms.insert(
-            room_id.to_string(),
-            RoomState {
-                channel_sender: channel_sender.clone(),
-                subscriber_sender: subscriber_sender.clone(),
-            },
-        );
+        rooms.insert(room_id.to_string(), room_state.clone());
 
         //release mutex lock
         drop(rooms);
 
         //insert room to DB
-        let query_str = r#"
-            insert into rooms(id, room_name)
-            values($1, $2);
-          "#;
-
-        let result = sqlx::query(query_str)
-            .bind(room_id)
-            .bind(room_name)
-            .execute(&pool)
-            .await;
-
-        match result {
+        match db.rooms().create(room_id, room_name).await {
             Ok(_query_result) => {
                 //spawn room handler
-                self.create_room(
-                    channel_receiver,
-                    subscriber_sender,
-                    pool.clone(),
-                    room_id,
-                    db_message_sender,
-                );
+                self.create_room(channel_receiver, room_state, db, room_id, message_writer);
 
                 let sender = channel_sender.clone();
                 let receiver = subscriber_receiver;
 
-                return Ok((sender, receiver, room_id.to_string()));
-            }
-            Err(err) => {
-                return Err(err);
+                Ok((sender, receiver, room_id.to_string()))
             }
+            Err(err) => Err(err),
         }
     }
 
     fn create_room(
         self: Arc<Self>,
         mut channel_receiver: mpsc::Receiver<RoomCommand>,
-        subscriber_sender: broadcast::Sender<RoomCommand>,
-        pool: Pool<Postgres>,
+        room_state: RoomState,
+        db: Db,
         room_id: Uuid,
-        db_message_sender: mpsc::Sender<RoomCommand>,
+        message_writer: MessageWriter,
     ) {
         tokio::spawn(async move {
-            let idle = self.idle;
+            let RoomState {
+                subscriber_sender,
+                history,
+                owner_id,
+                limits,
+                ..
+            } = room_state;
+            let idle = self.config.idle_timeout();
             let close_time = Arc::new(Mutex::new(Instant::now() + idle));
             let close_time_for_timer = close_time.clone();
             let close_time_for_room = close_time.clone();
+            let mut limiter = RoomLimiter::new(&*limits.lock().await);
+            let mut commands = RoomCommands::new();
+            let publisher = RoomPublisher {
+                room_id,
+                history: history.clone(),
+                subscriber_sender: subscriber_sender.clone(),
+                webhooks: self.webhooks.clone(),
+                metrics: self.metrics.clone(),
+            };
 
-            tokio::select! {
+            let close_reason = tokio::select! {
                 _ = async {
-                  let mut expiry = close_time_for_timer.lock().await.clone();
+                  let mut expiry = *close_time_for_timer.lock().await;
 
+                  // wakes at the deadline, activity in between pushes it back
                   while Instant::now() < expiry {
-                    sleep(idle).await;
+                    sleep_until(expiry).await;
 
                     let timer = close_time_for_timer.lock().await;
-                    expiry = timer.clone();
+                    expiry = *timer;
                   };
-                } => {}
-                _ = async {
-                  while let Some(command) = channel_receiver.recv().await {
+                } => None,
+                reason = async {
+                  while let Some(mut command) = channel_receiver.recv().await {
                     let mut time = close_time_for_room.lock().await;
                     *time = Instant::now() + idle;
 
+                    if let (Method::Join | Method::Send, Some(user_id), Some(user)) =
+                        (&command.method, command.user_id, &command.user)
+                    {
+                        commands.remember(user_id, user);
+                    }
+
+                    if let Method::Send = command.method {
+                        let user_id = command.user_id.unwrap_or_default();
+                        let limits = limits.lock().await;
+
+                        if let Err(violation) = limiter.check(user_id, &limits, Instant::now()) {
+                            command.reply_error(violation.code(), &violation.message());
+                            continue;
+                        }
+
+                        // slash commands are handled here and never stored as typed
+                        let mut ctx = CommandContext {
+                            owner_id,
+                            limiter: &mut limiter,
+                            limits: &limits,
+                        };
+
+                        match commands.intercept(&command, &mut ctx) {
+                            Some(Effect::Say(message)) => command.message = Some(message),
+                            Some(Effect::Rename { nick, notice }) => {
+                                drop(limits);
+                                let effect =
+                                    rename(&db, &mut commands, &command, &nick, notice).await;
+                                apply_effect(effect, &command, &history, &subscriber_sender).await;
+                                continue;
+                            }
+                            Some(effect) => {
+                                apply_effect(effect, &command, &history, &subscriber_sender).await;
+                                continue;
+                            }
+                            None => {}
+                        }
+
+                        if let Some(nick) = commands.nick(user_id) {
+                            command.user = Some(nick.clone());
+                        }
+                    }
+
                     match command.method {
+                        // delete_room announces the close
                         Method::Close => {
-                            break;
+                            return command.message;
                         }
                         Method::Send => {
-                            let _ = subscriber_sender.send(command.clone());
+                            //insert message to db, the writer publishes it and acks the sender
+                            let queued = message_writer.send(command, publisher.clone()).await;
 
-                            //insert message to db
-                            if let Err(err) = db_message_sender.send(command).await{
-                                eprintln!("Failed to insert message: {:?}", err);
+                            if let Err(err) = queued {
+                                tracing::error!("Failed to queue message: {:?}", err);
+
+                                err.0
+                                    .reply_error("NOT_STORED", "Message could not be stored");
                             }
                         }
+                        // kicks only reach the sockets, they take no place in the history
+                        Method::Kick => {
+                            self.webhooks.dispatch(room_id, &command);
+                            let _ = subscriber_sender.send(command);
+                        }
+                        // sent under the history lock, the writer publishes stored messages in
+                        // between and subscribers must see seqs in order
                         _ => {
+                            let mut history = history.lock().await;
+                            history.record(&mut command);
+
+                            self.webhooks.dispatch(room_id, &command);
                             let _ = subscriber_sender.send(command);
                         }
                     }
                 }
-              } => {}
+
+                None
+              } => reason,
             };
+            let close_reason = close_reason.unwrap_or_else(|| IDLE_CLOSE_REASON.to_string());
 
             //remove room from hashmap
-            self.delete_room(pool, room_id).await;
+            self.delete_room(db, room_id, &close_reason).await;
         });
     }
 
@@ -161,39 +251,179 @@ impl RoomManager {
         None
     }
 
-    pub async fn delete_room(self: Arc<Self>, pool: Pool<Postgres>, room_id: Uuid) {
+    // owner and live limits of a room, used by the settings endpoints
+    pub async fn settings(self: Arc<Self>, room_id: &str) -> Option<(i32, Arc<Mutex<RoomLimits>>)> {
+        let rooms = self.rooms.lock().await;
+
+        rooms
+            .get(room_id)
+            .map(|room_state| (room_state.owner_id, room_state.limits.clone()))
+    }
+
+    pub async fn bots(self: Arc<Self>, room_id: &str) -> Option<Arc<Mutex<HashSet<i32>>>> {
+        let rooms = self.rooms.lock().await;
+
+        rooms.get(room_id).map(|room_state| room_state.bots.clone())
+    }
+
+    pub async fn has_bot(self: Arc<Self>, room_id: &str, bot_id: i32) -> bool {
+        match self.bots(room_id).await {
+            Some(bots) => bots.lock().await.contains(&bot_id),
+            None => false,
+        }
+    }
+
+    pub async fn history(self: Arc<Self>, room_id: &str) -> Option<Arc<Mutex<RoomHistory>>> {
+        let rooms = self.rooms.lock().await;
+
+        rooms
+            .get(room_id)
+            .map(|room_state| room_state.history.clone())
+    }
+
+    // close every room and wait until they are gone
+    pub async fn shutdown(self: &Arc<Self>, reason: &str) {
+        let channel_senders: Vec<_> = self
+            .rooms
+            .lock()
+            .await
+            .values()
+            .map(|room_state| room_state.channel_sender.clone())
+            .collect();
+
+        tracing::info!("Closing {} rooms...", channel_senders.len());
+
+        for channel_sender in channel_senders {
+            let _ = channel_sender.send(RoomCommand::close(reason)).await;
+        }
+
+        while !self.rooms.lock().await.is_empty() {
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    pub async fn delete_room(self: Arc<Self>, db: Db, room_id: Uuid, reason: &str) {
         let room_manager = self.clone();
         let mut rooms = room_manager.rooms.lock().await;
 
         //send close message and remove room from rooms;
-        match rooms.get(&room_id.to_string()) {
-            Some(room) => {
-                let broadcast_sender = room.subscriber_sender.clone();
+        if let Some(room) = rooms.get(&room_id.to_string()) {
+            let broadcast_sender = room.subscriber_sender.clone();
 
-                let _ = broadcast_sender.send(RoomCommand::close());
+            let _ = broadcast_sender.send(RoomCommand::close(reason));
+            self.webhooks.dispatch(room_id, &RoomCommand::close(reason));
 
-                rooms.remove(&room_id.to_string());
+            rooms.remove(&room_id.to_string());
+            self.metrics.room_closed(&room_id.to_string());
 
-                //TODO: update closed_at;
-                let query_str = r#"
-                    update rooms set closed_at = now() where id = $1;
-                "#;
+            let _ = db
+                .rooms()
+                .close(room_id)
+                .await
+                .map_err(|err| eprintln!("Failed to close room: {:?}", err));
+        }
+    }
+}
+
+// a nick is checked against every username, not only the members the room has seen
+async fn rename(
+    db: &Db,
+    commands: &mut RoomCommands,
+    command: &RoomCommand,
+    nick: &str,
+    notice: String,
+) -> Effect {
+    let user_id = command.user_id.unwrap_or_default();
+
+    match db.accounts().find_username(nick).await {
+        Ok(Some(owner_id)) if owner_id != user_id => {
+            Effect::deny("BAD_COMMAND", "That name is already in use")
+        }
+        Ok(_) => {
+            commands.rename(user_id, command.user.as_deref().unwrap_or_default(), nick);
+
+            Effect::Announce(notice)
+        }
+        Err(err) => {
+            tracing::error!("Failed to check nick: {}", err);
 
-                let _ = sqlx::query(query_str)
-                    .bind(room_id)
-                    .execute(&pool)
-                    .await
-                    .map_err(|err| eprintln!("Failed to close room: {:?}", err));
+            Effect::deny("INTERNAL_SERVER_ERROR", "The name could not be checked")
+        }
+    }
+}
+
+// room side of a slash command, the issuer gets a private reply when it asked for one
+async fn apply_effect(
+    effect: Effect,
+    command: &RoomCommand,
+    history: &Mutex<RoomHistory>,
+    subscriber_sender: &broadcast::Sender<RoomCommand>,
+) {
+    // recorded and sent under one lock, like every event that gets a seq
+    let announce = |mut notice: RoomCommand| async move {
+        let mut history = history.lock().await;
+        history.record(&mut notice);
+        let _ = subscriber_sender.send(notice);
+    };
+
+    match effect {
+        // handled by the room task
+        Effect::Say(_) | Effect::Rename { .. } => {}
+        Effect::Announce(message) => {
+            if command.request_id.is_some() {
+                command.reply_notice(&message);
             }
 
-            None => {}
+            announce(RoomCommand::notice(message)).await;
+        }
+        Effect::Reply(message) => command.reply_notice(&message),
+        Effect::Deny { code, message } => command.reply_error(code, &message),
+        Effect::Kick { user_id, notice } => {
+            let _ = subscriber_sender.send(RoomCommand::kick(user_id));
+
+            if command.request_id.is_some() {
+                command.reply_notice(&notice);
+            }
+
+            announce(RoomCommand::notice(notice)).await;
         }
     }
 }
 
+#[derive(Clone)]
 pub struct RoomState {
     pub channel_sender: mpsc::Sender<RoomCommand>,
     pub subscriber_sender: broadcast::Sender<RoomCommand>,
+    pub history: Arc<Mutex<RoomHistory>>,
+    pub owner_id: i32,
+    pub limits: Arc<Mutex<RoomLimits>>,
+    // bot accounts the owner added to the room
+    pub bots: Arc<Mutex<HashSet<i32>>>,
+}
+
+// what a room does with a message once the writer stored it, a message that fails
+// to store reaches neither subscribers, the history nor webhooks
+#[derive(Clone)]
+pub struct RoomPublisher {
+    pub room_id: Uuid,
+    pub history: Arc<Mutex<RoomHistory>>,
+    pub subscriber_sender: broadcast::Sender<RoomCommand>,
+    pub webhooks: Arc<WebhookDispatcher>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl RoomPublisher {
+    // the history lock is held until the event is sent, so broadcasts keep seq order
+    // with the joins, leaves and notices the room task sends meanwhile
+    pub async fn publish(&self, command: &RoomCommand) {
+        let mut event = command.without_reply();
+        let mut history = self.history.lock().await;
+        history.record(&mut event);
+
+        self.metrics.message_sent(&self.room_id.to_string());
+        self.webhooks.dispatch(self.room_id, &event);
+        let _ = self.subscriber_sender.send(event);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -202,17 +432,27 @@ pub struct RoomCommand {
     pub room_id: Option<String>,
     pub user_id: Option<i32>,
     pub user: Option<String>,
+    // the user is a bot account, sent as a flag so no name can pass for one
+    pub is_bot: bool,
     pub message: Option<String>,
+    pub request_id: Option<String>,
+    pub reply: Option<mpsc::Sender<Reply>>,
+    // position in the room history, set by the room task
+    pub seq: Option<u64>,
 }
 
 impl RoomCommand {
-    pub fn join(user: String) -> Self {
+    pub fn join(user_id: i32, user: String) -> Self {
         RoomCommand {
             method: Method::Join,
             room_id: None,
-            user_id: None,
+            user_id: Some(user_id),
             user: Some(user),
             message: None,
+            request_id: None,
+            reply: None,
+            seq: None,
+            is_bot: false,
         }
     }
 
@@ -223,26 +463,136 @@ impl RoomCommand {
             user_id: Some(user_id),
             user: Some(user),
             message: Some(message),
+            request_id: None,
+            reply: None,
+            seq: None,
+            is_bot: false,
         }
     }
 
-    pub fn leave(user: String) -> Self {
+    pub fn by_bot(mut self, is_bot: bool) -> Self {
+        self.is_bot = is_bot;
+
+        self
+    }
+
+    // attach the client request id and the connection that should receive the ack
+    pub fn with_reply(mut self, request_id: Option<String>, reply: mpsc::Sender<Reply>) -> Self {
+        self.request_id = request_id;
+        self.reply = Some(reply);
+
+        self
+    }
+
+    // copy for broadcasting, subscribers never need the reply channel
+    pub fn without_reply(&self) -> Self {
+        RoomCommand {
+            reply: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn reply_ack(&self, message_id: i64, sent_at: DateTime<Utc>) {
+        if let Some(request_id) = &self.request_id {
+            self.reply(Reply::Ack {
+                request_id: request_id.clone(),
+                message_id,
+                sent_at,
+            });
+        }
+    }
+
+    pub fn reply_notice(&self, message: &str) {
+        self.reply(Reply::Notice {
+            request_id: self.request_id.clone(),
+            message: message.into(),
+        });
+    }
+
+    pub fn reply_error(&self, code: &str, message: &str) {
+        self.reply(Reply::Error {
+            request_id: self.request_id.clone(),
+            code: code.into(),
+            message: message.into(),
+        });
+    }
+
+    // never waits, a connection that stops reading must not hold up its room or the message writer
+    fn reply(&self, reply: Reply) {
+        let Some(sender) = &self.reply else {
+            return;
+        };
+
+        if let Err(TrySendError::Full(_reply)) = sender.try_send(reply) {
+            tracing::warn!("Reply channel is full, dropping a reply");
+        }
+    }
+
+    pub fn leave(user_id: i32, user: String) -> Self {
         RoomCommand {
             method: Method::Leave,
             room_id: None,
-            user_id: None,
+            user_id: Some(user_id),
             user: Some(user),
             message: None,
+            request_id: None,
+            reply: None,
+            seq: None,
+            is_bot: false,
         }
     }
 
-    pub fn close() -> Self {
+    // system notice for the whole room
+    pub fn notice(message: String) -> Self {
         RoomCommand {
-            method: Method::Close,
+            method: Method::Notice,
             room_id: None,
             user_id: None,
             user: None,
+            message: Some(message),
+            request_id: None,
+            reply: None,
+            seq: None,
+            is_bot: false,
+        }
+    }
+
+    // disconnects every connection of `user_id`
+    pub fn kick(user_id: i32) -> Self {
+        RoomCommand {
+            method: Method::Kick,
+            room_id: None,
+            user_id: Some(user_id),
+            user: None,
             message: None,
+            request_id: None,
+            reply: None,
+            seq: None,
+            is_bot: false,
+        }
+    }
+
+    // whether a connection of `user_id` must close after this command
+    pub fn ends_connection(&self, user_id: i32) -> bool {
+        match self.method {
+            Method::Close => true,
+            Method::Kick => self.user_id == Some(user_id),
+            _ => false,
+        }
+    }
+
+    // the reason is shown to clients as the room goes away
+    pub fn close(reason: &str) -> Self {
+        RoomCommand {
+            method: Method::Close,
+            room_id: None,
+            user_id: None,
+            user: None,
+            message: Some(reason.to_string()),
+            request_id: None,
+            reply: None,
+            seq: None,
+            is_bot: false,
         }
     }
 }
@@ -252,5 +602,78 @@ pub enum Method {
     Send,
     Leave,
     Join,
+    Notice,
+    Kick,
     Close,
 }
+
+// private responses for the connection that issued a command
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Ack {
+        request_id: String,
+        message_id: i64,
+        sent_at: DateTime<Utc>,
+    },
+    Notice {
+        request_id: Option<String>,
+        message: String,
+    },
+    Error {
+        request_id: Option<String>,
+        code: String,
+        message: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, db::MemoryStorage};
+
+    // joins go out from the room task while the writer publishes stored messages
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn broadcasts_follow_the_history_order() {
+        let mut config = Config::default();
+        config.room.broadcast_capacity = 2048;
+        let db: Db = Arc::new(MemoryStorage::new());
+        let metrics = Metrics::build();
+        let (message_writer, _db_writer) =
+            MessageWriter::spawn(db.clone(), &config.messages, metrics.clone());
+        let room_manager = RoomManager::build(
+            config.room,
+            WebhookDispatcher::build(db.clone(), config.webhook),
+            metrics,
+        );
+        let limits = RoomLimits {
+            user_burst: 10_000,
+            user_per_minute: 10_000,
+            room_burst: 10_000,
+            room_per_minute: 10_000,
+            ..RoomLimits::default()
+        };
+        let (sender, mut receiver, room_id) = room_manager
+            .create(db, "lobby", 1, limits, message_writer)
+            .await
+            .unwrap();
+        let rounds = 500;
+
+        for round in 0..rounds {
+            let message = RoomCommand::send(1, "alice".into(), room_id.clone(), round.to_string());
+            sender.send(message).await.unwrap();
+            sender
+                .send(RoomCommand::join(2, "bob".into()))
+                .await
+                .unwrap();
+        }
+
+        let mut last_seq = 0;
+        for _ in 0..rounds * 2 {
+            let event = receiver.recv().await.unwrap();
+            let seq = event.seq.unwrap();
+
+            assert!(seq > last_seq, "seq {} broadcast after {}", seq, last_seq);
+            last_seq = seq;
+        }
+    }
+}
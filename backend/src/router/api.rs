@@ -1,35 +1,84 @@
 use axum::{
-    Router,
-    routing::{get, post},
+    Router, middleware,
+    routing::{delete, get, patch, post},
 };
 use std::sync::Arc;
 
 use crate::{
-    handler::{auth, create_room, join_room, login, logout, rooms, signup},
+    handler::{
+        add_room_bot, auth, bots, create_bot, create_incoming_webhook, create_room, create_token,
+        create_webhook, delete_incoming_webhook, delete_webhook, disable_totp, enroll_totp,
+        incoming_webhooks, join_room, login, login_totp, logout, message_writer_stats, poll_room,
+        post_incoming_webhook, remove_room_bot, require_auth, revoke_all_sessions, revoke_session,
+        revoke_token, room_events, room_limits, rooms, send_message, sessions, signup, tokens,
+        update_limits, verify_totp, webhook_deliveries, webhooks,
+    },
     router::AppState,
 };
 
-pub fn api_router() -> Router<Arc<AppState>> {
-    let get_router = Router::new()
+pub fn api_router(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    // reachable without a session
+    let public_router = Router::new()
         .route("/logout", get(logout))
+        .route("/signup", post(signup))
+        .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
+        // the hook token is the credential
+        .route("/hooks/{token}", post(post_incoming_webhook));
+
+    let get_router = Router::new()
         .route("/auth", get(auth))
         .route("/create_room", get(create_room))
         .route("/join_room", get(join_room))
-        .route("/rooms", get(rooms));
+        .route("/rooms", get(rooms))
+        .route("/sessions", get(sessions))
+        .route("/tokens", get(tokens))
+        .route("/bots", get(bots))
+        .route("/stats/messages", get(message_writer_stats))
+        .route("/rooms/{room_id}/events", get(room_events))
+        .route("/rooms/{room_id}/poll", get(poll_room))
+        .route("/rooms/{room_id}/limits", get(room_limits))
+        .route("/rooms/{room_id}/webhooks", get(webhooks))
+        .route("/rooms/{room_id}/incoming_webhooks", get(incoming_webhooks))
+        .route(
+            "/rooms/{room_id}/webhooks/{id}/deliveries",
+            get(webhook_deliveries),
+        );
 
     let post_router = Router::new()
-        .route("/signup", post(signup))
-        .route("/login", post(login));
+        .route("/totp/enroll", post(enroll_totp))
+        .route("/totp/verify", post(verify_totp))
+        .route("/tokens", post(create_token))
+        .route("/bots", post(create_bot))
+        .route("/rooms/{room_id}/bots", post(add_room_bot))
+        .route("/rooms/{room_id}/webhooks", post(create_webhook))
+        .route(
+            "/rooms/{room_id}/incoming_webhooks",
+            post(create_incoming_webhook),
+        )
+        .route("/rooms/{room_id}/messages", post(send_message));
 
-    let patch_router = Router::new();
+    let patch_router = Router::new().route("/rooms/{room_id}/limits", patch(update_limits));
 
-    let delete_router = Router::new();
+    let delete_router = Router::new()
+        .route("/totp", delete(disable_totp))
+        .route("/sessions", delete(revoke_all_sessions))
+        .route("/sessions/{id}", delete(revoke_session))
+        .route("/tokens/{id}", delete(revoke_token))
+        .route("/rooms/{room_id}/bots/{bot_id}", delete(remove_room_bot))
+        .route("/rooms/{room_id}/webhooks/{id}", delete(delete_webhook))
+        .route(
+            "/rooms/{room_id}/incoming_webhooks/{id}",
+            delete(delete_incoming_webhook),
+        );
 
-    let api = Router::new()
+    // every other route needs a valid session
+    let protected_router = Router::new()
         .merge(get_router)
         .merge(post_router)
         .merge(patch_router)
-        .merge(delete_router);
+        .merge(delete_router)
+        .route_layer(middleware::from_fn_with_state(app_state, require_auth));
 
-    api
+    Router::new().merge(public_router).merge(protected_router)
 }
@@ -1,46 +1,64 @@
-use axum::{Extension, Router};
-use sqlx::{Pool, Postgres};
+use axum::{Router, middleware};
 use std::sync::Arc;
-use tokio::sync::mpsc;
 
 mod api;
 use api::api_router;
+mod monitoring;
+use monitoring::{internal_router, monitoring_router};
 mod static_file;
 use static_file::static_router;
 
 use crate::{
-    room_manager::{RoomCommand, RoomManager},
+    config::Config,
+    db::{Db, MessageWriter, WriterProbe},
+    health::Health,
+    login_guard::LoginGuard,
+    metrics::{Metrics, track_requests},
+    room_manager::RoomManager,
     session::SessionManager,
+    two_factor::PendingLogins,
 };
 
-pub async fn router(
-    pool: Pool<Postgres>,
-    session_manager: Arc<SessionManager>,
-    room_manager: Arc<RoomManager>,
-    db_message_sender: mpsc::Sender<RoomCommand>,
-) -> Router {
-    let app_state = AppState {
-        pool,
-        session_manager,
-        room_manager,
-    };
-
-    let api_router = api_router();
+pub async fn router(app_state: Arc<AppState>) -> Router {
+    let api_router = api_router(app_state.clone());
     let static_router = static_router();
 
     let app = Router::new()
         .merge(static_router)
+        .merge(monitoring_router())
         .nest("/api", api_router)
-        .layer(Extension(db_message_sender))
-        .with_state(Arc::new(app_state));
+        .layer(middleware::from_fn_with_state(
+            app_state.metrics.clone(),
+            track_requests,
+        ))
+        .with_state(app_state);
 
     tracing::info!("Router init...");
 
     app
 }
 
+// served on `server.metrics_bind` over plain HTTP, never on the public listener
+pub fn metrics_router(metrics_state: Arc<MetricsState>) -> Router {
+    internal_router().with_state(metrics_state)
+}
+
 pub struct AppState {
-    pub pool: Pool<Postgres>,
+    pub config: Arc<Config>,
+    pub db: Db,
     pub session_manager: Arc<SessionManager>,
     pub room_manager: Arc<RoomManager>,
+    pub login_guard: Arc<LoginGuard>,
+    pub pending_logins: Arc<PendingLogins>,
+    pub message_writer: MessageWriter,
+    pub metrics: Arc<Metrics>,
+    pub health: Arc<Health>,
+}
+
+// what /metrics reads, no `MessageWriter` so the listener never keeps the writer from draining
+pub struct MetricsState {
+    pub metrics: Arc<Metrics>,
+    pub room_manager: Arc<RoomManager>,
+    pub session_manager: Arc<SessionManager>,
+    pub message_writer: WriterProbe,
 }
@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use axum::{Router, routing::get};
+
+use crate::{
+    handler::{healthz, metrics, readyz},
+    router::{AppState, MetricsState},
+};
+
+// probed by the orchestrator, outside of `/api` and without a session
+pub fn monitoring_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+// scraped by Prometheus on the internal listener only, the series are labelled with room ids
+pub fn internal_router() -> Router<Arc<MetricsState>> {
+    Router::new().route("/metrics", get(metrics))
+}
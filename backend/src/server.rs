@@ -1,50 +1,253 @@
-use axum_server::tls_rustls::RustlsConfig;
-use std::{net::SocketAddr, path::PathBuf, time::Duration};
+use axum::Router;
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::TcpListener,
+    signal,
+    sync::oneshot,
+    task::JoinHandle,
+    time::{Instant, sleep, timeout_at},
+};
 
 use crate::{
-    db::{db_connection, db_messages_connection},
+    config::Config,
+    db::{self, Db, MessageWriter},
+    health::Health,
+    login_guard::LoginGuard,
+    metrics::Metrics,
     room_manager::RoomManager,
-    router::router,
-    session::SessionManager,
+    router::{AppState, MetricsState, metrics_router, router},
+    session::{SessionEnd, SessionManager},
+    two_factor::PendingLogins,
+    webhook::WebhookDispatcher,
 };
 
-pub async fn run() {
-    let pool = db_connection().await;
-    let session_manager = SessionManager::build(Duration::from_secs(30 * 60));
-    let room_manager = RoomManager::build(Duration::from_secs(30 * 60));
-    let db_message_sender = db_messages_connection().await;
-    let router = router(
-        pool,
-        session_manager.clone(),
-        room_manager.clone(),
-        db_message_sender.clone(),
-    )
-    .await;
+pub async fn run(config: Config) {
+    let config = Arc::new(config);
+    let db = db::connect(&config.database).await;
 
-    //run session background checker
-    let session_manager_for_bg = session_manager.clone();
-    session_manager_for_bg.run_checker();
+    if config.database.auto_migrate {
+        apply_migrations(&db).await;
+    }
+
+    if let Err(err) = db.check_schema().await {
+        tracing::error!("{}", err);
+        std::process::exit(1);
+    }
+
+    let App {
+        router,
+        metrics_router,
+        room_manager,
+        session_manager,
+        health,
+        db_writer,
+    } = app(config.clone(), db).await;
 
     //tls config
-    let config = RustlsConfig::from_pem_file(
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("self_signed_cert")
-            .join("cert.pem"),
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("self_signed_cert")
-            .join("key.pem"),
-    )
-    .await
-    .unwrap();
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8000 as u16));
+    let tls_config = RustlsConfig::from_pem_file(&config.tls.cert, &config.tls.key)
+        .await
+        .unwrap();
+    health.serve_tls(config.tls.clone());
+
+    let metrics_addr = config.server.metrics_bind;
+    let metrics_listener = match TcpListener::bind(metrics_addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!(
+                "Failed to bind the metrics listener on {}: {}",
+                metrics_addr,
+                err
+            );
+            std::process::exit(1);
+        }
+    };
+
+    tracing::info!("Serving metrics on {}...", metrics_addr);
+
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(metrics_listener, metrics_router).await {
+            tracing::error!("Metrics server error: {}", err);
+        }
+    });
+
+    let addr = config.server.bind;
+    let handle = Handle::new();
+    let (deadline_sender, deadline_receiver) = oneshot::channel();
+
+    tokio::spawn(shutdown_on_signal(
+        handle.clone(),
+        Shutdown {
+            config: config.clone(),
+            room_manager,
+            session_manager,
+            health,
+        },
+        deadline_sender,
+    ));
 
     tracing::info!("Listening on {}...", addr);
 
-    if let Err(err) = axum_server::bind_rustls(addr, config)
-        .serve(router.into_make_service())
+    if let Err(err) = axum_server::bind_rustls(addr, tls_config)
+        .handle(handle)
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
     {
         panic!("Server error: {}", err);
     }
+
+    // every sender is gone with the router and the rooms, the writer drains what is left
+    let deadline = deadline_receiver
+        .await
+        .unwrap_or_else(|_| Instant::now() + config.server.shutdown_timeout());
+
+    if timeout_at(deadline, db_writer).await.is_err() {
+        tracing::warn!("Shutdown deadline passed before queued messages were stored");
+    }
+
+    tracing::info!("Server stopped");
+}
+
+// everything `run` serves, tests build the same app over in-memory storage
+pub struct App {
+    pub router: Router,
+    // /metrics, bound apart from the public listener
+    pub metrics_router: Router,
+    pub room_manager: Arc<RoomManager>,
+    pub session_manager: Arc<SessionManager>,
+    pub health: Arc<Health>,
+    // finishes once the router and every room are dropped and the queue is stored
+    pub db_writer: JoinHandle<()>,
+}
+
+pub async fn app(config: Arc<Config>, db: Db) -> App {
+    let metrics = Metrics::build();
+    let health = Health::build();
+    let session_manager = SessionManager::build(config.session.idle_timeout());
+    let webhooks = WebhookDispatcher::build(db.clone(), config.webhook.clone());
+    let room_manager = RoomManager::build(config.room.clone(), webhooks, metrics.clone());
+    let login_guard = LoginGuard::build(&config.login);
+    let pending_logins = PendingLogins::build();
+    let (message_writer, db_writer) =
+        MessageWriter::spawn(db.clone(), &config.messages, metrics.clone());
+    let metrics_router = metrics_router(Arc::new(MetricsState {
+        metrics: metrics.clone(),
+        room_manager: room_manager.clone(),
+        session_manager: session_manager.clone(),
+        message_writer: message_writer.probe(),
+    }));
+    let router = router(Arc::new(AppState {
+        config: config.clone(),
+        db,
+        session_manager: session_manager.clone(),
+        room_manager: room_manager.clone(),
+        login_guard: login_guard.clone(),
+        pending_logins,
+        message_writer,
+        metrics,
+        health: health.clone(),
+    }))
+    .await;
+
+    //run session background checker
+    session_manager.run_checker();
+    login_guard.run_sweeper();
+
+    App {
+        router,
+        metrics_router,
+        room_manager,
+        session_manager,
+        health,
+        db_writer,
+    }
+}
+
+// `ws_chat_room migrate`
+pub async fn migrate(config: Config) {
+    let db = db::connect(&config.database).await;
+
+    apply_migrations(&db).await;
+}
+
+async fn apply_migrations(db: &Db) {
+    if let Err(err) = db.migrate().await {
+        tracing::error!("{}", err);
+        std::process::exit(1);
+    }
+
+    tracing::info!("Database schema is up to date");
+}
+
+// the graceful drain `run` starts on a signal, tests start it directly
+pub struct Shutdown {
+    pub config: Arc<Config>,
+    pub room_manager: Arc<RoomManager>,
+    pub session_manager: Arc<SessionManager>,
+    pub health: Arc<Health>,
+}
+
+impl Shutdown {
+    // stop accepting connections, close every room and end every session; the deadline it
+    // returns bounds storing the messages still queued
+    pub async fn drain(self, stop_accepting: impl FnOnce(Duration)) -> Instant {
+        tracing::info!("Shutting down...");
+
+        // /readyz fails first, connections are still accepted until the drain delay is over
+        self.health.drain();
+        sleep(self.config.server.drain_delay()).await;
+
+        let shutdown_timeout = self.config.server.shutdown_timeout();
+        let deadline = Instant::now() + shutdown_timeout;
+
+        stop_accepting(shutdown_timeout);
+
+        let reason = SessionEnd::Shutdown.reason();
+        if timeout_at(deadline, self.room_manager.shutdown(reason))
+            .await
+            .is_err()
+        {
+            tracing::warn!("Shutdown deadline passed before every room was closed");
+        }
+
+        self.session_manager.shutdown().await;
+
+        deadline
+    }
+}
+
+async fn shutdown_on_signal(
+    handle: Handle,
+    shutdown: Shutdown,
+    deadline_sender: oneshot::Sender<Instant>,
+) {
+    wait_for_signal().await;
+
+    let deadline = shutdown
+        .drain(|shutdown_timeout| handle.graceful_shutdown(Some(shutdown_timeout)))
+        .await;
+
+    let _ = deadline_sender.send(deadline);
+}
+
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c().await.unwrap();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
@@ -1,19 +1,29 @@
 use std::{
     collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
+    net::IpAddr,
+    sync::{self, Arc},
+    time::Duration,
 };
 
+use axum::http::{HeaderMap, header::USER_AGENT};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use tokio::{
-    sync::{Mutex, broadcast},
-    time::sleep,
+    sync::{Mutex, broadcast, watch},
+    task::JoinHandle,
+    time::{Instant, sleep},
 };
 use uuid::Uuid;
 
+use crate::api_token::Scope;
+
 pub struct SessionManager {
     sessions: Arc<Mutex<HashMap<String, Session>>>,
+    // connections authenticated by an API token, ended when the token is revoked
+    tokens: Mutex<HashMap<i64, watch::Sender<Option<SessionEnd>>>>,
     duration: Duration,
     shutdown: broadcast::Sender<()>,
+    checker: sync::Mutex<Option<JoinHandle<()>>>,
 }
 
 impl SessionManager {
@@ -22,8 +32,10 @@ impl SessionManager {
 
         Arc::new(SessionManager {
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            tokens: Mutex::new(HashMap::new()),
             duration,
             shutdown: tx,
+            checker: sync::Mutex::new(None),
         })
     }
 
@@ -31,31 +43,183 @@ impl SessionManager {
         let mut sessions = self.sessions.lock().await;
 
         match sessions.get_mut(session_id) {
-            Some(session) => {
+            Some(session) if !session.is_expired() => {
                 //update expiration
                 session.expiration = Instant::now() + self.duration;
+                session.last_seen = Utc::now();
 
                 Some((session.user_id, session.username.clone()))
             }
-            None => None,
+            _ => None,
         }
     }
 
-    pub async fn new_session(self: &Arc<Self>, user_id: i32, username: String) -> String {
+    // renew the session and describe it, used by the AuthUser extractor
+    pub async fn authenticate(
+        self: &Arc<Self>,
+        session_id: &str,
+    ) -> Option<(i32, String, SessionInfo)> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions
+            .get_mut(session_id)
+            .filter(|session| !session.is_expired())?;
+
+        session.expiration = Instant::now() + self.duration;
+        session.last_seen = Utc::now();
+
+        Some((
+            session.user_id,
+            session.username.clone(),
+            SessionInfo {
+                id: session.id.clone(),
+                created_at: session.created_at,
+                last_seen: session.last_seen,
+                ip: session.ip,
+                user_agent: session.user_agent.clone(),
+                current: true,
+            },
+        ))
+    }
+
+    pub async fn new_session(
+        self: &Arc<Self>,
+        user_id: i32,
+        username: String,
+        client: ClientInfo,
+    ) -> String {
         let mut sessions = self.sessions.lock().await;
         let session_id = Uuid::new_v4();
+        let (ended, _receiver) = watch::channel(None);
         sessions.insert(
             session_id.to_string(),
             Session {
+                id: Uuid::new_v4().to_string(),
                 user_id,
                 username,
                 expiration: Instant::now() + self.duration,
+                created_at: Utc::now(),
+                last_seen: Utc::now(),
+                ip: client.ip,
+                user_agent: client.user_agent,
+                ended,
             },
         );
 
         session_id.to_string()
     }
 
+    // link a live connection to its session
+    pub async fn watch(self: &Arc<Self>, session_id: &str) -> Option<SessionWatch> {
+        let sessions = self.sessions.lock().await;
+
+        sessions
+            .get(session_id)
+            .filter(|session| !session.is_expired())
+            .map(|session| SessionWatch {
+                session_manager: self.clone(),
+                session_id: Some(session_id.to_string()),
+                user: (session.user_id, session.username.clone()),
+                is_bot: false,
+                scope: Scope::Admin,
+                ended: Some(session.ended.subscribe()),
+            })
+    }
+
+    // link a live connection to the API token it authenticated with
+    pub async fn watch_token(
+        self: &Arc<Self>,
+        token_id: i64,
+        user: (i32, String),
+        is_bot: bool,
+        scope: Scope,
+    ) -> SessionWatch {
+        let ended = self
+            .tokens
+            .lock()
+            .await
+            .entry(token_id)
+            .or_insert_with(|| watch::channel(None).0)
+            .subscribe();
+
+        SessionWatch {
+            session_manager: self.clone(),
+            session_id: None,
+            user,
+            is_bot,
+            scope,
+            ended: Some(ended),
+        }
+    }
+
+    // the token is already revoked in storage, this ends its live connections
+    pub async fn revoke_token(self: &Arc<Self>, token_id: i64) {
+        if let Some(ended) = self.tokens.lock().await.get(&token_id) {
+            // kept until the sweep, so a connection that authenticated just before still ends
+            let _ = ended.send(Some(SessionEnd::TokenRevoked));
+        }
+    }
+
+    pub async fn count(&self) -> usize {
+        self.sessions.lock().await.len()
+    }
+
+    pub async fn list_sessions(self: &Arc<Self>, user_id: i32, current: &str) -> Vec<SessionInfo> {
+        let sessions = self.sessions.lock().await;
+
+        let mut session_infos: Vec<SessionInfo> = sessions
+            .iter()
+            .filter(|(_session_id, session)| session.user_id == user_id)
+            .map(|(session_id, session)| SessionInfo {
+                id: session.id.clone(),
+                created_at: session.created_at,
+                last_seen: session.last_seen,
+                ip: session.ip,
+                user_agent: session.user_agent.clone(),
+                current: session_id == current,
+            })
+            .collect();
+        session_infos.sort_by_key(|session_info| session_info.created_at);
+
+        session_infos
+    }
+
+    // revoke by the public id, only sessions of the same user
+    pub async fn revoke_session(self: &Arc<Self>, user_id: i32, id: &str) -> bool {
+        let mut sessions = self.sessions.lock().await;
+
+        let session_id = sessions
+            .iter()
+            .find(|(_session_id, session)| session.user_id == user_id && session.id == id)
+            .map(|(session_id, _session)| session_id.clone());
+
+        match session_id.and_then(|session_id| sessions.remove(&session_id)) {
+            Some(session) => {
+                let _ = session.ended.send(Some(SessionEnd::Revoked));
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn revoke_all_sessions(self: &Arc<Self>, user_id: i32) -> usize {
+        let mut sessions = self.sessions.lock().await;
+        let mut revoked = 0;
+
+        sessions.retain(|_session_id, session| {
+            if session.user_id != user_id {
+                return true;
+            }
+
+            let _ = session.ended.send(Some(SessionEnd::Revoked));
+            revoked += 1;
+
+            false
+        });
+
+        revoked
+    }
+
     // pub async fn update_session(self: &Arc<Self>, session_id: String, user_id: i32) {
     //     let mut sessions = self.sessions.lock().await;
 
@@ -71,21 +235,31 @@ impl SessionManager {
     pub async fn delete_session(self: &Arc<Self>, session_id: &str) {
         let mut sessions = self.sessions.lock().await;
 
-        sessions.remove(session_id);
+        if let Some(session) = sessions.remove(session_id) {
+            let _ = session.ended.send(Some(SessionEnd::LoggedOut));
+        }
+    }
+
+    // ends every session and token connection with the shutdown reason, then stops the checker
+    pub async fn shutdown(&self) {
+        for (_session_id, session) in self.sessions.lock().await.drain() {
+            let _ = session.ended.send(Some(SessionEnd::Shutdown));
+        }
+
+        for (_token_id, ended) in self.tokens.lock().await.drain() {
+            let _ = ended.send(Some(SessionEnd::Shutdown));
+        }
+
+        let _ = self.shutdown.send(());
     }
 
     pub fn run_checker(self: &Arc<Self>) {
         let session_manager = self.clone();
-        let session_manager_for_shutdown = self.clone();
         let mut shutdown_receiver = self.shutdown.subscribe();
 
-        tokio::spawn(async move {
+        let checker = tokio::spawn(async move {
             tokio::select! {
               _ = shutdown_receiver.recv() => {
-                //clear sessions
-                let mut sessions = session_manager_for_shutdown.sessions.lock().await;
-                sessions.clear();
-
                 tracing::info!("Server shutdown...")
               }
               _ = tokio::spawn(async move {
@@ -99,17 +273,157 @@ impl SessionManager {
                       let mut sessions = session_manager.sessions.lock().await;
 
                       //session was expired when expiration smaller then now
-                      sessions.retain(|_k, session| session.expiration > Instant::now());
+                      sessions.retain(|_k, session| {
+                          if !session.is_expired() {
+                              return true;
+                          }
+
+                          let _ = session.ended.send(Some(SessionEnd::Expired));
+
+                          false
+                      });
+                      drop(sessions);
+
+                      // tokens without live connections, revoked ones included
+                      session_manager
+                          .tokens
+                          .lock()
+                          .await
+                          .retain(|_token_id, ended| ended.receiver_count() > 0);
                   }
               }) => {}
             }
         });
+
+        *self.checker.lock().unwrap() = Some(checker);
+    }
+
+    // false before run_checker, after shutdown or when the checker died
+    pub fn checker_running(&self) -> bool {
+        self.checker
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|checker| !checker.is_finished())
     }
 }
 
-#[derive(Clone)]
 pub struct Session {
+    // public id, the session_id itself is never exposed
+    id: String,
     user_id: i32,
     username: String,
     expiration: Instant,
+    created_at: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    ended: watch::Sender<Option<SessionEnd>>,
+}
+
+impl Session {
+    // an expired session is refused even before the checker sweeps it
+    fn is_expired(&self) -> bool {
+        self.expiration <= Instant::now()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEnd {
+    LoggedOut,
+    Revoked,
+    Expired,
+    TokenRevoked,
+    Shutdown,
+}
+
+impl SessionEnd {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SessionEnd::LoggedOut => "Logged out",
+            SessionEnd::Revoked => "Session revoked",
+            SessionEnd::Expired => "Session expired",
+            SessionEnd::TokenRevoked => "Token revoked",
+            SessionEnd::Shutdown => "Server is shutting down",
+        }
+    }
+}
+
+// held by sockets and streams, resolves when the session goes away
+#[derive(Clone)]
+pub struct SessionWatch {
+    session_manager: Arc<SessionManager>,
+    session_id: Option<String>,
+    user: (i32, String),
+    is_bot: bool,
+    scope: Scope,
+    ended: Option<watch::Receiver<Option<SessionEnd>>>,
+}
+
+impl SessionWatch {
+    pub fn user(&self) -> &(i32, String) {
+        &self.user
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.is_bot
+    }
+
+    pub fn scope(&self) -> Scope {
+        self.scope
+    }
+
+    // activity on a live connection keeps the session from expiring
+    pub async fn touch(&self) {
+        if let Some(session_id) = &self.session_id {
+            self.session_manager.check_session(session_id).await;
+        }
+    }
+
+    pub async fn ended(&mut self) -> SessionEnd {
+        let Some(ended) = &mut self.ended else {
+            // connections without a session or token only end with the socket
+            return std::future::pending().await;
+        };
+
+        loop {
+            // a revoked token's end is already there when the connection subscribes
+            if let Some(end) = *ended.borrow_and_update() {
+                return end;
+            }
+
+            if ended.changed().await.is_err() {
+                // dropped without a reason, the session is gone anyway
+                return ended.borrow().unwrap_or(SessionEnd::Revoked);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub current: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ClientInfo {
+    ip: Option<IpAddr>,
+    user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(ip: IpAddr, headers: &HeaderMap) -> Self {
+        ClientInfo {
+            ip: Some(ip),
+            user_agent: headers
+                .get(USER_AGENT)
+                .and_then(|user_agent| user_agent.to_str().ok())
+                .map(|user_agent| user_agent.to_string()),
+        }
+    }
 }
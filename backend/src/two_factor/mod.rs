@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::db::{Db, StorageError};
+
+const ISSUER: &str = "WS Chat Room";
+const RECOVERY_CODES: usize = 10;
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+const CHALLENGE_ATTEMPTS: u32 = 5;
+const STEP_SECS: u64 = 30;
+// codes of the step before or after the current one pass too, for clock drift
+const SKEW_STEPS: u64 = 1;
+
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+pub fn provisioning_uri(secret: &str, account: &str) -> Option<String> {
+    totp(secret, account).map(|totp| totp.get_url())
+}
+
+// the time step a valid code belongs to, callers accept each step only once
+pub fn verify_code(secret: &str, code: &str) -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+    verify_code_at(secret, code, now)
+}
+
+fn verify_code_at(secret: &str, code: &str, now: u64) -> Option<u64> {
+    let totp = totp(secret, "")?;
+    let current = now / STEP_SECS;
+
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .find(|step| totp.check(code.trim(), step * STEP_SECS))
+}
+
+// codes are shown once and only their hashes are stored
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let random = Uuid::new_v4().simple().to_string();
+
+            format!("{}-{}", &random[..5], &random[5..10])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    let digest = Sha256::digest(code.trim().to_lowercase().as_bytes());
+
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn totp(secret: &str, account: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+
+    // no skew here, `verify_code_at` checks each step on its own
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        STEP_SECS,
+        secret,
+        Some(ISSUER.into()),
+        account.replace(':', "_"),
+    )
+    .ok()
+}
+
+// password-verified logins waiting for their second factor
+pub struct PendingLogins {
+    challenges: Mutex<HashMap<String, PendingLogin>>,
+}
+
+pub struct PendingLogin {
+    pub account_id: i32,
+    pub account: String,
+    pub user_id: i32,
+    pub username: String,
+    expiration: Instant,
+    attempts: u32,
+}
+
+impl PendingLogins {
+    pub fn build() -> Arc<PendingLogins> {
+        Arc::new(PendingLogins {
+            challenges: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn create(
+        &self,
+        account_id: i32,
+        account: String,
+        user_id: i32,
+        username: String,
+    ) -> String {
+        let mut challenges = self.challenges.lock().await;
+        let now = Instant::now();
+        challenges.retain(|_challenge, pending| pending.expiration > now);
+
+        let challenge = Uuid::new_v4().to_string();
+        challenges.insert(
+            challenge.clone(),
+            PendingLogin {
+                account_id,
+                account,
+                user_id,
+                username,
+                expiration: now + CHALLENGE_TTL,
+                attempts: 0,
+            },
+        );
+
+        challenge
+    }
+
+    // removes the challenge while its code is checked, so two requests can't both complete it
+    pub async fn take(&self, challenge: &str) -> Option<PendingLogin> {
+        let mut pending = self.challenges.lock().await.remove(challenge)?;
+
+        if pending.expiration <= Instant::now() || pending.attempts >= CHALLENGE_ATTEMPTS {
+            return None;
+        }
+
+        pending.attempts += 1;
+
+        Some(pending)
+    }
+
+    // a wrong code keeps the challenge until it runs out of attempts
+    pub async fn put_back(&self, challenge: &str, pending: PendingLogin) {
+        if pending.attempts < CHALLENGE_ATTEMPTS {
+            self.challenges
+                .lock()
+                .await
+                .insert(challenge.to_string(), pending);
+        }
+    }
+}
+
+// accepts a current TOTP code that was not used yet or burns one unused recovery code
+pub async fn verify_second_factor(
+    db: &Db,
+    account_id: i32,
+    secret: &str,
+    code: &str,
+) -> Result<bool, StorageError> {
+    if let Some(step) = verify_code(secret, code) {
+        return db.totp().accept_step(account_id, step).await;
+    }
+
+    db.totp()
+        .use_recovery_code(account_id, &hash_recovery_code(code))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn code_at(secret: &str, time: u64) -> String {
+        totp(secret, "").unwrap().generate(time)
+    }
+
+    #[test]
+    fn code_is_accepted_one_step_either_way() {
+        let secret = generate_secret();
+        let current = NOW / STEP_SECS;
+
+        for step in [current - 1, current, current + 1] {
+            let code = code_at(&secret, step * STEP_SECS);
+            assert_eq!(verify_code_at(&secret, &code, NOW), Some(step));
+        }
+
+        let stale = code_at(&secret, (current - 2) * STEP_SECS);
+        assert_eq!(verify_code_at(&secret, &stale, NOW), None);
+    }
+
+    #[test]
+    fn code_is_trimmed() {
+        let secret = generate_secret();
+        let code = format!(" {}\n", code_at(&secret, NOW));
+
+        assert_eq!(verify_code_at(&secret, &code, NOW), Some(NOW / STEP_SECS));
+    }
+
+    #[tokio::test]
+    async fn taken_challenge_is_gone_until_put_back() {
+        let pending_logins = PendingLogins::build();
+        let challenge = pending_logins
+            .create(1, "alice".to_string(), 1, "alice".to_string())
+            .await;
+
+        let pending = pending_logins.take(&challenge).await.unwrap();
+        assert!(pending_logins.take(&challenge).await.is_none());
+
+        pending_logins.put_back(&challenge, pending).await;
+        assert!(pending_logins.take(&challenge).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn challenge_runs_out_of_attempts() {
+        let pending_logins = PendingLogins::build();
+        let challenge = pending_logins
+            .create(1, "alice".to_string(), 1, "alice".to_string())
+            .await;
+
+        for _ in 0..CHALLENGE_ATTEMPTS {
+            let pending = pending_logins.take(&challenge).await.unwrap();
+            pending_logins.put_back(&challenge, pending).await;
+        }
+
+        assert!(pending_logins.take(&challenge).await.is_none());
+    }
+}
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+const TOKEN_PREFIX: &str = "wsh_";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IncomingWebhook {
+    pub id: i64,
+    pub name: String,
+    // the integration user its messages are stored under
+    pub user_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// who is posting through a hook token
+pub struct Integration {
+    pub room_id: Uuid,
+    pub user_id: i32,
+    pub name: String,
+}
+
+pub fn generate_token() -> String {
+    format!(
+        "{}{}{}",
+        TOKEN_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+pub fn has_prefix(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
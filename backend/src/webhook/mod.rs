@@ -0,0 +1,302 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use std::sync::Arc;
+use tokio::{sync::mpsc, time::sleep};
+use uuid::Uuid;
+
+use crate::{
+    config::WebhookConfig,
+    db::Db,
+    room_manager::{Method, RoomCommand},
+};
+
+pub mod incoming;
+pub mod target;
+use target::TargetError;
+
+const SECRET_PREFIX: &str = "whsec_";
+const SIGNATURE_HEADER: &str = "X-WsChat-Signature";
+const EVENT_HEADER: &str = "X-WsChat-Event";
+const DELIVERY_HEADER: &str = "X-WsChat-Delivery";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Message,
+    Join,
+    Leave,
+    Close,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Message => "message",
+            EventKind::Join => "join",
+            EventKind::Leave => "leave",
+            EventKind::Close => "close",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<EventKind> {
+        match kind {
+            "message" => Some(EventKind::Message),
+            "join" => Some(EventKind::Join),
+            "leave" => Some(EventKind::Leave),
+            "close" => Some(EventKind::Close),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<EventKind>,
+    pub created_at: DateTime<Utc>,
+}
+
+// one attempt to deliver an event, retries share the delivery id
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub delivery_id: Uuid,
+    pub event: String,
+    pub attempt: i32,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub succeeded: bool,
+    pub attempted_at: DateTime<Utc>,
+}
+
+// secrets are shown once on creation but kept in plain text, they are needed to sign
+pub fn generate_secret() -> String {
+    format!("{}{}", SECRET_PREFIX, Uuid::new_v4().simple())
+}
+
+// hex HMAC-SHA256 of the raw request body
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// room event waiting for delivery
+#[derive(Debug)]
+struct RoomEvent {
+    room_id: Uuid,
+    kind: EventKind,
+    data: Value,
+    occurred_at: DateTime<Utc>,
+}
+
+impl RoomEvent {
+    fn from_command(room_id: Uuid, command: &RoomCommand) -> Option<Self> {
+        let (kind, data) = match command.method {
+            Method::Send => (
+                EventKind::Message,
+                json!({
+                    "user_id": command.user_id,
+                    "sender": command.user,
+                    "bot": command.is_bot,
+                    "content": command.message,
+                    "seq": command.seq,
+                }),
+            ),
+            Method::Join => (
+                EventKind::Join,
+                json!({ "user_id": command.user_id, "user": command.user, "bot": command.is_bot }),
+            ),
+            Method::Leave => (
+                EventKind::Leave,
+                json!({ "user_id": command.user_id, "user": command.user, "bot": command.is_bot }),
+            ),
+            Method::Close => (EventKind::Close, json!({})),
+            _ => return None,
+        };
+
+        Some(RoomEvent {
+            room_id,
+            kind,
+            data,
+            occurred_at: Utc::now(),
+        })
+    }
+}
+
+// target of one delivery, loaded when the event is picked up
+pub struct Target {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+}
+
+// queues room events for the background worker, rooms never wait on HTTP
+pub struct WebhookDispatcher {
+    sender: mpsc::Sender<RoomEvent>,
+}
+
+impl WebhookDispatcher {
+    pub fn build(db: Db, config: WebhookConfig) -> Arc<WebhookDispatcher> {
+        let (sender, receiver) = mpsc::channel(config.queue_capacity);
+        // a redirect could lead anywhere, the receiver has to answer itself
+        let mut client = reqwest::Client::builder()
+            .timeout(config.timeout())
+            .redirect(reqwest::redirect::Policy::none());
+
+        if !config.allow_private_targets {
+            client = client.dns_resolver(Arc::new(target::PublicResolver));
+        }
+
+        let client = client.build().unwrap();
+
+        tokio::spawn(run_worker(db, client, Arc::new(config), receiver));
+
+        Arc::new(WebhookDispatcher { sender })
+    }
+
+    pub fn dispatch(&self, room_id: Uuid, command: &RoomCommand) {
+        let Some(event) = RoomEvent::from_command(room_id, command) else {
+            return;
+        };
+
+        if let Err(err) = self.sender.try_send(event) {
+            tracing::warn!("Dropped webhook event: {}", err);
+        }
+    }
+}
+
+async fn run_worker(
+    db: Db,
+    client: reqwest::Client,
+    config: Arc<WebhookConfig>,
+    mut receiver: mpsc::Receiver<RoomEvent>,
+) {
+    while let Some(event) = receiver.recv().await {
+        let targets = match db.webhooks().targets(event.room_id, event.kind).await {
+            Ok(targets) => targets,
+            Err(err) => {
+                tracing::error!("Failed to load webhooks: {}", err);
+                continue;
+            }
+        };
+
+        for target in targets {
+            let delivery_id = Uuid::new_v4();
+            let body = json!({
+                "id": delivery_id,
+                "event": event.kind,
+                "room_id": event.room_id,
+                "occurred_at": event.occurred_at,
+                "data": event.data,
+            })
+            .to_string();
+
+            // retries back off independently, one slow endpoint does not hold the others
+            tokio::spawn(deliver(
+                db.clone(),
+                client.clone(),
+                config.clone(),
+                target,
+                delivery_id,
+                event.kind,
+                body,
+            ));
+        }
+    }
+}
+
+async fn deliver(
+    db: Db,
+    client: reqwest::Client,
+    config: Arc<WebhookConfig>,
+    target: Target,
+    delivery_id: Uuid,
+    kind: EventKind,
+    body: String,
+) {
+    let signature = format!("sha256={}", sign(&target.secret, body.as_bytes()));
+
+    for attempt in 1..=config.max_attempts {
+        // checked again on every attempt, the host may have moved since the webhook was created
+        let (status_code, error, retryable) =
+            match target::check(&target.url, config.allow_private_targets).await {
+                Ok(url) => post(&client, url, kind, delivery_id, &signature, &body).await,
+                // an internal address stays internal, a failed lookup may recover
+                Err(err) => (None, Some(err.to_string()), err == TargetError::Unresolved),
+            };
+
+        let succeeded = error.is_none();
+        let result = db
+            .webhooks()
+            .record_attempt(
+                target.id,
+                delivery_id,
+                kind,
+                attempt,
+                status_code,
+                error.as_deref(),
+            )
+            .await;
+
+        if let Err(err) = result {
+            tracing::error!("Failed to record webhook delivery: {}", err);
+        }
+
+        if succeeded || !retryable || attempt == config.max_attempts {
+            if !succeeded {
+                tracing::warn!(
+                    "Webhook {} gave up on delivery {} after {} attempts",
+                    target.id,
+                    delivery_id,
+                    attempt
+                );
+            }
+
+            return;
+        }
+
+        sleep(config.base_delay() * 2u32.saturating_pow(attempt - 1)).await;
+    }
+}
+
+// status code, error and whether another attempt may succeed
+async fn post(
+    client: &reqwest::Client,
+    url: reqwest::Url,
+    kind: EventKind,
+    delivery_id: Uuid,
+    signature: &str,
+    body: &str,
+) -> (Option<u16>, Option<String>, bool) {
+    let result = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, kind.as_str())
+        .header(DELIVERY_HEADER, delivery_id.to_string())
+        .header(SIGNATURE_HEADER, signature)
+        .body(body.to_string())
+        .send()
+        .await;
+
+    match result {
+        Ok(response) if response.status().is_success() => {
+            (Some(response.status().as_u16()), None, false)
+        }
+        Ok(response) => {
+            let status = response.status();
+            // the receiver rejected the payload, sending it again will not help
+            let retryable = status.is_server_error()
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+
+            (Some(status.as_u16()), Some(status.to_string()), retryable)
+        }
+        Err(err) => (None, Some(err.to_string()), true),
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use reqwest::{
+    Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+};
+use tokio::net::lookup_host;
+
+// why a webhook url may not be called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetError {
+    InvalidUrl,
+    Unresolved,
+    // loopback, private, link-local and other addresses only reachable from inside
+    NotPublic,
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::InvalidUrl => write!(f, "Webhook url must be an http(s) URL"),
+            TargetError::Unresolved => write!(f, "Webhook host could not be resolved"),
+            TargetError::NotPublic => write!(f, "Webhook url must point to a public address"),
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+// the url parses and, unless private targets are allowed, every address it names is public
+pub async fn check(url: &str, allow_private: bool) -> Result<Url, TargetError> {
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or(TargetError::InvalidUrl)?;
+
+    if allow_private {
+        return Ok(url);
+    }
+
+    let host = url.host_str().ok_or(TargetError::InvalidUrl)?;
+    // ipv6 hosts keep their brackets in urls
+    let ips: Vec<IpAddr> = match host.trim_matches(|c| c == '[' || c == ']').parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => lookup_host((host, 0))
+            .await
+            .map_err(|_err| TargetError::Unresolved)?
+            .map(|addr| addr.ip())
+            .collect(),
+    };
+
+    if ips.is_empty() {
+        Err(TargetError::Unresolved)
+    } else if ips.into_iter().all(is_public) {
+        Ok(url)
+    } else {
+        Err(TargetError::NotPublic)
+    }
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    // 100.64.0.0/10, carrier-grade NAT
+    let shared = a == 100 && (64..128).contains(&b);
+
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || shared
+        || a == 0)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local())
+}
+
+// resolves delivery hosts and keeps only public addresses, so a name that is
+// re-pointed after the webhook was created can't reach internal services
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(TargetError::NotPublic.into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for internal in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+        ] {
+            assert!(!is_public(ip(internal)), "{} is public", internal);
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        for public in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public(ip(public)), "{} is not public", public);
+        }
+    }
+
+    #[tokio::test]
+    async fn urls_naming_internal_hosts_are_refused() {
+        assert_eq!(
+            check("http://127.0.0.1:9000/hook", false).await,
+            Err(TargetError::NotPublic)
+        );
+        assert_eq!(
+            check("http://[::1]/hook", false).await,
+            Err(TargetError::NotPublic)
+        );
+        assert_eq!(
+            check("http://localhost/hook", false).await,
+            Err(TargetError::NotPublic)
+        );
+        assert_eq!(
+            check("ftp://example.com/hook", false).await,
+            Err(TargetError::InvalidUrl)
+        );
+        assert!(check("http://127.0.0.1:9000/hook", true).await.is_ok());
+    }
+}
@@ -0,0 +1,260 @@
+mod common;
+
+use common::{TestServer, User};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+// the code of the time step `offset` steps from the current one
+fn totp_code(secret: &str, offset: u64) -> String {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    let totp = TOTP::new(Algorithm::SHA1, 6, 0, 30, secret, None, String::new()).unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    totp.generate(now + offset * 30)
+}
+
+// enables two-factor with a code of the current step and returns the secret
+async fn enable_totp(server: &TestServer, user: &User) -> String {
+    let reply = server.post("/totp/enroll", Some(user), json!({})).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    let secret = reply.body["data"]["secret"].as_str().unwrap().to_string();
+
+    let reply = server
+        .post(
+            "/totp/verify",
+            Some(user),
+            json!({ "code": totp_code(&secret, 0) }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+    secret
+}
+
+async fn totp_challenge(server: &TestServer) -> String {
+    let reply = server.login("alice", "secret").await;
+    assert_eq!(reply.code(), "TOTP_REQUIRED");
+
+    reply.body["data"]["challenge"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+async fn signup_signs_the_user_in() {
+    let server = TestServer::start().await;
+
+    let alice = server.signup("alice", "secret").await;
+    let reply = server.get("/auth", &alice).await;
+
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.body["data"]["current"], true);
+}
+
+#[tokio::test]
+async fn signup_rejects_a_taken_account() {
+    let server = TestServer::start().await;
+    server.signup("alice", "secret").await;
+
+    let reply = server
+        .post(
+            "/signup",
+            None,
+            json!({ "account": "alice", "password": "other" }),
+        )
+        .await;
+
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    assert_eq!(reply.code(), "DUPLICATE_ENTRY");
+}
+
+#[tokio::test]
+async fn login_with_the_right_password() {
+    let server = TestServer::start().await;
+    server.signup("alice", "secret").await;
+
+    let alice = server.login("alice", "secret").await.user("alice");
+    let reply = server.get("/auth", &alice).await;
+
+    assert_eq!(reply.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn login_with_a_wrong_password() {
+    let server = TestServer::start().await;
+    server.signup("alice", "secret").await;
+
+    let reply = server.login("alice", "wrong").await;
+
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+
+    let reply = server.login("nobody", "secret").await;
+
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn logout_ends_the_session() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+
+    let reply = server.get("/logout", &alice).await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    let reply = server.get("/auth", &alice).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn totp_codes_are_single_use() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let secret = enable_totp(&server, &alice).await;
+
+    // the code that enabled two-factor can't log in
+    let challenge = totp_challenge(&server).await;
+    let login = |code: String| {
+        server.post(
+            "/login/totp",
+            None,
+            json!({ "challenge": challenge, "code": code }),
+        )
+    };
+    let reply = login(totp_code(&secret, 0)).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reply.code(), "INVALID_TOTP");
+
+    // the next step's code is still in the accepted drift, once
+    login(totp_code(&secret, 1)).await.user("alice");
+
+    let challenge = totp_challenge(&server).await;
+    let reply = server
+        .post(
+            "/login/totp",
+            None,
+            json!({ "challenge": challenge, "code": totp_code(&secret, 1) }),
+        )
+        .await;
+    assert_eq!(reply.code(), "INVALID_TOTP");
+}
+
+#[tokio::test]
+async fn totp_challenge_completes_once() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let secret = enable_totp(&server, &alice).await;
+
+    let challenge = totp_challenge(&server).await;
+    let body = json!({ "challenge": challenge, "code": totp_code(&secret, 1) });
+    let (first, second) = tokio::join!(
+        server.post("/login/totp", None, body.clone()),
+        server.post("/login/totp", None, body),
+    );
+
+    let signed_in = [&first, &second]
+        .iter()
+        .filter(|reply| reply.status == StatusCode::OK)
+        .count();
+    assert_eq!(signed_in, 1);
+}
+
+#[tokio::test]
+async fn revoking_a_token_closes_its_connections() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (_owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    let reply = server
+        .post(
+            "/tokens",
+            Some(&alice),
+            json!({ "name": "script", "scope": "read" }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+    let token_id = reply.body["data"]["id"].as_i64().unwrap();
+    let script = User::with_token("alice", reply.body["data"]["token"].as_str().unwrap());
+
+    let mut socket = server.join_room(&script, &room_id).await;
+    let mut events = server.events(&room_id, &script, None).await;
+    // the stream is open once its own join arrives
+    events.next_frame("joined").await;
+
+    let reply = server
+        .request(
+            Method::DELETE,
+            &format!("/tokens/{}", token_id),
+            Some(&alice),
+            None,
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+    let close_frame = socket.closed().await.expect("no close frame");
+    assert_eq!(close_frame.code, CloseCode::Policy);
+    assert_eq!(close_frame.reason.as_str(), "Token revoked");
+
+    loop {
+        let event = events.next().await;
+
+        if event.event.as_deref() == Some("close") {
+            assert_eq!(event.data, "Token revoked");
+            break;
+        }
+    }
+
+    // and the token no longer authenticates
+    assert_eq!(
+        server.get("/rooms", &script).await.status,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn creating_a_room_needs_the_chat_scope() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let mut sockets = Vec::new();
+
+    for (scope, allowed) in [
+        ("read", false),
+        ("send", false),
+        ("chat", true),
+        ("admin", true),
+    ] {
+        let reply = server
+            .post(
+                "/tokens",
+                Some(&alice),
+                json!({ "name": scope, "scope": scope }),
+            )
+            .await;
+        assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+        let script = User::with_token("alice", reply.body["data"]["token"].as_str().unwrap());
+
+        match server
+            .connect(&format!("/create_room?room_name={}", scope), &script)
+            .await
+        {
+            Ok(socket) if allowed => sockets.push(socket),
+            Err(StatusCode::FORBIDDEN) if !allowed => {}
+            result => panic!("{} token got {:?}", scope, result.err()),
+        }
+    }
+
+    let mut rooms: Vec<String> = server
+        .rooms(&alice)
+        .await
+        .into_iter()
+        .map(|(_room_id, room_name)| room_name)
+        .collect();
+    rooms.sort();
+    assert_eq!(rooms, ["admin", "chat"]);
+}
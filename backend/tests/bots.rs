@@ -0,0 +1,144 @@
+mod common;
+
+use common::{TestServer, User};
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+// creates a bot for `owner` and adds it to the room
+async fn add_bot(server: &TestServer, owner: &User, room_id: &str, name: &str) -> (i64, User) {
+    let reply = server
+        .post("/bots", Some(owner), json!({ "name": name }))
+        .await;
+    assert!(reply.status.is_success(), "{}", reply.body);
+    let bot_id = reply.body["data"]["id"].as_i64().unwrap();
+    let bot = User::with_token(name, reply.body["data"]["token"].as_str().unwrap());
+
+    let reply = server
+        .post(
+            &format!("/rooms/{}/bots", room_id),
+            Some(owner),
+            json!({ "bot_id": bot_id }),
+        )
+        .await;
+    assert!(reply.status.is_success(), "{}", reply.body);
+
+    (bot_id, bot)
+}
+
+#[tokio::test]
+async fn bots_are_flagged_not_renamed() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    owner.join().await;
+    owner.recv_type("joined").await;
+    let (_bot_id, bot) = add_bot(&server, &alice, &room_id, "helper").await;
+
+    let mut bot_socket = server.join_room(&bot, &room_id).await;
+    bot_socket.join().await;
+    let joined = owner.recv_type("joined").await;
+    assert_eq!(joined["user"], "helper");
+    assert_eq!(joined["bot"], true);
+
+    bot_socket.say("beep").await;
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["sender"], "helper");
+    assert_eq!(message["bot"], true);
+
+    // a human who names themselves like a bot is still a human
+    let mallory = server.signup("mallory", "secret").await;
+    let mut spoof = server.join_room(&mallory, &room_id).await;
+    spoof.say("helper [bot]").await;
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["sender"], "mallory");
+    assert_eq!(message["bot"], false);
+}
+
+#[tokio::test]
+async fn bot_tokens_can_only_chat() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (_owner, room_id) = server.create_room(&alice, "lobby").await;
+    let (_bot_id, bot) = add_bot(&server, &alice, &room_id, "helper").await;
+
+    assert_eq!(server.get("/rooms", &bot).await.status, StatusCode::OK);
+
+    let reply = server
+        .post(
+            "/tokens",
+            Some(&bot),
+            json!({ "name": "escalate", "scope": "admin" }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN, "{}", reply.body);
+    assert_eq!(reply.code(), "INSUFFICIENT_SCOPE");
+}
+
+#[tokio::test]
+async fn removing_a_bot_kicks_it() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (_owner, room_id) = server.create_room(&alice, "lobby").await;
+    let (bot_id, bot) = add_bot(&server, &alice, &room_id, "helper").await;
+
+    let mut bot_socket = server.join_room(&bot, &room_id).await;
+    bot_socket.join().await;
+    bot_socket.recv_type("joined").await;
+
+    let reply = server
+        .request(
+            Method::DELETE,
+            &format!("/rooms/{}/bots/{}", room_id, bot_id),
+            Some(&alice),
+            None,
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+    let error = bot_socket.recv_type("error").await;
+    assert_eq!(error["code"], "KICKED");
+    bot_socket.closed().await;
+
+    // and it can't come back
+    assert_eq!(
+        server
+            .connect(&format!("/join_room?room_id={}", room_id), &bot)
+            .await
+            .err(),
+        Some(StatusCode::FORBIDDEN)
+    );
+}
+
+#[tokio::test]
+async fn owners_can_only_add_their_own_bots() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let mallory = server.signup("mallory", "secret").await;
+    let (_owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    let reply = server
+        .post("/bots", Some(&mallory), json!({ "name": "lurker" }))
+        .await;
+    assert!(reply.status.is_success(), "{}", reply.body);
+    let bot_id = reply.body["data"]["id"].as_i64().unwrap();
+    let bot = User::with_token("lurker", reply.body["data"]["token"].as_str().unwrap());
+
+    let reply = server
+        .post(
+            &format!("/rooms/{}/bots", room_id),
+            Some(&alice),
+            json!({ "bot_id": bot_id }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND, "{}", reply.body);
+    assert_eq!(reply.code(), "NOT_FOUND");
+
+    // never added, so it still can't join
+    assert_eq!(
+        server
+            .connect(&format!("/join_room?room_id={}", room_id), &bot)
+            .await
+            .err(),
+        Some(StatusCode::FORBIDDEN)
+    );
+}
@@ -0,0 +1,535 @@
+// every test binary uses a different part of the harness
+#![allow(dead_code)]
+
+use futures_util::{SinkExt, StreamExt};
+use reqwest::{
+    Method, StatusCode,
+    header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, HeaderName, SET_COOKIE},
+};
+use serde_json::{Value, json};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    task::JoinHandle,
+    time::{timeout, timeout_at},
+};
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{
+        self, Message,
+        client::IntoClientRequest,
+        http::{HeaderValue, header::SEC_WEBSOCKET_PROTOCOL},
+        protocol::CloseFrame,
+    },
+};
+use ws_chat_room::{
+    config::{Config, StorageBackend},
+    db::{Db, MemoryStorage},
+    health::Health,
+    server::{self, App, Shutdown},
+};
+
+// how long a test waits for a frame before it fails
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the v2 frames in either wire format, tests see both as JSON values
+#[derive(Debug, Clone, Copy)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
+// the full router over in-memory storage on an ephemeral port, plain HTTP instead of TLS
+pub struct TestServer {
+    addr: SocketAddr,
+    // the internal listener serving /metrics
+    metrics_addr: SocketAddr,
+    http: reqwest::Client,
+    encoding: Encoding,
+    // lets a test start the drain without a signal
+    pub health: Arc<Health>,
+    // taken by `shut_down`
+    stopping: Mutex<Option<Stopping>>,
+}
+
+// what the signal handler and `run` hold on to until the server stopped
+struct Stopping {
+    shutdown: Shutdown,
+    stop_serving: oneshot::Sender<()>,
+    db_writer: JoinHandle<()>,
+}
+
+// a signed in user, the session cookie or API token goes along with every request
+pub struct User {
+    pub name: String,
+    // header name and value
+    credential: (HeaderName, String),
+}
+
+pub struct ApiReply {
+    pub status: StatusCode,
+    pub body: Value,
+    cookie: Option<String>,
+}
+
+// a Server-Sent Events response, read one event at a time
+pub struct EventStream {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+}
+
+pub struct RoomSocket {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    encoding: Encoding,
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        TestServer::start_with(|_config| {}).await
+    }
+
+    pub async fn start_with(configure: impl FnOnce(&mut Config)) -> TestServer {
+        TestServer::start_over(Arc::new(MemoryStorage::new()), configure).await
+    }
+
+    // the same server over a storage the test wraps, e.g. to hold back inserts
+    pub async fn start_over(db: Db, configure: impl FnOnce(&mut Config)) -> TestServer {
+        let mut config = Config::default();
+        config.database.backend = StorageBackend::Memory;
+        configure(&mut config);
+        let config = Arc::new(config);
+
+        let App {
+            router,
+            metrics_router,
+            room_manager,
+            session_manager,
+            health,
+            db_writer,
+        } = server::app(config.clone(), db).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics_addr = metrics_listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::serve(metrics_listener, metrics_router).await.unwrap();
+        });
+
+        let (stop_serving, stopped) = oneshot::channel();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap();
+        });
+
+        TestServer {
+            addr,
+            metrics_addr,
+            http: reqwest::Client::new(),
+            encoding: Encoding::Json,
+            health: health.clone(),
+            stopping: Mutex::new(Some(Stopping {
+                shutdown: Shutdown {
+                    config,
+                    room_manager,
+                    session_manager,
+                    health,
+                },
+                stop_serving,
+                db_writer,
+            })),
+        }
+    }
+
+    // the drain a signal starts, finishes once the writer stored what was queued
+    pub fn shut_down(&self) -> JoinHandle<()> {
+        let Stopping {
+            shutdown,
+            stop_serving,
+            db_writer,
+        } = self
+            .stopping
+            .lock()
+            .unwrap()
+            .take()
+            .expect("already shut down");
+
+        tokio::spawn(async move {
+            let deadline = shutdown
+                .drain(|_shutdown_timeout| {
+                    let _ = stop_serving.send(());
+                })
+                .await;
+
+            timeout_at(deadline, db_writer)
+                .await
+                .expect("the message writer kept running after shutdown")
+                .unwrap();
+        })
+    }
+
+    // sockets opened from here on negotiate this encoding
+    pub fn with_encoding(mut self, encoding: Encoding) -> TestServer {
+        self.encoding = encoding;
+
+        self
+    }
+
+    // JSON request to `/api{path}`, signed in when a user is given
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        user: Option<&User>,
+        body: Option<Value>,
+    ) -> ApiReply {
+        let mut request = self
+            .http
+            .request(method, format!("http://{}/api{}", self.addr, path));
+
+        if let Some(user) = user {
+            request = request.header(&user.credential.0, &user.credential.1);
+        }
+        if let Some(body) = body {
+            request = request
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        let cookie = response
+            .headers()
+            .get(SET_COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(str::to_string);
+        let text = response.text().await.unwrap();
+
+        ApiReply {
+            status,
+            body: serde_json::from_str(&text).unwrap_or(Value::String(text)),
+            cookie,
+        }
+    }
+
+    pub async fn get(&self, path: &str, user: &User) -> ApiReply {
+        self.request(Method::GET, path, Some(user), None).await
+    }
+
+    pub async fn post(&self, path: &str, user: Option<&User>, body: Value) -> ApiReply {
+        self.request(Method::POST, path, user, Some(body)).await
+    }
+
+    pub async fn signup(&self, account: &str, password: &str) -> User {
+        let reply = self
+            .post(
+                "/signup",
+                None,
+                json!({ "account": account, "password": password }),
+            )
+            .await;
+
+        reply.user(account)
+    }
+
+    pub async fn login(&self, account: &str, password: &str) -> ApiReply {
+        self.post(
+            "/login",
+            None,
+            json!({ "account": account, "password": password }),
+        )
+        .await
+    }
+
+    // the upgrade is answered once the room exists, so its id can be looked up right away
+    pub async fn create_room(&self, user: &User, room_name: &str) -> (RoomSocket, String) {
+        let socket = self
+            .connect(&format!("/create_room?room_name={}", room_name), user)
+            .await
+            .unwrap();
+
+        let room_id = self
+            .rooms(user)
+            .await
+            .into_iter()
+            .find_map(|(room_id, name)| (name == room_name).then_some(room_id))
+            .unwrap();
+
+        (socket, room_id)
+    }
+
+    pub async fn join_room(&self, user: &User, room_id: &str) -> RoomSocket {
+        self.connect(&format!("/join_room?room_id={}", room_id), user)
+            .await
+            .unwrap()
+    }
+
+    // the HTTP status when the server refuses the upgrade
+    pub async fn connect(&self, path: &str, user: &User) -> Result<RoomSocket, StatusCode> {
+        let mut request = format!("ws://{}/api{}", self.addr, path)
+            .into_client_request()
+            .unwrap();
+        let headers = request.headers_mut();
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(self.encoding.subprotocol()),
+        );
+        headers.insert(
+            &user.credential.0,
+            HeaderValue::from_str(&user.credential.1).unwrap(),
+        );
+
+        match connect_async(request).await {
+            Ok((stream, _response)) => Ok(RoomSocket {
+                stream,
+                encoding: self.encoding,
+            }),
+            Err(tungstenite::Error::Http(response)) => {
+                Err(StatusCode::from_u16(response.status().as_u16()).unwrap())
+            }
+            Err(err) => panic!("WebSocket connect failed: {}", err),
+        }
+    }
+
+    // the room's SSE stream, resumed after `last_event_id` when given
+    pub async fn events(
+        &self,
+        room_id: &str,
+        user: &User,
+        last_event_id: Option<u64>,
+    ) -> EventStream {
+        let mut request = self
+            .http
+            .get(format!("http://{}/api/rooms/{}/events", self.addr, room_id))
+            .header(&user.credential.0, &user.credential.1);
+
+        if let Some(last_event_id) = last_event_id {
+            request = request.header("last-event-id", last_event_id.to_string());
+        }
+
+        let response = request.send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        EventStream {
+            response,
+            buffer: String::new(),
+        }
+    }
+
+    // plain GET outside of `/api`, e.g. the health endpoints
+    pub async fn get_text(&self, path: &str) -> (StatusCode, String) {
+        self.fetch_text(self.addr, path).await
+    }
+
+    // GET /metrics from the internal listener
+    pub async fn metrics(&self) -> (StatusCode, String) {
+        self.fetch_text(self.metrics_addr, "/metrics").await
+    }
+
+    async fn fetch_text(&self, addr: SocketAddr, path: &str) -> (StatusCode, String) {
+        let response = self
+            .http
+            .get(format!("http://{}{}", addr, path))
+            .send()
+            .await
+            .unwrap();
+
+        (response.status(), response.text().await.unwrap())
+    }
+
+    // (room_id, room_name) of every open room
+    pub async fn rooms(&self, user: &User) -> Vec<(String, String)> {
+        let reply = self.get("/rooms", user).await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+        reply.body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|room| {
+                (
+                    room["room_id"].as_str().unwrap().to_string(),
+                    room["room_name"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Encoding {
+    fn subprotocol(&self) -> &'static str {
+        match self {
+            Encoding::Json => "ws_chat.v2",
+            Encoding::MessagePack => "ws_chat.v2.msgpack",
+        }
+    }
+}
+
+impl ApiReply {
+    pub fn code(&self) -> &str {
+        self.body["code"].as_str().unwrap_or_default()
+    }
+
+    // the session the reply signed in, fails the test when there is none
+    pub fn user(self, name: &str) -> User {
+        assert_eq!(self.status, StatusCode::OK, "{}", self.body);
+
+        User {
+            name: name.to_string(),
+            credential: (COOKIE, self.cookie.expect("no session cookie")),
+        }
+    }
+}
+
+impl EventStream {
+    // the next event, keep-alive comments are skipped
+    pub async fn next(&mut self) -> SseEvent {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                let mut event = SseEvent::default();
+
+                for line in block.lines() {
+                    match line.split_once(':') {
+                        Some(("id", value)) => event.id = Some(value.trim_start().to_string()),
+                        Some(("event", value)) => {
+                            event.event = Some(value.trim_start().to_string())
+                        }
+                        Some(("data", value)) => event.data.push_str(value.trim_start()),
+                        _ => {}
+                    }
+                }
+
+                if event.event.is_some() || !event.data.is_empty() {
+                    return event;
+                }
+                continue;
+            }
+
+            let chunk = timeout(FRAME_TIMEOUT, self.response.chunk())
+                .await
+                .expect("no event before the timeout")
+                .unwrap()
+                .expect("stream ended");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    // the next frame of the type, named events and other frames are skipped
+    pub async fn next_frame(&mut self, frame_type: &str) -> (SseEvent, Value) {
+        loop {
+            let event = self.next().await;
+
+            if event.event.is_none() {
+                let frame: Value = serde_json::from_str(&event.data).unwrap();
+
+                if frame["type"] == frame_type {
+                    return (event, frame);
+                }
+            }
+        }
+    }
+}
+
+impl User {
+    // authenticated by an API token instead of a session
+    pub fn with_token(name: &str, token: &str) -> User {
+        User {
+            name: name.to_string(),
+            credential: (AUTHORIZATION, format!("Bearer {}", token)),
+        }
+    }
+}
+
+impl RoomSocket {
+    pub async fn join(&mut self) {
+        self.send_frame(json!({ "type": "join" })).await;
+    }
+
+    pub async fn say(&mut self, content: &str) {
+        self.send_frame(json!({ "type": "send", "content": content }))
+            .await;
+    }
+
+    pub async fn send_frame(&mut self, frame: Value) {
+        let message = match self.encoding {
+            Encoding::Json => Message::text(frame.to_string()),
+            Encoding::MessagePack => Message::binary(rmp_serde::to_vec_named(&frame).unwrap()),
+        };
+
+        self.stream.send(message).await.unwrap();
+    }
+
+    // the next frame, fails the test on a timeout or a closed socket
+    pub async fn recv(&mut self) -> Value {
+        loop {
+            let message = timeout(FRAME_TIMEOUT, self.stream.next())
+                .await
+                .expect("no frame before the timeout")
+                .expect("socket closed")
+                .unwrap();
+
+            match (self.encoding, message) {
+                (Encoding::Json, Message::Text(text)) => {
+                    return serde_json::from_str(text.as_str()).unwrap();
+                }
+                (Encoding::MessagePack, Message::Binary(bytes)) => {
+                    return rmp_serde::from_slice(&bytes).unwrap();
+                }
+                (_, Message::Close(frame)) => panic!("socket closed: {:?}", frame),
+                (_, Message::Ping(_) | Message::Pong(_)) => continue,
+                (encoding, message) => panic!("unexpected {:?} frame: {:?}", encoding, message),
+            }
+        }
+    }
+
+    // skips frames of other types, e.g. the acks of the socket's own messages
+    pub async fn recv_type(&mut self, frame_type: &str) -> Value {
+        loop {
+            let frame = self.recv().await;
+
+            if frame["type"] == frame_type {
+                return frame;
+            }
+        }
+    }
+
+    // waits for the server to close the socket, frames before the close are skipped
+    pub async fn closed(&mut self) -> Option<CloseFrame> {
+        loop {
+            let message = timeout(FRAME_TIMEOUT, self.stream.next())
+                .await
+                .expect("socket still open after the timeout");
+
+            match message {
+                Some(Ok(Message::Close(frame))) => return frame,
+                Some(Ok(_)) => continue,
+                _ => return None,
+            }
+        }
+    }
+
+    pub async fn close(mut self) {
+        self.stream.close(None).await.unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+// session and room timers under paused tokio time, sleeps advance the clock instantly
+use axum::http::HeaderMap;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time::{Instant, sleep},
+};
+use ws_chat_room::{
+    config::Config,
+    db::{Db, MemoryStorage, MessageWriter},
+    metrics::Metrics,
+    rate_limit::RoomLimits,
+    room_manager::{Method, RoomCommand, RoomManager},
+    session::{ClientInfo, SessionEnd, SessionManager},
+    webhook::WebhookDispatcher,
+};
+
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const ROOM_TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn sign_in(session_manager: &Arc<SessionManager>) -> String {
+    let client = ClientInfo::new(IpAddr::V4(Ipv4Addr::LOCALHOST), &HeaderMap::new());
+
+    session_manager
+        .new_session(1, "alice".to_string(), client)
+        .await
+}
+
+struct Room {
+    room_manager: Arc<RoomManager>,
+    db: Db,
+    sender: mpsc::Sender<RoomCommand>,
+    receiver: broadcast::Receiver<RoomCommand>,
+    room_id: String,
+}
+
+async fn open_room() -> Room {
+    let mut config = Config::default();
+    config.room.idle_timeout_secs = ROOM_TIMEOUT.as_secs();
+
+    let db: Db = Arc::new(MemoryStorage::new());
+    let metrics = Metrics::build();
+    let (message_writer, _db_writer) =
+        MessageWriter::spawn(db.clone(), &config.messages, metrics.clone());
+    let room_manager = RoomManager::build(
+        config.room,
+        WebhookDispatcher::build(db.clone(), config.webhook),
+        metrics,
+    );
+    let (sender, receiver, room_id) = room_manager
+        .clone()
+        .create(
+            db.clone(),
+            "lobby",
+            1,
+            RoomLimits::default(),
+            message_writer,
+        )
+        .await
+        .unwrap();
+
+    Room {
+        room_manager,
+        db,
+        sender,
+        receiver,
+        room_id,
+    }
+}
+
+impl Room {
+    async fn is_open(&self) -> bool {
+        self.room_manager
+            .clone()
+            .join(&self.room_id)
+            .await
+            .is_some()
+    }
+
+    // skips everything but the close and returns its reason
+    async fn closed(&mut self) -> Option<String> {
+        loop {
+            let command = self.receiver.recv().await.unwrap();
+
+            if let Method::Close = command.method {
+                return command.message;
+            }
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn session_expires_after_the_idle_timeout() {
+    let session_manager = SessionManager::build(SESSION_TIMEOUT);
+    session_manager.run_checker();
+    let session_id = sign_in(&session_manager).await;
+    let mut watch = session_manager.watch(&session_id).await.unwrap();
+    let start = Instant::now();
+
+    assert_eq!(watch.ended().await, SessionEnd::Expired);
+    assert_eq!(start.elapsed(), SESSION_TIMEOUT);
+    assert!(session_manager.authenticate(&session_id).await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn activity_renews_the_session() {
+    let session_manager = SessionManager::build(SESSION_TIMEOUT);
+    session_manager.run_checker();
+    let session_id = sign_in(&session_manager).await;
+
+    // active every 20 minutes, well past the first timeout
+    for _ in 0..3 {
+        sleep(Duration::from_secs(20 * 60)).await;
+        assert!(session_manager.authenticate(&session_id).await.is_some());
+    }
+
+    sleep(SESSION_TIMEOUT - Duration::from_secs(1)).await;
+    assert!(session_manager.watch(&session_id).await.is_some());
+
+    sleep(Duration::from_secs(1)).await;
+    assert!(session_manager.authenticate(&session_id).await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_session_is_refused_before_the_sweep() {
+    // no checker runs, only the expiration itself
+    let session_manager = SessionManager::build(SESSION_TIMEOUT);
+    let session_id = sign_in(&session_manager).await;
+
+    sleep(SESSION_TIMEOUT).await;
+
+    assert!(session_manager.authenticate(&session_id).await.is_none());
+    assert!(session_manager.watch(&session_id).await.is_none());
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_room_closes_after_the_timeout() {
+    let mut room = open_room().await;
+    let start = Instant::now();
+
+    assert_eq!(room.closed().await.as_deref(), Some("Room closed"));
+    assert_eq!(start.elapsed(), ROOM_TIMEOUT);
+    assert!(!room.is_open().await);
+    assert!(room.db.rooms().list_open().await.unwrap().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn activity_keeps_the_room_open() {
+    let mut room = open_room().await;
+
+    sleep(Duration::from_secs(40)).await;
+    room.sender
+        .send(RoomCommand::join(1, "alice".to_string()))
+        .await
+        .unwrap();
+    let active = Instant::now();
+
+    sleep(Duration::from_secs(40)).await;
+    assert!(room.is_open().await);
+
+    assert_eq!(room.closed().await.as_deref(), Some("Room closed"));
+    assert_eq!(active.elapsed(), ROOM_TIMEOUT);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::TestServer;
+use reqwest::StatusCode;
+use serde_json::Value;
+
+async fn readyz(server: &TestServer) -> (StatusCode, Value) {
+    let (status, body) = server.get_text("/readyz").await;
+
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+#[tokio::test]
+async fn healthz_answers_without_a_session() {
+    let server = TestServer::start().await;
+
+    let (status, _body) = server.get_text("/healthz").await;
+
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn readyz_reports_every_check() {
+    let server = TestServer::start().await;
+
+    let (status, body) = readyz(&server).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["data"]["draining"], false);
+    assert_eq!(body["data"]["database"], "ok");
+    assert_eq!(body["data"]["message_writer"], "ok");
+    assert_eq!(body["data"]["session_checker"], "ok");
+    // the harness serves plain HTTP
+    assert_eq!(body["data"]["tls"], "disabled");
+}
+
+#[tokio::test]
+async fn readyz_fails_while_draining() {
+    let server = TestServer::start().await;
+
+    server.health.drain();
+    let (status, body) = readyz(&server).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "NOT_READY");
+    assert_eq!(body["data"]["draining"], true);
+
+    let (status, _body) = server.get_text("/healthz").await;
+    assert_eq!(status, StatusCode::OK);
+}
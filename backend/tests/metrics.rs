@@ -0,0 +1,111 @@
+mod common;
+
+use common::TestServer;
+use reqwest::StatusCode;
+use std::{sync::Arc, time::Duration};
+use tokio::{net::TcpListener, time::timeout};
+use ws_chat_room::{
+    config::{Config, StorageBackend},
+    db::MemoryStorage,
+    server::{self, App},
+};
+
+// the value of one sample line, e.g. `ws_chat_rooms 1`
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[tokio::test]
+async fn metrics_track_rooms_sockets_and_messages() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    owner
+        .send_frame(serde_json::json!({ "type": "send", "content": "hello", "request_id": "r1" }))
+        .await;
+    owner.recv_type("ack").await;
+
+    let (status, metrics) = server.metrics().await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(sample(&metrics, "ws_chat_rooms"), Some(1.0));
+    assert_eq!(sample(&metrics, "ws_chat_sessions"), Some(1.0));
+    assert_eq!(sample(&metrics, "ws_chat_sockets"), Some(1.0));
+    assert_eq!(sample(&metrics, "ws_chat_writer_queue_depth"), Some(0.0));
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!("ws_chat_room_messages_total{{room_id=\"{}\"}}", room_id)
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, "ws_chat_writer_insert_seconds_count"),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "ws_chat_http_request_duration_seconds_count{method=\"POST\",route=\"/api/signup\",status=\"200\"}"
+        ),
+        Some(1.0)
+    );
+}
+
+#[tokio::test]
+async fn closed_sockets_leave_the_gauge() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let mut guest = server.join_room(&bob, &room_id).await;
+    guest.join().await;
+    assert_eq!(owner.recv_type("joined").await["user"], "bob");
+
+    guest.close().await;
+    owner.recv_type("left").await;
+
+    let (_status, metrics) = server.metrics().await;
+    assert_eq!(sample(&metrics, "ws_chat_sockets"), Some(1.0));
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_on_the_public_listener() {
+    let server = TestServer::start().await;
+
+    // the public listener falls back to the static files
+    let (_status, body) = server.get_text("/metrics").await;
+    assert!(!body.contains("ws_chat_rooms"));
+
+    let (status, _body) = server.metrics().await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn metrics_listener_does_not_hold_the_writer_open() {
+    let mut config = Config::default();
+    config.database.backend = StorageBackend::Memory;
+    let App {
+        router,
+        metrics_router,
+        room_manager,
+        db_writer,
+        ..
+    } = server::app(Arc::new(config), Arc::new(MemoryStorage::new())).await;
+
+    // still serving when the public listener is gone
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, metrics_router).await.unwrap() });
+
+    drop(router);
+    room_manager.shutdown("Server is shutting down").await;
+
+    timeout(Duration::from_secs(5), db_writer)
+        .await
+        .expect("the message writer kept running after shutdown")
+        .unwrap();
+}
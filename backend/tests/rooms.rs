@@ -0,0 +1,224 @@
+mod common;
+
+use common::{Encoding, TestServer};
+use reqwest::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use ws_chat_room::room_manager::{Reply, RoomCommand};
+
+// every room test runs once per wire format, the frames mean the same in both
+macro_rules! encoded_tests {
+    ($($name:ident),* $(,)?) => {
+        mod json {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(super::Encoding::Json).await;
+                }
+            )*
+        }
+
+        mod msgpack {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(super::Encoding::MessagePack).await;
+                }
+            )*
+        }
+    };
+}
+
+encoded_tests!(
+    created_room_is_listed_and_joinable,
+    joining_an_unknown_room_is_refused,
+    messages_fan_out_to_every_member,
+    stored_messages_are_acked,
+    disconnecting_leaves_the_room,
+    idle_room_closes_its_sockets,
+    slash_commands_only_claim_their_names,
+    nicks_cannot_take_a_username,
+);
+
+async fn created_room_is_listed_and_joinable(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    owner.join().await;
+    assert_eq!(owner.recv().await["user"], "alice");
+
+    assert!(
+        server
+            .rooms(&bob)
+            .await
+            .contains(&(room_id.clone(), "lobby".to_string()))
+    );
+
+    let mut guest = server.join_room(&bob, &room_id).await;
+    guest.join().await;
+
+    let joined = owner.recv().await;
+    assert_eq!(joined["type"], "joined");
+    assert_eq!(joined["user"], "bob");
+    assert_eq!(guest.recv().await["user"], "bob");
+}
+
+async fn joining_an_unknown_room_is_refused(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+
+    let result = server
+        .connect(
+            "/join_room?room_id=00000000-0000-0000-0000-000000000000",
+            &alice,
+        )
+        .await;
+
+    assert_eq!(result.err(), Some(StatusCode::BAD_REQUEST));
+}
+
+async fn messages_fan_out_to_every_member(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let carol = server.signup("carol", "secret").await;
+
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let mut members = vec![
+        server.join_room(&bob, &room_id).await,
+        server.join_room(&carol, &room_id).await,
+    ];
+
+    owner.say("hello").await;
+
+    let own = owner.recv_type("message").await;
+    assert_eq!(own["content"], "hello");
+    assert_eq!(own["sender"], "alice");
+    assert_eq!(own["own"], true);
+
+    for member in &mut members {
+        let message = member.recv_type("message").await;
+
+        assert_eq!(message["content"], "hello");
+        assert_eq!(message["sender"], "alice");
+        assert_eq!(message["own"], false);
+    }
+}
+
+async fn stored_messages_are_acked(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, _room_id) = server.create_room(&alice, "lobby").await;
+
+    owner
+        .send_frame(serde_json::json!({ "type": "send", "content": "hello", "request_id": "r1" }))
+        .await;
+
+    let ack = owner.recv_type("ack").await;
+    assert_eq!(ack["request_id"], "r1");
+    assert!(ack["message_id"].as_i64().is_some());
+}
+
+async fn disconnecting_leaves_the_room(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let mut guest = server.join_room(&bob, &room_id).await;
+    guest.join().await;
+    assert_eq!(owner.recv_type("joined").await["user"], "bob");
+
+    guest.close().await;
+
+    let left = owner.recv_type("left").await;
+    assert_eq!(left["user"], "bob");
+}
+
+async fn idle_room_closes_its_sockets(encoding: Encoding) {
+    let server = TestServer::start_with(|config| config.room.idle_timeout_secs = 1)
+        .await
+        .with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let mut guest = server.join_room(&bob, &room_id).await;
+
+    for socket in [&mut owner, &mut guest] {
+        let close_frame = socket.closed().await.expect("no close frame");
+
+        assert_eq!(close_frame.code, CloseCode::Away);
+        assert_eq!(close_frame.reason.as_str(), "Room closed");
+    }
+
+    assert!(server.rooms(&alice).await.is_empty());
+    assert_eq!(
+        server
+            .connect(&format!("/join_room?room_id={}", room_id), &bob)
+            .await
+            .err(),
+        Some(StatusCode::BAD_REQUEST)
+    );
+}
+
+async fn slash_commands_only_claim_their_names(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, _room_id) = server.create_room(&alice, "lobby").await;
+
+    // unknown commands are left to people and bots
+    owner.say("/shrug").await;
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["content"], "/shrug");
+
+    owner.say("/me waves").await;
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["content"], "* alice waves");
+}
+
+async fn nicks_cannot_take_a_username(encoding: Encoding) {
+    let server = TestServer::start().await.with_encoding(encoding);
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    // never joins the room
+    server.signup("carol", "secret").await;
+
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let mut guest = server.join_room(&bob, &room_id).await;
+
+    guest
+        .send_frame(
+            serde_json::json!({ "type": "send", "content": "/nick Carol", "request_id": "r1" }),
+        )
+        .await;
+    let error = guest.recv_type("error").await;
+    assert_eq!(error["request_id"], "r1");
+    assert_eq!(error["code"], "BAD_COMMAND");
+
+    guest.say("/nick robert").await;
+    let notice = owner.recv_type("notice").await;
+    assert_eq!(notice["content"], "bob is now known as robert");
+
+    guest.say("hi").await;
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["sender"], "robert");
+}
+
+#[tokio::test]
+async fn replies_never_wait_for_a_full_connection() {
+    let (reply_sender, mut reply_receiver) = tokio::sync::mpsc::channel(1);
+    let command = RoomCommand::send(1, "alice".to_string(), "room".to_string(), "hi".to_string())
+        .with_reply(Some("r1".to_string()), reply_sender);
+
+    // the second reply finds the channel full and is dropped instead of blocking
+    command.reply_error("RATE_LIMITED", "Slow down");
+    command.reply_error("RATE_LIMITED", "Slow down");
+
+    assert!(matches!(
+        reply_receiver.recv().await,
+        Some(Reply::Error { .. })
+    ));
+    assert!(reply_receiver.try_recv().is_err());
+}
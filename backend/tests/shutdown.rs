@@ -0,0 +1,161 @@
+mod common;
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use common::TestServer;
+use futures_util::future::BoxFuture;
+use reqwest::StatusCode;
+use serde_json::json;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use ws_chat_room::{
+    api_token::Scope,
+    db::{
+        AccountStore, BotStore, IncomingWebhookStore, MemoryStorage, MessageStore, NewMessage,
+        RoomStore, SchemaError, Storage, StorageFuture, TokenStore, TotpStore, WebhookStore,
+    },
+    session::{ClientInfo, SessionEnd, SessionManager},
+};
+
+// memory storage whose inserts wait until the test releases them, so messages stay queued
+struct HeldInserts {
+    storage: MemoryStorage,
+    released: watch::Receiver<bool>,
+    stored: Mutex<Vec<String>>,
+}
+
+impl Storage for HeldInserts {
+    fn accounts(&self) -> &dyn AccountStore {
+        self.storage.accounts()
+    }
+
+    fn bots(&self) -> &dyn BotStore {
+        self.storage.bots()
+    }
+
+    fn incoming_webhooks(&self) -> &dyn IncomingWebhookStore {
+        self.storage.incoming_webhooks()
+    }
+
+    fn messages(&self) -> &dyn MessageStore {
+        self
+    }
+
+    fn rooms(&self) -> &dyn RoomStore {
+        self.storage.rooms()
+    }
+
+    fn tokens(&self) -> &dyn TokenStore {
+        self.storage.tokens()
+    }
+
+    fn totp(&self) -> &dyn TotpStore {
+        self.storage.totp()
+    }
+
+    fn webhooks(&self) -> &dyn WebhookStore {
+        self.storage.webhooks()
+    }
+
+    fn migrate(&self) -> BoxFuture<'_, Result<(), SchemaError>> {
+        self.storage.migrate()
+    }
+
+    fn check_schema(&self) -> BoxFuture<'_, Result<(), SchemaError>> {
+        self.storage.check_schema()
+    }
+
+    fn ping(&self) -> StorageFuture<'_, ()> {
+        self.storage.ping()
+    }
+}
+
+impl MessageStore for HeldInserts {
+    fn insert_batch<'a>(
+        &'a self,
+        messages: &'a [NewMessage],
+    ) -> StorageFuture<'a, Vec<(i64, DateTime<Utc>)>> {
+        Box::pin(async move {
+            let _ = self.released.clone().wait_for(|released| *released).await;
+            let rows = self.storage.messages().insert_batch(messages).await?;
+
+            self.stored.lock().unwrap().extend(
+                messages
+                    .iter()
+                    .filter_map(|message| message.content.clone()),
+            );
+
+            Ok(rows)
+        })
+    }
+}
+
+#[tokio::test]
+async fn shutdown_drains_readiness_rooms_and_the_message_queue() {
+    let (release, released) = watch::channel(false);
+    let db = Arc::new(HeldInserts {
+        storage: MemoryStorage::new(),
+        released,
+        stored: Mutex::new(Vec::new()),
+    });
+    let server = TestServer::start_over(db.clone(), |config| {
+        config.server.drain_delay_secs = 1;
+    })
+    .await;
+    let alice = server.signup("alice", "password").await;
+    let (mut room, _room_id) = server.create_room(&alice, "lobby").await;
+
+    // held by the storage, still queued when the drain starts
+    room.send_frame(json!({ "type": "send", "content": "last words", "request_id": "r1" }))
+        .await;
+
+    let shutdown = server.shut_down();
+
+    // /readyz fails for the drain delay while the server keeps serving
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (status, _body) = server.get_text("/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(server.rooms(&alice).await.len(), 1);
+
+    // then every room closes with the shutdown notice
+    let close_frame = room.closed().await.expect("no close frame");
+    assert_eq!(close_frame.code, CloseCode::Away);
+    assert_eq!(close_frame.reason.as_str(), "Server is shutting down");
+
+    // the drain waits for the writer, which still holds the message
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!shutdown.is_finished());
+    assert!(db.stored.lock().unwrap().is_empty());
+
+    release.send(true).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), shutdown)
+        .await
+        .expect("the drain did not finish")
+        .unwrap();
+
+    assert_eq!(*db.stored.lock().unwrap(), vec!["last words".to_string()]);
+}
+
+#[tokio::test]
+async fn shutdown_ends_sessions_with_its_own_reason() {
+    let session_manager = SessionManager::build(Duration::from_secs(60));
+    let client = ClientInfo::new(IpAddr::V4(Ipv4Addr::LOCALHOST), &HeaderMap::new());
+    let session_id = session_manager
+        .new_session(1, "alice".to_string(), client)
+        .await;
+    let mut session_watch = session_manager.watch(&session_id).await.unwrap();
+    let mut token_watch = session_manager
+        .watch_token(7, (2, "bob".to_string()), false, Scope::Chat)
+        .await;
+
+    session_manager.shutdown().await;
+
+    assert_eq!(session_watch.ended().await, SessionEnd::Shutdown);
+    assert_eq!(token_watch.ended().await, SessionEnd::Shutdown);
+    assert_eq!(session_manager.count().await, 0);
+}
@@ -0,0 +1,206 @@
+// the HTTP transports next to the WebSocket, SSE and long-polling to read and a POST to send
+mod common;
+use common::{RoomSocket, TestServer, User};
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+
+// says every message and waits until it is stored
+async fn say_all(owner: &mut RoomSocket, messages: &[&str]) {
+    for (index, content) in messages.iter().enumerate() {
+        let request_id = index.to_string();
+        owner
+            .send_frame(json!({ "type": "send", "content": content, "request_id": request_id }))
+            .await;
+        assert_eq!(owner.recv_type("ack").await["request_id"], request_id);
+    }
+}
+
+async fn poll(server: &TestServer, user: &User, room_id: &str, query: &str) -> Value {
+    let reply = server
+        .get(&format!("/rooms/{}/poll?{}", room_id, query), user)
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+
+    reply.body["data"].clone()
+}
+
+fn contents(polled: &Value) -> Vec<&str> {
+    polled["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|event| event["type"] == "message")
+        .map(|event| event["content"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn sse_stream_delivers_room_frames() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    let mut events = server.events(&room_id, &bob, None).await;
+    assert_eq!(owner.recv_type("joined").await["user"], "bob");
+
+    owner.say("hello").await;
+    let (event, message) = events.next_frame("message").await;
+    assert_eq!(message["content"], "hello");
+    assert_eq!(message["sender"], "alice");
+    assert_eq!(message["own"], false);
+    assert!(event.id.is_some_and(|id| id.parse::<u64>().is_ok()));
+}
+
+#[tokio::test]
+async fn sse_stream_resumes_after_the_last_event_id() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    let mut events = server.events(&room_id, &bob, None).await;
+    say_all(&mut owner, &["one", "two"]).await;
+    let (first, _) = events.next_frame("message").await;
+    drop(events);
+
+    let last_event_id = first.id.unwrap().parse().unwrap();
+    let mut events = server.events(&room_id, &bob, Some(last_event_id)).await;
+
+    // nothing is missing, the replay starts right after "one"
+    let event = events.next().await;
+    assert_eq!(event.event, None);
+    let frame: Value = serde_json::from_str(&event.data).unwrap();
+    assert_eq!(frame["content"], "two");
+}
+
+#[tokio::test]
+async fn sse_stream_resets_when_missed_events_are_gone() {
+    let server = TestServer::start_with(|config| config.room.history_size = 2).await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    say_all(&mut owner, &["one", "two", "three", "four"]).await;
+
+    let mut events = server.events(&room_id, &bob, Some(1)).await;
+    assert_eq!(events.next().await.event.as_deref(), Some("reset"));
+
+    // what is left of the history still follows
+    assert_eq!(events.next_frame("message").await.1["content"], "three");
+    assert_eq!(events.next_frame("message").await.1["content"], "four");
+}
+
+#[tokio::test]
+async fn poll_returns_events_after_the_cursor() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    say_all(&mut owner, &["one", "two"]).await;
+
+    let polled = poll(&server, &bob, &room_id, "after=0").await;
+    assert_eq!(contents(&polled), vec!["one", "two"]);
+    assert_eq!(polled["reset"], false);
+    assert_eq!(polled["closed"], false);
+
+    // nothing new, the request is held until the timeout
+    let next = polled["next"].as_u64().unwrap();
+    let polled = poll(
+        &server,
+        &bob,
+        &room_id,
+        &format!("after={}&timeout=1", next),
+    )
+    .await;
+    assert!(contents(&polled).is_empty());
+    assert_eq!(polled["next"], next);
+    assert_eq!(polled["reset"], false);
+}
+
+#[tokio::test]
+async fn held_poll_returns_with_the_next_event() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    say_all(&mut owner, &["one"]).await;
+    let next = poll(&server, &bob, &room_id, "after=0").await["next"]
+        .as_u64()
+        .unwrap();
+
+    let query = format!("after={}&timeout=5", next);
+    let held = poll(&server, &bob, &room_id, &query);
+    let (polled, _) = tokio::join!(held, async {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        say_all(&mut owner, &["two"]).await;
+    });
+
+    assert_eq!(contents(&polled), vec!["two"]);
+    assert!(polled["next"].as_u64().unwrap() > next);
+}
+
+#[tokio::test]
+async fn poll_resets_when_missed_events_are_gone() {
+    let server = TestServer::start_with(|config| config.room.history_size = 2).await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    say_all(&mut owner, &["one", "two", "three", "four"]).await;
+
+    let polled = poll(&server, &bob, &room_id, "after=1").await;
+    assert_eq!(polled["reset"], true);
+    assert_eq!(contents(&polled), vec!["three", "four"]);
+
+    // a cursor the room never handed out starts over from the newest event
+    let newest = polled["next"].clone();
+    let polled = poll(&server, &bob, &room_id, "after=1000&timeout=5").await;
+    assert_eq!(polled["reset"], true);
+    assert!(contents(&polled).is_empty());
+    assert_eq!(polled["next"], newest);
+}
+
+#[tokio::test]
+async fn http_send_is_broadcast_and_stored() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let bob = server.signup("bob", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    let reply = server
+        .post(
+            &format!("/rooms/{}/messages", room_id),
+            Some(&bob),
+            json!({ "content": "hello", "request_id": "r1" }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(reply.body["data"]["request_id"], "r1");
+    assert!(reply.body["data"]["message_id"].as_i64().is_some());
+
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["content"], "hello");
+    assert_eq!(message["sender"], "bob");
+
+    let stats = server.get("/stats/messages", &alice).await;
+    assert_eq!(stats.body["data"]["stored"], 1);
+}
+
+#[tokio::test]
+async fn http_send_to_an_unknown_room_is_refused() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+
+    let reply = server
+        .post(
+            "/rooms/00000000-0000-0000-0000-000000000000/messages",
+            Some(&alice),
+            json!({ "content": "hello" }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reply.code(), "BAD_REQUEST");
+}
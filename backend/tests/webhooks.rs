@@ -0,0 +1,302 @@
+mod common;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+};
+use common::{TestServer, User};
+use serde_json::{Value, json};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc,
+    time::{Instant, sleep, timeout},
+};
+use ws_chat_room::webhook;
+
+// one request the receiver got
+struct Received {
+    headers: HeaderMap,
+    body: Bytes,
+    at: Instant,
+}
+
+struct Receiver {
+    requests: mpsc::UnboundedSender<Received>,
+    // answered in order, the last status repeats
+    statuses: Vec<StatusCode>,
+    calls: AtomicUsize,
+    // where 3xx answers point, back at the same receiver
+    location: String,
+}
+
+async fn receive(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let call = receiver.calls.fetch_add(1, Ordering::SeqCst);
+    let status = receiver.statuses[call.min(receiver.statuses.len() - 1)];
+    let _ = receiver.requests.send(Received {
+        headers,
+        body,
+        at: Instant::now(),
+    });
+
+    (status, [("location", receiver.location.clone())])
+}
+
+// a local webhook endpoint answering with `statuses`
+async fn listen(statuses: Vec<StatusCode>) -> (SocketAddr, mpsc::UnboundedReceiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (requests, received) = mpsc::unbounded_channel();
+    let receiver = Arc::new(Receiver {
+        requests,
+        statuses,
+        calls: AtomicUsize::new(0),
+        location: format!("http://{}/elsewhere", addr),
+    });
+    let router = Router::new()
+        .route("/hook", post(receive))
+        .route("/elsewhere", post(receive))
+        .with_state(receiver);
+
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    (addr, received)
+}
+
+async fn next_request(received: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+    timeout(Duration::from_secs(10), received.recv())
+        .await
+        .expect("no webhook request before the timeout")
+        .unwrap()
+}
+
+// returns the webhook id and its signing secret
+async fn create_webhook(
+    server: &TestServer,
+    owner: &User,
+    room_id: &str,
+    url: &str,
+) -> (i64, String) {
+    let reply = server
+        .post(
+            &format!("/rooms/{}/webhooks", room_id),
+            Some(owner),
+            json!({ "url": url, "events": ["message"] }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+
+    (
+        reply.body["data"]["id"].as_i64().unwrap(),
+        reply.body["data"]["secret"].as_str().unwrap().to_string(),
+    )
+}
+
+// waits until `count` attempts are logged, oldest first
+async fn deliveries(
+    server: &TestServer,
+    owner: &User,
+    room_id: &str,
+    webhook_id: i64,
+    count: usize,
+) -> Vec<Value> {
+    let path = format!("/rooms/{}/webhooks/{}/deliveries", room_id, webhook_id);
+
+    for _ in 0..100 {
+        let reply = server.get(&path, owner).await;
+        let mut deliveries = reply.body["data"].as_array().cloned().unwrap_or_default();
+
+        if deliveries.len() >= count {
+            deliveries.reverse();
+            return deliveries;
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    panic!("fewer than {} deliveries were logged", count);
+}
+
+async fn start() -> TestServer {
+    TestServer::start_with(|config| {
+        config.webhook.allow_private_targets = true;
+        config.webhook.max_attempts = 3;
+        config.webhook.base_delay_secs = 1;
+    })
+    .await
+}
+
+#[tokio::test]
+async fn deliveries_are_signed_retried_and_logged() {
+    let (addr, mut received) = listen(vec![
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::OK,
+    ])
+    .await;
+    let server = start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let (webhook_id, secret) =
+        create_webhook(&server, &alice, &room_id, &format!("http://{}/hook", addr)).await;
+
+    owner.say("hello").await;
+
+    let mut requests = Vec::new();
+    for _ in 0..3 {
+        requests.push(next_request(&mut received).await);
+    }
+
+    let delivery_id = &requests[0].headers["x-wschat-delivery"];
+    for request in &requests {
+        let signature = format!("sha256={}", webhook::sign(&secret, &request.body));
+
+        assert_eq!(request.headers["x-wschat-signature"], signature.as_str());
+        assert_eq!(request.headers["x-wschat-event"], "message");
+        // retries are the same delivery
+        assert_eq!(&request.headers["x-wschat-delivery"], delivery_id);
+    }
+
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["event"], "message");
+    assert_eq!(body["room_id"], room_id.as_str());
+    assert_eq!(body["data"]["content"], "hello");
+    assert_eq!(body["data"]["sender"], "alice");
+
+    // the delay doubles after every failed attempt
+    assert!(requests[1].at - requests[0].at >= Duration::from_secs(1));
+    assert!(requests[2].at - requests[1].at >= Duration::from_secs(2));
+
+    let deliveries = deliveries(&server, &alice, &room_id, webhook_id, 3).await;
+    let attempts: Vec<_> = deliveries
+        .iter()
+        .map(|delivery| {
+            (
+                delivery["attempt"].as_i64().unwrap(),
+                delivery["status_code"].as_i64().unwrap(),
+                delivery["succeeded"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        attempts,
+        vec![(1, 500, false), (2, 503, false), (3, 200, true)]
+    );
+    assert!(
+        deliveries
+            .iter()
+            .all(|delivery| delivery["delivery_id"] == delivery_id.to_str().unwrap())
+    );
+}
+
+#[tokio::test]
+async fn redirects_are_not_followed() {
+    let (addr, mut received) = listen(vec![StatusCode::FOUND]).await;
+    let server = start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+    let (webhook_id, _secret) =
+        create_webhook(&server, &alice, &room_id, &format!("http://{}/hook", addr)).await;
+
+    owner.say("hello").await;
+    next_request(&mut received).await;
+
+    let deliveries = deliveries(&server, &alice, &room_id, webhook_id, 1).await;
+    assert_eq!(deliveries[0]["status_code"], 302);
+    assert_eq!(deliveries[0]["succeeded"], false);
+
+    // neither the redirect nor a retry reaches the receiver
+    sleep(Duration::from_millis(1500)).await;
+    assert!(received.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn internal_targets_are_refused_by_default() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (_owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    for url in [
+        "http://127.0.0.1:9000/hook",
+        "http://localhost/hook",
+        "http://10.0.0.1/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/hook",
+    ] {
+        let reply = server
+            .post(
+                &format!("/rooms/{}/webhooks", room_id),
+                Some(&alice),
+                json!({ "url": url, "events": ["message"] }),
+            )
+            .await;
+
+        assert_eq!(reply.status, StatusCode::BAD_REQUEST, "{}", url);
+        assert_eq!(
+            reply.body["message"],
+            "Webhook url must point to a public address"
+        );
+    }
+}
+
+#[tokio::test]
+async fn incoming_webhook_posts_are_broadcast_and_stored() {
+    let server = TestServer::start().await;
+    let alice = server.signup("alice", "secret").await;
+    let (mut owner, room_id) = server.create_room(&alice, "lobby").await;
+
+    let reply = server
+        .post(
+            &format!("/rooms/{}/incoming_webhooks", room_id),
+            Some(&alice),
+            json!({ "name": "CI" }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+    let url = reply.body["data"]["url"].as_str().unwrap().to_string();
+
+    // the secret url is the only credential
+    let reply = server
+        .post(
+            url.strip_prefix("/api").unwrap(),
+            None,
+            json!({ "content": "build passed", "request_id": "ci-1" }),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(reply.body["data"]["request_id"], "ci-1");
+    assert!(reply.body["data"]["message_id"].as_i64().is_some());
+
+    let message = owner.recv_type("message").await;
+    assert_eq!(message["content"], "build passed");
+    assert_eq!(message["sender"], "CI");
+    assert_eq!(message["bot"], true);
+
+    let stats = server.get("/stats/messages", &alice).await;
+    assert_eq!(stats.body["data"]["stored"], 1);
+}
+
+#[tokio::test]
+async fn unknown_incoming_webhook_is_not_found() {
+    let server = TestServer::start().await;
+
+    let reply = server
+        .post("/hooks/wsh_unknown", None, json!({ "content": "hello" }))
+        .await;
+
+    assert_eq!(reply.status, StatusCode::NOT_FOUND, "{}", reply.body);
+}